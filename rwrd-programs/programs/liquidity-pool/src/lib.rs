@@ -1,12 +1,35 @@
+// `initialize` takes one argument per pool policy, which Anchor also mirrors into the generated CPI helpers.
+#![allow(clippy::too_many_arguments)]
+
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    program::{get_return_data, invoke_signed},
+};
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
 
 // Importing merchant registry for CPI to verify merchants
-use merchant_registry::{program::MerchantRegistry, MerchantAccount};
+use merchant_registry::{
+    cpi::accounts::RecordTrustEvent, program::MerchantRegistry, MerchantAccount, RegistryState,
+    RevenueLedger, TrustEvent, TrustScore, MAX_TRUST_SCORE, MIN_TRUST_SCORE, PAUSE_BORROWS,
+    PAUSE_DEPOSITS, PAUSE_PAYMENTS,
+};
 
 declare_id!("CJpW4FJkG86qj6p41S2NFBzWYCcYESNaCRDwGew21DyA");
 
+// Fixed-point scale used for the supply and borrow indexes (1.0 == INDEX_SCALE)
+pub const INDEX_SCALE: u128 = 1_000_000_000_000;
+// Lowest the supply index can be written down to, so shares always stay priceable
+pub const MIN_SUPPLY_INDEX: u128 = 1;
+// Rates are expressed in basis points (1/100 of 1%)
+pub const BASIS_POINTS: u128 = 10_000;
+pub const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+// Hard ceiling on any pool's borrow APR (500%), in basis points
+pub const MAX_BORROW_RATE_CAP: u64 = 50_000;
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
 #[program]
 pub mod liquidity_pool {
     use super::*;
@@ -15,27 +38,235 @@ pub mod liquidity_pool {
     pub fn initialize(
         ctx: Context<Initialize>,
         authority: Pubkey,
-        base_rate: u64,           // Base interest rate in basis points (1/100 of 1%)
-        utilization_slope: u64,   // Utilization rate multiplier in basis points
-        protocol_fee_percent: u8, // Protocol fee percentage (0-100)
+        rate_model: RateModel,             // Two-slope borrow rate model
+        protocol_fee_percent: u8,          // Protocol fee percentage (0-100)
+        reserve_fee_percent: u8, // Share of loan interest sent to the reserve (0-protocol fee)
+        loan_policy: LoanPolicy, // Repayment cadence and delinquency grace periods
+        liquidity_policy: LiquidityPolicy, // Utilization cap and liquid reserve floor
+        deposit_limits: DepositLimits, // TVL cap and per-deposit bounds
     ) -> Result<()> {
         require!(protocol_fee_percent <= 100, ErrorCode::InvalidFeePercentage);
+        // The reserve is funded out of the protocol's share, never the depositors'
+        require!(
+            reserve_fee_percent <= protocol_fee_percent,
+            ErrorCode::InvalidFeePercentage
+        );
+        rate_model.validate()?;
+        loan_policy.validate()?;
+        liquidity_policy.validate()?;
+        deposit_limits.validate()?;
 
         let pool_state = &mut ctx.accounts.pool_state;
         pool_state.authority = authority;
+        pool_state.token_mint = ctx.accounts.token_mint.key();
+        pool_state.pool_vault = ctx.accounts.pool_vault.key();
+        pool_state.treasury = ctx.accounts.treasury.key();
+        pool_state.reserve_vault = ctx.accounts.reserve_vault.key();
+        pool_state.receipt_mint = ctx.accounts.receipt_mint.key();
         pool_state.total_deposited = 0;
         pool_state.total_borrowed = 0;
-        pool_state.base_rate = base_rate;
-        pool_state.utilization_slope = utilization_slope;
+        pool_state.total_shares = 0;
+        pool_state.supply_index = INDEX_SCALE;
+        pool_state.borrow_index = INDEX_SCALE;
+        pool_state.last_accrual_timestamp = Clock::get()?.unix_timestamp;
+        pool_state.rate_model = rate_model;
         pool_state.protocol_fee_percent = protocol_fee_percent;
+        pool_state.accrued_protocol_fees = 0;
+        pool_state.reserve_fee_percent = reserve_fee_percent;
+        pool_state.loan_policy = loan_policy;
+        pool_state.liquidity_policy = liquidity_policy;
+        pool_state.deposit_limits = deposit_limits;
+        pool_state.withdrawal_queue_head = 0;
+        pool_state.withdrawal_queue_tail = 0;
+        pool_state.pending_withdrawals = 0;
+        pool_state.queued_shares = 0;
+        pool_state.total_allocated = 0;
         pool_state.bump = ctx.bumps.pool_state;
 
         Ok(())
     }
 
+    // Update the interest rate model - only callable by authority
+    pub fn update_rate_model(ctx: Context<UpdateRateModel>, rate_model: RateModel) -> Result<()> {
+        rate_model.validate()?;
+
+        // Settle interest owed under the old rates before switching
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        let previous_rate_model = pool_state.rate_model;
+        pool_state.rate_model = rate_model;
+
+        emit!(RateModelUpdatedEvent {
+            token_mint: pool_state.token_mint,
+            authority: ctx.accounts.authority.key(),
+            previous_rate_model,
+            rate_model,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Update the loan repayment policy - only callable by authority
+    pub fn update_loan_policy(
+        ctx: Context<UpdateLoanPolicy>,
+        loan_policy: LoanPolicy,
+    ) -> Result<()> {
+        loan_policy.validate()?;
+
+        let pool_state = &mut ctx.accounts.pool_state;
+        let previous_loan_policy = pool_state.loan_policy;
+        pool_state.loan_policy = loan_policy;
+
+        emit!(LoanPolicyUpdatedEvent {
+            token_mint: pool_state.token_mint,
+            authority: ctx.accounts.authority.key(),
+            previous_loan_policy,
+            loan_policy,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Update the pool's liquidity limits - only callable by authority
+    pub fn update_liquidity_policy(
+        ctx: Context<UpdateLiquidityPolicy>,
+        liquidity_policy: LiquidityPolicy,
+    ) -> Result<()> {
+        liquidity_policy.validate()?;
+
+        let pool_state = &mut ctx.accounts.pool_state;
+        let previous_liquidity_policy = pool_state.liquidity_policy;
+        pool_state.liquidity_policy = liquidity_policy;
+
+        emit!(LiquidityPolicyUpdatedEvent {
+            token_mint: pool_state.token_mint,
+            authority: ctx.accounts.authority.key(),
+            previous_liquidity_policy,
+            liquidity_policy,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Update the pool's deposit limits - only callable by authority
+    pub fn update_deposit_limits(
+        ctx: Context<UpdateDepositLimits>,
+        deposit_limits: DepositLimits,
+    ) -> Result<()> {
+        deposit_limits.validate()?;
+
+        let pool_state = &mut ctx.accounts.pool_state;
+        let previous_deposit_limits = pool_state.deposit_limits;
+        pool_state.deposit_limits = deposit_limits;
+
+        emit!(DepositLimitsUpdatedEvent {
+            token_mint: pool_state.token_mint,
+            authority: ctx.accounts.authority.key(),
+            previous_deposit_limits,
+            deposit_limits,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Move accrued protocol fees to the treasury - only callable by authority
+    pub fn collect_protocol_fees(ctx: Context<CollectProtocolFees>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Book any interest accrued since the last touch
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        require!(
+            pool_state.accrued_protocol_fees >= amount,
+            ErrorCode::InsufficientProtocolFees
+        );
+        // Fees still owed inside outstanding loans can't be paid out yet
+        require!(
+            ctx.accounts.pool_vault.amount >= amount,
+            ErrorCode::InsufficientLiquidity
+        );
+
+        pool_state.accrued_protocol_fees = pool_state.accrued_protocol_fees.saturating_sub(amount);
+
+        // Transfer tokens from the pool vault to the treasury
+        let bump = ctx.accounts.pool_state.bump;
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.treasury.to_account_info(),
+                authority: ctx.accounts.pool_state.to_account_info(),
+            },
+            signer_seeds,
+        );
+
+        token::transfer(transfer_ctx, amount)?;
+
+        emit!(ProtocolFeesCollectedEvent {
+            token_mint: ctx.accounts.token_mint.key(),
+            treasury: ctx.accounts.treasury.key(),
+            amount,
+            remaining_fees: ctx.accounts.pool_state.accrued_protocol_fees,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
     // User deposits funds into the pool
+    // Passing the position accounts instead of user_deposit keys the deposit to a position NFT
     pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_DEPOSITS),
+            ErrorCode::ActionPaused
+        );
+
+        // Mint the NFT for a new position; an existing one only takes deposits from its holder
+        let position_mint = ctx.accounts.position_mint.as_ref().map(|mint| mint.key());
+        if let Some(position_mint) = &ctx.accounts.position_mint {
+            let position_token_account = ctx
+                .accounts
+                .position_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingPositionToken)?;
+
+            if position_mint.supply == 0 {
+                let bump = ctx.accounts.pool_state.bump;
+                let token_mint_key = ctx.accounts.token_mint.key();
+                let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
+                let signer_seeds = &[&seeds[..]];
+
+                let mint_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: position_mint.to_account_info(),
+                        to: position_token_account.to_account_info(),
+                        authority: ctx.accounts.pool_state.to_account_info(),
+                    },
+                    signer_seeds,
+                );
+
+                token::mint_to(mint_ctx, 1)?;
+            } else {
+                require!(
+                    position_token_account.amount == 1,
+                    ErrorCode::UnauthorizedAccess
+                );
+            }
+        }
 
         // Transfer tokens from the user to the pool vault
         let transfer_ctx = CpiContext::new(
@@ -49,46 +280,109 @@ pub mod liquidity_pool {
 
         token::transfer(transfer_ctx, amount)?;
 
-        // Update user deposit account
-        let user_deposit = &mut ctx.accounts.user_deposit;
+        // Bring the pool indexes up to date before pricing the new shares
         let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        prepare_supply_index(pool_state)?;
+
+        let user_deposit = match (
+            &mut ctx.accounts.user_deposit,
+            &mut ctx.accounts.position,
+            position_mint,
+        ) {
+            (Some(user_deposit), None, None) => user_deposit,
+            (None, Some(position), Some(_)) => position,
+            _ => return err!(ErrorCode::InvalidPosition),
+        };
+        if position_mint.is_none() {
+            let released = reconcile_receipts(
+                user_deposit,
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.user_receipt_account.as_ref(),
+            )?;
+            let depositor = &mut ctx.accounts.depositor;
+            depositor.deposited_amount = depositor.deposited_amount.saturating_sub(released);
+        }
 
-        // If existing deposit, calculate interest first
-        if user_deposit.deposited_amount > 0 {
-            let earned_interest = calculate_interest(
-                user_deposit.deposited_amount,
-                user_deposit.last_interest_calculation,
-                current_timestamp,
-                ctx.accounts.pool_state.base_rate,
-                ctx.accounts.pool_state.utilization_slope,
-                ctx.accounts.pool_state.protocol_fee_percent,
-                ctx.accounts.pool_state.total_deposited,
-                ctx.accounts.pool_state.total_borrowed,
-            );
+        // An NFT position bought from someone else moves onto the new holder's cap
+        let depositor = &mut ctx.accounts.depositor;
+        if position_mint.is_some() {
+            charge_position_to_holder(
+                user_deposit,
+                ctx.accounts.user.key(),
+                ctx.accounts.previous_depositor.as_deref_mut(),
+                depositor,
+            )?;
+        }
 
-            user_deposit.interest_earned =
-                user_deposit.interest_earned.saturating_add(earned_interest);
-        } else {
+        // Keep the pool and this depositor inside the launch limits
+        let deposit_limits = &pool_state.deposit_limits;
+        require!(
+            amount >= deposit_limits.min_deposit,
+            ErrorCode::DepositTooSmall
+        );
+        require!(
+            pool_state.total_deposited.saturating_add(amount) <= deposit_limits.total_deposit_cap,
+            ErrorCode::DepositCapExceeded
+        );
+        // The per-wallet cap covers every position charged to the wallet, not just this one
+        require!(
+            depositor.deposited_amount.saturating_add(amount) <= deposit_limits.max_user_deposit,
+            ErrorCode::UserDepositLimitExceeded
+        );
+
+        // Round down so the depositor never receives more than they paid for
+        let shares = amount_to_shares(amount, pool_state.supply_index, false)?;
+        require!(shares > 0, ErrorCode::InvalidAmount);
+
+        // Update user deposit account
+        if user_deposit.shares == 0 && user_deposit.deposited_amount == 0 {
             // First deposit for this user
             user_deposit.user = ctx.accounts.user.key();
             user_deposit.token_mint = ctx.accounts.token_mint.key();
             user_deposit.deposit_date = current_timestamp;
+            user_deposit.position_mint = position_mint.unwrap_or_default();
         }
 
-        // Update deposit amount and timestamp
+        user_deposit.shares = user_deposit.shares.saturating_add(shares);
         user_deposit.deposited_amount = user_deposit.deposited_amount.saturating_add(amount);
-        user_deposit.last_interest_calculation = current_timestamp;
+        let total_deposited = user_deposit.deposited_amount;
 
-        // Update pool total deposited amount
-        let pool_state = &mut ctx.accounts.pool_state;
+        depositor.user = user_deposit.user;
+        depositor.token_mint = user_deposit.token_mint;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_add(amount);
+
+        // Update pool totals
+        pool_state.total_shares = pool_state.total_shares.saturating_add(shares);
         pool_state.total_deposited = pool_state.total_deposited.saturating_add(amount);
 
+        // Wallet positions are backed by transferable receipt tokens
+        if position_mint.is_none() {
+            mint_receipts(
+                &ctx.accounts.pool_state,
+                ctx.accounts
+                    .receipt_mint
+                    .as_ref()
+                    .ok_or(ErrorCode::MissingReceiptAccount)?,
+                ctx.accounts
+                    .user_receipt_account
+                    .as_ref()
+                    .ok_or(ErrorCode::MissingReceiptAccount)?,
+                &ctx.accounts.token_program,
+                shares,
+            )?;
+        }
+
         // Emit deposit event
         emit!(DepositEvent {
             user: ctx.accounts.user.key(),
             token_mint: ctx.accounts.token_mint.key(),
+            position_mint,
             amount,
-            total_deposited: user_deposit.deposited_amount,
+            shares,
+            total_deposited,
             timestamp: current_timestamp,
         });
 
@@ -98,39 +392,83 @@ pub mod liquidity_pool {
     // User withdraws deposited funds
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
-
-        let user_deposit = &mut ctx.accounts.user_deposit;
+        authorize_position(
+            &ctx.accounts.user_deposit,
+            ctx.accounts.user.key(),
+            ctx.accounts.token_mint.key(),
+            ctx.accounts.position_token_account.as_ref(),
+        )?;
+        let released = reconcile_receipts(
+            &mut ctx.accounts.user_deposit,
+            ctx.accounts.receipt_mint.as_ref(),
+            ctx.accounts.user_receipt_account.as_ref(),
+        )?;
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(released);
+        // Queued withdrawals are filled first; instant ones can't jump ahead of them
         require!(
-            user_deposit.deposited_amount >= amount,
-            ErrorCode::InsufficientFunds
+            ctx.accounts.pool_state.pending_withdrawals == 0,
+            ErrorCode::WithdrawalQueueNotEmpty
+        );
+        require!(
+            ctx.accounts.pool_vault.amount >= amount,
+            ErrorCode::InsufficientLiquidity
         );
 
-        // Calculate and update interest accrued up to now
+        // Bring the pool indexes up to date before pricing the burned shares
         let current_timestamp = Clock::get()?.unix_timestamp;
-        let earned_interest = calculate_interest(
-            user_deposit.deposited_amount,
-            user_deposit.last_interest_calculation,
-            current_timestamp,
-            ctx.accounts.pool_state.base_rate,
-            ctx.accounts.pool_state.utilization_slope,
-            ctx.accounts.pool_state.protocol_fee_percent,
-            ctx.accounts.pool_state.total_deposited,
-            ctx.accounts.pool_state.total_borrowed,
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        // Withdrawals draw on principal only; earnings stay in the position
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        let position_value = shares_to_amount(user_deposit.shares, pool_state.supply_index)?;
+        let written_down = write_down_principal(user_deposit, position_value);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(written_down);
+        require!(
+            user_deposit.deposited_amount >= amount && position_value >= amount,
+            ErrorCode::InsufficientFunds
         );
 
-        user_deposit.interest_earned = user_deposit.interest_earned.saturating_add(earned_interest);
-        user_deposit.last_interest_calculation = current_timestamp;
+        // Round up so the remaining depositors are never diluted
+        let shares = amount_to_shares(amount, pool_state.supply_index, true)?;
+        let shares = std::cmp::min(shares, user_deposit.shares);
 
-        // Update deposit amount
+        // Update user deposit account
+        user_deposit.shares = user_deposit.shares.saturating_sub(shares);
         user_deposit.deposited_amount = user_deposit.deposited_amount.saturating_sub(amount);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(amount);
 
-        // Update pool total deposited amount
-        let pool_state = &mut ctx.accounts.pool_state;
+        // Update pool totals
+        pool_state.total_shares = pool_state.total_shares.saturating_sub(shares);
         pool_state.total_deposited = pool_state.total_deposited.saturating_sub(amount);
 
+        // Leave the remaining depositors a pool they can still withdraw from
+        check_liquidity(
+            pool_state,
+            ctx.accounts.pool_vault.amount.saturating_sub(amount),
+        )?;
+
+        if let Some((receipt_mint, user_receipt_account)) = position_receipts(
+            user_deposit,
+            ctx.accounts.receipt_mint.as_ref(),
+            ctx.accounts.user_receipt_account.as_ref(),
+        )? {
+            burn_receipts(
+                receipt_mint,
+                user_receipt_account,
+                &ctx.accounts.user,
+                &ctx.accounts.token_program,
+                shares,
+            )?;
+        }
+
         // Transfer tokens from the pool vault to the user
         let bump = ctx.accounts.pool_state.bump;
-        let seeds = &[b"pool_state".as_ref(), &[bump]];
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
         let signer_seeds = &[&seeds[..]];
 
         let transfer_ctx = CpiContext::new_with_signer(
@@ -150,172 +488,129 @@ pub mod liquidity_pool {
             user: ctx.accounts.user.key(),
             token_mint: ctx.accounts.token_mint.key(),
             amount,
-            remaining_deposit: user_deposit.deposited_amount,
+            shares,
+            remaining_deposit: ctx.accounts.user_deposit.deposited_amount,
             timestamp: current_timestamp,
         });
 
+        // Principal left in a closed position no longer counts toward the cap
+        let written_off = close_empty_position(
+            &mut ctx.accounts.pool_state,
+            &mut ctx.accounts.user_deposit,
+            ctx.accounts.position_mint.as_ref(),
+            ctx.accounts.position_token_account.as_ref(),
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            current_timestamp,
+        )?;
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(written_off);
+
         Ok(())
     }
 
-    // Process a payment from user to merchant via the pool
-    // Priority: Interest earned > Deposited funds > Direct wallet
-    pub fn pay_via_pool(ctx: Context<PayViaPool>, amount: u64) -> Result<()> {
-        require!(amount > 0, ErrorCode::InvalidAmount);
-
-        let current_timestamp = Clock::get()?.unix_timestamp;
-        let user = ctx.accounts.user_wallet.key();
-        let merchant = ctx.accounts.merchant_wallet.key();
-        let token_mint = ctx.accounts.token_mint.key();
-
-        // Calculate payment sources
-        let mut from_interest = 0;
-        let mut from_deposit = 0;
-        let mut from_wallet = 0;
-
-        // If user has a deposit account, use interest first, then deposit
-        if let Some(user_deposit) = &mut ctx.accounts.user_deposit {
-            // Calculate and update earned interest
-            let earned_interest = calculate_interest(
-                user_deposit.deposited_amount,
-                user_deposit.last_interest_calculation,
-                current_timestamp,
-                ctx.accounts.pool_state.base_rate,
-                ctx.accounts.pool_state.utilization_slope,
-                ctx.accounts.pool_state.protocol_fee_percent,
-                ctx.accounts.pool_state.total_deposited,
-                ctx.accounts.pool_state.total_borrowed,
-            );
-
-            user_deposit.interest_earned =
-                user_deposit.interest_earned.saturating_add(earned_interest);
-            user_deposit.last_interest_calculation = current_timestamp;
-
-            // Use interest first
-            if user_deposit.interest_earned > 0 {
-                from_interest = std::cmp::min(user_deposit.interest_earned, amount);
-                user_deposit.interest_earned =
-                    user_deposit.interest_earned.saturating_sub(from_interest);
-            }
-
-            // If interest not enough, use deposit
-            let remaining = amount.saturating_sub(from_interest);
-            if remaining > 0 && user_deposit.deposited_amount > 0 {
-                from_deposit = std::cmp::min(user_deposit.deposited_amount, remaining);
-                user_deposit.deposited_amount =
-                    user_deposit.deposited_amount.saturating_sub(from_deposit);
-
-                // Update pool total deposited
-                let pool_state = &mut ctx.accounts.pool_state;
-                pool_state.total_deposited =
-                    pool_state.total_deposited.saturating_sub(from_deposit);
-            }
-        }
-
-        // If interest + deposit not enough, use wallet
-        from_wallet = amount.saturating_sub(from_interest + from_deposit);
-
-        // Process transfers based on source allocation
-
-        // Transfer from wallet if needed
-        if from_wallet > 0 {
-            let transfer_ctx = CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_token_account.to_account_info(),
-                    to: ctx.accounts.merchant_token_account.to_account_info(),
-                    authority: ctx.accounts.user_wallet.to_account_info(),
-                },
-            );
-
-            token::transfer(transfer_ctx, from_wallet)?;
-        }
-
-        // Transfer from pool if using interest or deposit
-        if from_interest > 0 || from_deposit > 0 {
-            let bump = ctx.accounts.pool_state.bump;
-            let seeds = &[b"pool_state".as_ref(), &[bump]];
-            let signer_seeds = &[&seeds[..]];
-
-            let transfer_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.pool_vault.to_account_info(),
-                    to: ctx.accounts.merchant_token_account.to_account_info(),
-                    authority: ctx.accounts.pool_state.to_account_info(),
-                },
-                signer_seeds,
-            );
-
-            token::transfer(transfer_ctx, from_interest + from_deposit)?;
-        }
-
-        // Emit payment event
-        emit!(PaymentEvent {
-            user,
-            merchant,
-            token_mint,
-            total_amount: amount,
-            from_interest,
-            from_deposit,
-            from_wallet,
-            timestamp: current_timestamp,
-        });
-
-        Ok(())
+    // Calculate interest earned by a user up to now, returned as return data
+    pub fn calculate_earned(ctx: Context<CalculateEarned>) -> Result<u64> {
+        // Accrue on a copy so the view does not need a writable pool state
+        let mut pool_state = (*ctx.accounts.pool_state).clone();
+        accrue_interest(&mut pool_state, Clock::get()?.unix_timestamp)?;
+        authorize_position(
+            &ctx.accounts.user_deposit,
+            ctx.accounts.user_wallet.key(),
+            ctx.accounts.token_mint.key(),
+            ctx.accounts.position_token_account.as_ref(),
+        )?;
+
+        // Count only the shares the wallet still holds receipts for
+        let mut user_deposit = (*ctx.accounts.user_deposit).clone();
+        reconcile_receipts(
+            &mut user_deposit,
+            ctx.accounts.receipt_mint.as_ref(),
+            ctx.accounts.user_receipt_account.as_ref(),
+        )?;
+        let position_value = shares_to_amount(user_deposit.shares, pool_state.supply_index)?;
+
+        Ok(position_value.saturating_sub(user_deposit.deposited_amount))
     }
 
-    // Merchant borrows funds from the pool
-    pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+    // User withdraws earned interest, leaving the principal in the pool
+    pub fn withdraw_earned(ctx: Context<WithdrawEarned>, amount: u64) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
-
-        // Verify merchant is registered and verified
-        let merchant_account = &ctx.accounts.merchant_account;
-        require!(merchant_account.verified, ErrorCode::MerchantNotVerified);
-
-        // Calculate merchant trust score (1-5) and borrow limit
-        let trust_score = calculate_trust_score(merchant_account);
-        let borrow_limit = calculate_borrow_limit(trust_score, amount);
-
-        // Check if merchant can borrow this amount
-        let merchant_loan = &ctx.accounts.merchant_loan;
-        let current_loans = get_outstanding_loan_amount(merchant_loan);
-        let total_borrowed_after = current_loans.saturating_add(amount);
+        authorize_position(
+            &ctx.accounts.user_deposit,
+            ctx.accounts.user.key(),
+            ctx.accounts.token_mint.key(),
+            ctx.accounts.position_token_account.as_ref(),
+        )?;
+        let released = reconcile_receipts(
+            &mut ctx.accounts.user_deposit,
+            ctx.accounts.receipt_mint.as_ref(),
+            ctx.accounts.user_receipt_account.as_ref(),
+        )?;
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(released);
+        // Queued withdrawals are filled first; instant ones can't jump ahead of them
         require!(
-            total_borrowed_after <= borrow_limit,
-            ErrorCode::ExceedsBorrowLimit
+            ctx.accounts.pool_state.pending_withdrawals == 0,
+            ErrorCode::WithdrawalQueueNotEmpty
         );
-
-        // Check if there's enough liquidity in the pool
-        let pool_state = &mut ctx.accounts.pool_state;
         require!(
-            pool_state.total_deposited >= amount,
+            ctx.accounts.pool_vault.amount >= amount,
             ErrorCode::InsufficientLiquidity
         );
 
-        // Update merchant loan account
-        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        // Bring the pool indexes up to date before pricing the burned shares
         let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
 
-        // For simplicity, we'll just update/overwrite the loan
-        merchant_loan.merchant = ctx.accounts.merchant_wallet.key();
-        merchant_loan.principal = merchant_loan.principal.saturating_add(amount);
-        merchant_loan.issue_date = current_timestamp;
-        merchant_loan.last_repayment_date = current_timestamp;
-        merchant_loan.status = LoanStatus::Active;
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        let position_value = shares_to_amount(user_deposit.shares, pool_state.supply_index)?;
+        let earned = position_value.saturating_sub(user_deposit.deposited_amount);
+        require!(earned >= amount, ErrorCode::InsufficientEarnings);
 
-        // Update pool state
-        pool_state.total_borrowed = pool_state.total_borrowed.saturating_add(amount);
+        // Round up so the remaining depositors are never diluted
+        let shares = amount_to_shares(amount, pool_state.supply_index, true)?;
+        let shares = std::cmp::min(shares, user_deposit.shares);
 
-        // Transfer tokens from pool vault to merchant
+        // Only shares are burned; the principal is left untouched
+        user_deposit.shares = user_deposit.shares.saturating_sub(shares);
+
+        // Update pool totals
+        pool_state.total_shares = pool_state.total_shares.saturating_sub(shares);
+        pool_state.total_deposited = pool_state.total_deposited.saturating_sub(amount);
+
+        // Leave the remaining depositors a pool they can still withdraw from
+        check_liquidity(
+            pool_state,
+            ctx.accounts.pool_vault.amount.saturating_sub(amount),
+        )?;
+
+        if let Some((receipt_mint, user_receipt_account)) = position_receipts(
+            user_deposit,
+            ctx.accounts.receipt_mint.as_ref(),
+            ctx.accounts.user_receipt_account.as_ref(),
+        )? {
+            burn_receipts(
+                receipt_mint,
+                user_receipt_account,
+                &ctx.accounts.user,
+                &ctx.accounts.token_program,
+                shares,
+            )?;
+        }
+
+        // Transfer tokens from the pool vault to the user
         let bump = ctx.accounts.pool_state.bump;
-        let seeds = &[b"pool_state".as_ref(), &[bump]];
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
         let signer_seeds = &[&seeds[..]];
 
         let transfer_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
             Transfer {
                 from: ctx.accounts.pool_vault.to_account_info(),
-                to: ctx.accounts.merchant_token_account.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
                 authority: ctx.accounts.pool_state.to_account_info(),
             },
             signer_seeds,
@@ -323,394 +618,3234 @@ pub mod liquidity_pool {
 
         token::transfer(transfer_ctx, amount)?;
 
-        // Emit borrow event
-        emit!(BorrowEvent {
-            merchant: ctx.accounts.merchant_wallet.key(),
+        // Emit withdraw earned event
+        emit!(WithdrawEarnedEvent {
+            user: ctx.accounts.user.key(),
             token_mint: ctx.accounts.token_mint.key(),
             amount,
-            borrow_limit,
-            current_outstanding: total_borrowed_after,
+            shares,
+            remaining_earned: earned.saturating_sub(amount),
             timestamp: current_timestamp,
         });
 
+        close_empty_position(
+            &mut ctx.accounts.pool_state,
+            &mut ctx.accounts.user_deposit,
+            ctx.accounts.position_mint.as_ref(),
+            ctx.accounts.position_token_account.as_ref(),
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            current_timestamp,
+        )?;
+
         Ok(())
     }
 
-    // Merchant repays loan
-    pub fn repay_loan(ctx: Context<RepayLoan>, amount: u64) -> Result<()> {
-        require!(amount > 0, ErrorCode::InvalidAmount);
-
-        let merchant_loan = &mut ctx.accounts.merchant_loan;
+    // Move an NFT position's principal onto the cap of the wallet holding its NFT - callable by anyone
+    // Lets the previous holder free their cap without waiting for the new holder to act
+    pub fn reattribute_position(ctx: Context<ReattributePosition>) -> Result<()> {
+        let position_token_account = &ctx.accounts.position_token_account;
         require!(
-            merchant_loan.status == LoanStatus::Active,
-            ErrorCode::LoanNotActive
+            position_token_account.amount == 1,
+            ErrorCode::MissingPositionToken
         );
+        let holder = position_token_account.owner;
+        let position = &mut ctx.accounts.position;
+        let previous_user = position.user;
+        require!(
+            previous_user != holder,
+            ErrorCode::PositionAlreadyAttributed
+        );
+
+        charge_position_to_holder(
+            position,
+            holder,
+            Some(&mut ctx.accounts.previous_depositor),
+            &mut ctx.accounts.depositor,
+        )?;
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.user = holder;
+        depositor.token_mint = ctx.accounts.token_mint.key();
+
+        emit!(PositionReattributedEvent {
+            position_mint: position.position_mint,
+            token_mint: ctx.accounts.token_mint.key(),
+            previous_user,
+            user: holder,
+            deposited_amount: position.deposited_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
 
-        // Calculate total outstanding including interest
+    // Lock shares in a withdrawal request, filled in order as liquidity returns to the vault
+    // Wallet positions only; an NFT position has no receipt tokens to lock and withdraws instantly
+    // Only allowed when withdraw can't pay out now, since a queued request holds up instant withdrawals
+    pub fn request_withdrawal(ctx: Context<RequestWithdrawal>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let released = reconcile_receipts(
+            &mut ctx.accounts.user_deposit,
+            Some(&ctx.accounts.receipt_mint),
+            Some(&ctx.accounts.user_receipt_account),
+        )?;
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(released);
+
+        // Bring the pool indexes up to date before pricing the locked shares
         let current_timestamp = Clock::get()?.unix_timestamp;
-        let accrued_interest = calculate_loan_interest(
-            merchant_loan.principal,
-            merchant_loan.last_repayment_date,
-            current_timestamp,
-            ctx.accounts.pool_state.base_rate,
-            ctx.accounts.pool_state.utilization_slope,
-            ctx.accounts.pool_state.total_deposited,
-            ctx.accounts.pool_state.total_borrowed,
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        // Queued withdrawals draw on principal only, like instant ones
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        let position_value = shares_to_amount(user_deposit.shares, pool_state.supply_index)?;
+        let written_down = write_down_principal(user_deposit, position_value);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(written_down);
+        require!(
+            user_deposit.deposited_amount >= amount && position_value >= amount,
+            ErrorCode::InsufficientFunds
+        );
+        require!(
+            pool_state.pending_withdrawals > 0
+                || amount
+                    > calculate_available_liquidity(pool_state, ctx.accounts.pool_vault.amount),
+            ErrorCode::InstantWithdrawalAvailable
         );
 
-        let total_outstanding = merchant_loan.principal.saturating_add(accrued_interest);
+        // Round up so the remaining depositors are never diluted
+        let shares = amount_to_shares(amount, pool_state.supply_index, true)?;
+        let shares = std::cmp::min(shares, user_deposit.shares);
 
-        // Transfer tokens from merchant to pool vault
-        let transfer_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
+        // Locked shares leave the position but keep earning until they are filled
+        user_deposit.shares = user_deposit.shares.saturating_sub(shares);
+        user_deposit.deposited_amount = user_deposit.deposited_amount.saturating_sub(amount);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(amount);
+        burn_receipts(
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            shares,
+        )?;
+
+        let request_id = pool_state.withdrawal_queue_tail;
+        pool_state.withdrawal_queue_tail = request_id.saturating_add(1);
+        pool_state.pending_withdrawals = pool_state.pending_withdrawals.saturating_add(1);
+        pool_state.queued_shares = pool_state.queued_shares.saturating_add(shares);
+
+        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
+        withdrawal_request.user = ctx.accounts.user.key();
+        withdrawal_request.token_mint = ctx.accounts.token_mint.key();
+        withdrawal_request.request_id = request_id;
+        withdrawal_request.shares = shares;
+        withdrawal_request.principal = amount;
+        withdrawal_request.filled_amount = 0;
+        withdrawal_request.requested_at = current_timestamp;
+        withdrawal_request.status = WithdrawalStatus::Pending;
+
+        emit!(WithdrawalRequestedEvent {
+            user: ctx.accounts.user.key(),
+            token_mint: ctx.accounts.token_mint.key(),
+            request_id,
+            amount,
+            shares,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Return a withdrawal request's unfilled shares to the user's position
+    pub fn cancel_withdrawal_request(ctx: Context<CancelWithdrawalRequest>) -> Result<()> {
+        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
+        require!(
+            withdrawal_request.status == WithdrawalStatus::Pending,
+            ErrorCode::WithdrawalRequestNotPending
+        );
+
+        // Settle receipts sent away before the returned shares are added back
+        let released = reconcile_receipts(
+            &mut ctx.accounts.user_deposit,
+            Some(&ctx.accounts.receipt_mint),
+            Some(&ctx.accounts.user_receipt_account),
+        )?;
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor.deposited_amount.saturating_sub(released);
+
+        let shares = withdrawal_request.shares;
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        user_deposit.shares = user_deposit.shares.saturating_add(shares);
+        user_deposit.deposited_amount = user_deposit
+            .deposited_amount
+            .saturating_add(withdrawal_request.principal);
+        let depositor = &mut ctx.accounts.depositor;
+        depositor.deposited_amount = depositor
+            .deposited_amount
+            .saturating_add(withdrawal_request.principal);
+
+        // The crank skips cancelled requests when it reaches them
+        withdrawal_request.shares = 0;
+        withdrawal_request.principal = 0;
+        withdrawal_request.status = WithdrawalStatus::Cancelled;
+
+        let pool_state = &mut ctx.accounts.pool_state;
+        pool_state.pending_withdrawals = pool_state.pending_withdrawals.saturating_sub(1);
+        pool_state.queued_shares = pool_state.queued_shares.saturating_sub(shares);
+
+        // The returned shares are backed by receipt tokens again
+        mint_receipts(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.token_program,
+            shares,
+        )?;
+
+        emit!(WithdrawalCancelledEvent {
+            user: ctx.accounts.user.key(),
+            token_mint: ctx.accounts.token_mint.key(),
+            request_id: withdrawal_request.request_id,
+            shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Fill queued withdrawal requests in order from available liquidity - callable by anyone
+    // Remaining accounts are (withdrawal request, user token account) pairs starting at the queue head
+    pub fn process_withdrawal_queue<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ProcessWithdrawalQueue<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.remaining_accounts.len() % 2 == 0,
+            ErrorCode::InvalidWithdrawalRequest
+        );
+
+        // Bring the pool indexes up to date before pricing the filled shares
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let token_mint = ctx.accounts.token_mint.key();
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        let mut vault_balance = ctx.accounts.pool_vault.amount;
+        let mut fills = Vec::new();
+        for accounts in ctx.remaining_accounts.chunks(2) {
+            // Both accounts are written to: the request is saved back and the user is paid
+            require!(
+                accounts[0].is_writable && accounts[1].is_writable,
+                ErrorCode::WithdrawalAccountNotWritable
+            );
+            require_keys_eq!(
+                *accounts[0].owner,
+                crate::ID,
+                ErrorCode::InvalidWithdrawalRequestOwner
+            );
+            let mut withdrawal_request =
+                Account::<'info, WithdrawalRequest>::try_from(&accounts[0])?;
+            require!(
+                withdrawal_request.token_mint == token_mint,
+                ErrorCode::WithdrawalRequestWrongPool
+            );
+            require!(
+                withdrawal_request.request_id == pool_state.withdrawal_queue_head,
+                ErrorCode::InvalidWithdrawalRequest
+            );
+
+            // Requests that were cancelled or already filled just advance the queue
+            if withdrawal_request.status != WithdrawalStatus::Pending {
+                pool_state.withdrawal_queue_head =
+                    pool_state.withdrawal_queue_head.saturating_add(1);
+                continue;
+            }
+
+            let user_token_account = Account::<'info, TokenAccount>::try_from(&accounts[1])?;
+            require!(
+                user_token_account.owner == withdrawal_request.user
+                    && user_token_account.mint == token_mint,
+                ErrorCode::InvalidWithdrawalDestination
+            );
+
+            // Fill as much of the request as the pool can spare
+            let request_value =
+                shares_to_amount(withdrawal_request.shares, pool_state.supply_index)?;
+            let available = calculate_available_liquidity(pool_state, vault_balance);
+            let amount = std::cmp::min(request_value, available);
+            if amount == 0 {
+                break;
+            }
+
+            // Burn every share on a full fill; round up on a partial one
+            let shares = if amount == request_value {
+                withdrawal_request.shares
+            } else {
+                std::cmp::min(
+                    amount_to_shares(amount, pool_state.supply_index, true)?,
+                    withdrawal_request.shares,
+                )
+            };
+
+            pool_state.total_shares = pool_state.total_shares.saturating_sub(shares);
+            pool_state.total_deposited = pool_state.total_deposited.saturating_sub(amount);
+            pool_state.queued_shares = pool_state.queued_shares.saturating_sub(shares);
+            vault_balance -= amount;
+
+            withdrawal_request.shares = withdrawal_request.shares.saturating_sub(shares);
+            withdrawal_request.principal = withdrawal_request.principal.saturating_sub(amount);
+            withdrawal_request.filled_amount =
+                withdrawal_request.filled_amount.saturating_add(amount);
+            let filled = withdrawal_request.shares == 0;
+            if filled {
+                withdrawal_request.status = WithdrawalStatus::Filled;
+                pool_state.withdrawal_queue_head =
+                    pool_state.withdrawal_queue_head.saturating_add(1);
+                pool_state.pending_withdrawals = pool_state.pending_withdrawals.saturating_sub(1);
+            }
+            withdrawal_request.exit(ctx.program_id)?;
+
+            emit!(WithdrawalFilledEvent {
+                user: withdrawal_request.user,
+                token_mint,
+                request_id: withdrawal_request.request_id,
+                amount,
+                shares,
+                remaining_shares: withdrawal_request.shares,
+                timestamp: current_timestamp,
+            });
+            fills.push((accounts[1].clone(), amount));
+
+            // A partial fill means the pool has nothing more to give this round
+            if !filled {
+                break;
+            }
+        }
+
+        // Transfer tokens from the pool vault to each filled user
+        let bump = ctx.accounts.pool_state.bump;
+        let seeds = &[b"pool_state".as_ref(), token_mint.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        for (user_token_account, amount) in fills {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: user_token_account,
+                    authority: ctx.accounts.pool_state.to_account_info(),
+                },
+                signer_seeds,
+            );
+
+            token::transfer(transfer_ctx, amount)?;
+        }
+
+        Ok(())
+    }
+
+    // Redeem LP receipt tokens for the underlying they are worth - callable by any holder
+    // Each receipt token is one pool share, so no deposit position is needed
+    pub fn redeem_receipts(ctx: Context<RedeemReceipts>, shares: u64) -> Result<()> {
+        require!(shares > 0, ErrorCode::InvalidAmount);
+        // Queued withdrawals are filled first; redemptions can't jump ahead of them
+        require!(
+            ctx.accounts.pool_state.pending_withdrawals == 0,
+            ErrorCode::WithdrawalQueueNotEmpty
+        );
+
+        // Bring the pool indexes up to date before pricing the redeemed shares
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        // Round down so the remaining depositors are never diluted
+        let amount = shares_to_amount(shares, pool_state.supply_index)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            ctx.accounts.pool_vault.amount >= amount,
+            ErrorCode::InsufficientLiquidity
+        );
+
+        pool_state.total_shares = pool_state.total_shares.saturating_sub(shares);
+        pool_state.total_deposited = pool_state.total_deposited.saturating_sub(amount);
+
+        // Leave the remaining depositors a pool they can still withdraw from
+        check_liquidity(
+            pool_state,
+            ctx.accounts.pool_vault.amount.saturating_sub(amount),
+        )?;
+
+        burn_receipts(
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            shares,
+        )?;
+
+        // Transfer tokens from the pool vault to the holder
+        let bump = ctx.accounts.pool_state.bump;
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
             Transfer {
-                from: ctx.accounts.merchant_token_account.to_account_info(),
-                to: ctx.accounts.pool_vault.to_account_info(),
-                authority: ctx.accounts.merchant_wallet.to_account_info(),
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: ctx.accounts.pool_state.to_account_info(),
             },
+            signer_seeds,
         );
 
         token::transfer(transfer_ctx, amount)?;
 
-        // Update loan state
-        let repayment_amount = std::cmp::min(amount, total_outstanding);
+        emit!(ReceiptsRedeemedEvent {
+            user: ctx.accounts.user.key(),
+            token_mint: token_mint_key,
+            shares,
+            amount,
+            supply_index: ctx.accounts.pool_state.supply_index,
+            timestamp: current_timestamp,
+        });
 
-        // Apply repayment to interest first, then principal
-        if repayment_amount <= accrued_interest {
-            // Only paying interest
-            merchant_loan.last_repayment_date = current_timestamp;
-        } else {
-            // Paying off some principal
-            let principal_payment = repayment_amount.saturating_sub(accrued_interest);
-            merchant_loan.principal = merchant_loan.principal.saturating_sub(principal_payment);
-            merchant_loan.last_repayment_date = current_timestamp;
+        Ok(())
+    }
 
-            // Update pool total borrowed
-            let pool_state = &mut ctx.accounts.pool_state;
-            pool_state.total_borrowed = pool_state.total_borrowed.saturating_sub(principal_payment);
+    // Approve a yield strategy program for idle vault funds - only callable by authority
+    pub fn approve_strategy(ctx: Context<ApproveStrategy>, allocation_cap: u64) -> Result<()> {
+        let strategy = &mut ctx.accounts.strategy;
+        strategy.token_mint = ctx.accounts.token_mint.key();
+        strategy.strategy_program = ctx.accounts.strategy_program.key();
+        strategy.allocation_cap = allocation_cap;
+        strategy.allocated = 0;
+        strategy.marked_value = 0;
+        strategy.total_harvested = 0;
+        strategy.is_active = true;
+        strategy.strategy_token_account = ctx.accounts.strategy_token_account.key();
+        strategy.authority_bump = ctx.bumps.strategy_authority;
+        strategy.bump = ctx.bumps.strategy;
+
+        emit!(StrategyUpdatedEvent {
+            token_mint: strategy.token_mint,
+            strategy_program: strategy.strategy_program,
+            authority: ctx.accounts.authority.key(),
+            allocation_cap,
+            is_active: true,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-            // If loan fully repaid, update status
-            if merchant_loan.principal == 0 {
-                merchant_loan.status = LoanStatus::Repaid;
-            }
-        }
+        Ok(())
+    }
 
-        // Emit repayment event
-        emit!(RepaymentEvent {
-            merchant: ctx.accounts.merchant_wallet.key(),
+    // Change a strategy's allocation cap or retire it - only callable by authority
+    // Retired strategies take no new funds but can still be harvested and unwound
+    pub fn update_strategy(
+        ctx: Context<UpdateStrategy>,
+        allocation_cap: u64,
+        is_active: bool,
+    ) -> Result<()> {
+        let strategy = &mut ctx.accounts.strategy;
+        strategy.allocation_cap = allocation_cap;
+        strategy.is_active = is_active;
+
+        emit!(StrategyUpdatedEvent {
+            token_mint: strategy.token_mint,
+            strategy_program: strategy.strategy_program,
+            authority: ctx.accounts.authority.key(),
+            allocation_cap,
+            is_active,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Move idle vault funds into an approved strategy - only callable by authority
+    // Remaining accounts are the strategy's own accounts for its deposit instruction
+    pub fn allocate_to_strategy<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ManageStrategy<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let strategy = &ctx.accounts.strategy;
+        require!(strategy.is_active, ErrorCode::StrategyInactive);
+        require!(
+            strategy.allocated.saturating_add(amount) <= strategy.allocation_cap,
+            ErrorCode::StrategyCapExceeded
+        );
+        // Queued withdrawals are filled before idle funds are put to work
+        require!(
+            ctx.accounts.pool_state.pending_withdrawals == 0,
+            ErrorCode::WithdrawalQueueNotEmpty
+        );
+        let vault_balance = ctx.accounts.pool_vault.amount;
+        require!(vault_balance >= amount, ErrorCode::InsufficientLiquidity);
+
+        // Allocated funds count against the utilization cap alongside loans
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+        pool_state.total_allocated = pool_state.total_allocated.saturating_add(amount);
+        check_liquidity(pool_state, vault_balance - amount)?;
+
+        // Stage the funds with the strategy authority, which is all the strategy gets to sign with
+        let token_mint = ctx.accounts.token_mint.key();
+        let bump = ctx.accounts.pool_state.bump;
+        let seeds = &[b"pool_state".as_ref(), token_mint.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.strategy_token_account.to_account_info(),
+                authority: ctx.accounts.pool_state.to_account_info(),
+            },
+            signer_seeds,
+        );
+
+        token::transfer(transfer_ctx, amount)?;
+
+        // Loaded before the transfer, so this is the balance without the staged funds
+        let unstaged_balance = ctx.accounts.strategy_token_account.amount;
+        invoke_strategy(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            strategy_instruction_data("deposit", Some(amount)),
+        )?;
+
+        // The strategy must take exactly what was allocated
+        ctx.accounts.strategy_token_account.reload()?;
+        require!(
+            ctx.accounts.strategy_token_account.amount == unstaged_balance,
+            ErrorCode::StrategyTransferMismatch
+        );
+
+        let strategy = &mut ctx.accounts.strategy;
+        strategy.allocated = strategy.allocated.saturating_add(amount);
+        strategy.marked_value = strategy.marked_value.saturating_add(amount);
+
+        emit!(StrategyAllocatedEvent {
+            token_mint,
+            strategy_program: strategy.strategy_program,
+            amount,
+            allocated: strategy.allocated,
+            total_allocated: ctx.accounts.pool_state.total_allocated,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Collect a strategy's profit into the pool vault - only callable by authority
+    // Remaining accounts are the strategy's own accounts for its harvest instruction
+    pub fn harvest_strategy<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ManageStrategy<'info>>,
+    ) -> Result<()> {
+        // Book interest under the old supply before the yield lands
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        accrue_interest(&mut ctx.accounts.pool_state, current_timestamp)?;
+
+        invoke_strategy(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            strategy_instruction_data("harvest", None),
+        )?;
+        let value = read_strategy_value(ctx.accounts, ctx.remaining_accounts)?;
+        let harvested = sweep_strategy_token_account(ctx.accounts)?;
+        let (protocol_fee, _) = mark_strategy_value(
+            &mut ctx.accounts.pool_state,
+            &mut ctx.accounts.strategy,
+            harvested,
+            0,
+            value,
+        )?;
+
+        let strategy = &mut ctx.accounts.strategy;
+        strategy.total_harvested = strategy.total_harvested.saturating_add(harvested);
+
+        emit!(StrategyHarvestedEvent {
             token_mint: ctx.accounts.token_mint.key(),
-            amount: repayment_amount,
-            to_interest: std::cmp::min(repayment_amount, accrued_interest),
-            to_principal: repayment_amount.saturating_sub(accrued_interest),
-            remaining_principal: merchant_loan.principal,
+            strategy_program: strategy.strategy_program,
+            amount: harvested,
+            protocol_fee,
+            total_harvested: strategy.total_harvested,
+            supply_index: ctx.accounts.pool_state.supply_index,
             timestamp: current_timestamp,
         });
 
         Ok(())
     }
 
-    // Get the current deposit APR
-    pub fn get_deposit_apr(ctx: Context<GetInterestRate>) -> Result<u64> {
-        let pool_state = &ctx.accounts.pool_state;
-        let borrow_apr = calculate_borrow_apr(
-            pool_state.base_rate,
-            pool_state.utilization_slope,
-            pool_state.total_deposited,
-            pool_state.total_borrowed,
+    // Pull allocated funds back from a strategy into the pool vault - only callable by authority
+    // Remaining accounts are the strategy's own accounts for its withdraw instruction
+    pub fn unwind_strategy<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ManageStrategy<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            ctx.accounts.strategy.allocated >= amount,
+            ErrorCode::InsufficientStrategyBalance
         );
 
-        // Calculate utilization rate (in basis points)
-        let utilization_rate = if pool_state.total_deposited == 0 {
-            0
-        } else {
-            ((pool_state.total_borrowed as u128) * 10_000 / (pool_state.total_deposited as u128))
-                as u64
-        };
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        accrue_interest(&mut ctx.accounts.pool_state, current_timestamp)?;
+
+        invoke_strategy(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            strategy_instruction_data("withdraw", Some(amount)),
+        )?;
+        let value = read_strategy_value(ctx.accounts, ctx.remaining_accounts)?;
+        let received = sweep_strategy_token_account(ctx.accounts)?;
+
+        // Anything above principal is yield; a shortfall is socialized across depositors
+        let (_, loss) = mark_strategy_value(
+            &mut ctx.accounts.pool_state,
+            &mut ctx.accounts.strategy,
+            received,
+            amount,
+            value,
+        )?;
 
-        // deposit_apr = (borrow_apr * utilization) * (1 - protocol_fee_percent)
-        let deposit_apr = (borrow_apr as u128)
-            .saturating_mul(utilization_rate as u128)
-            .saturating_div(10_000)
-            .saturating_mul((100 - pool_state.protocol_fee_percent as u128))
-            .saturating_div(100)
-            .try_into()
-            .unwrap_or(0);
+        emit!(StrategyUnwoundEvent {
+            token_mint: ctx.accounts.token_mint.key(),
+            strategy_program: ctx.accounts.strategy.strategy_program,
+            amount,
+            received,
+            loss,
+            allocated: ctx.accounts.strategy.allocated,
+            total_allocated: ctx.accounts.pool_state.total_allocated,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Mark a strategy's position to the value it reports - only callable by authority
+    // Remaining accounts are the strategy's own accounts for its report_value instruction
+    pub fn mark_strategy<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ManageStrategy<'info>>,
+    ) -> Result<()> {
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        accrue_interest(&mut ctx.accounts.pool_state, current_timestamp)?;
+
+        let value = read_strategy_value(ctx.accounts, ctx.remaining_accounts)?;
+        let (_, loss) = mark_strategy_value(
+            &mut ctx.accounts.pool_state,
+            &mut ctx.accounts.strategy,
+            0,
+            0,
+            value,
+        )?;
+
+        emit!(StrategyMarkedEvent {
+            token_mint: ctx.accounts.token_mint.key(),
+            strategy_program: ctx.accounts.strategy.strategy_program,
+            value,
+            marked_value: ctx.accounts.strategy.marked_value,
+            loss,
+            total_deposited: ctx.accounts.pool_state.total_deposited,
+            supply_index: ctx.accounts.pool_state.supply_index,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Process a payment from user to merchant via the pool
+    // Priority: Interest earned > Deposited funds > Direct wallet
+    pub fn pay_via_pool(ctx: Context<PayViaPool>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_PAYMENTS),
+            ErrorCode::ActionPaused
+        );
+
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let user = ctx.accounts.user_wallet.key();
+        let merchant = ctx.accounts.merchant_wallet.key();
+        let token_mint = ctx.accounts.token_mint.key();
+
+        // Paying yourself would only inflate your own revenue and trust score
+        require!(user != merchant, ErrorCode::SelfPayment);
+
+        // Calculate payment sources
+        let mut from_interest = 0;
+        let mut from_deposit = 0;
+
+        // If user has a deposit account, use interest first, then deposit
+        if let Some(user_deposit) = &mut ctx.accounts.user_deposit {
+            authorize_position(
+                user_deposit,
+                user,
+                token_mint,
+                ctx.accounts.position_token_account.as_ref(),
+            )?;
+            let released = reconcile_receipts(
+                user_deposit,
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.user_receipt_account.as_ref(),
+            )?;
+            if released > 0 {
+                let depositor = ctx
+                    .accounts
+                    .depositor
+                    .as_mut()
+                    .ok_or(ErrorCode::MissingDepositor)?;
+                depositor.deposited_amount = depositor.deposited_amount.saturating_sub(released);
+            }
+            let pool_state = &mut ctx.accounts.pool_state;
+            accrue_interest(pool_state, current_timestamp)?;
+
+            // Earnings are whatever the shares are worth above the principal
+            let position_value = shares_to_amount(user_deposit.shares, pool_state.supply_index)?;
+            let earned = position_value.saturating_sub(user_deposit.deposited_amount);
+
+            // Use interest first
+            from_interest = std::cmp::min(earned, amount);
+
+            // If interest not enough, use deposit
+            let remaining = amount.saturating_sub(from_interest);
+            let available_deposit = std::cmp::min(
+                user_deposit.deposited_amount,
+                position_value.saturating_sub(from_interest),
+            );
+            from_deposit = std::cmp::min(available_deposit, remaining);
+
+            let from_pool = from_interest + from_deposit;
+            if from_pool > 0 {
+                let shares = amount_to_shares(from_pool, pool_state.supply_index, true)?;
+                let shares = std::cmp::min(shares, user_deposit.shares);
+
+                user_deposit.shares = user_deposit.shares.saturating_sub(shares);
+                user_deposit.deposited_amount =
+                    user_deposit.deposited_amount.saturating_sub(from_deposit);
+                if from_deposit > 0 {
+                    let depositor = ctx
+                        .accounts
+                        .depositor
+                        .as_mut()
+                        .ok_or(ErrorCode::MissingDepositor)?;
+                    depositor.deposited_amount =
+                        depositor.deposited_amount.saturating_sub(from_deposit);
+                }
+
+                // Update pool totals
+                pool_state.total_shares = pool_state.total_shares.saturating_sub(shares);
+                pool_state.total_deposited = pool_state.total_deposited.saturating_sub(from_pool);
+
+                // Spending a position withdraws from the pool, under the same rules as withdraw
+                require!(
+                    pool_state.pending_withdrawals == 0,
+                    ErrorCode::WithdrawalQueueNotEmpty
+                );
+                require!(
+                    ctx.accounts.pool_vault.amount >= from_pool,
+                    ErrorCode::InsufficientLiquidity
+                );
+                check_liquidity(
+                    pool_state,
+                    ctx.accounts.pool_vault.amount.saturating_sub(from_pool),
+                )?;
+
+                if let Some((receipt_mint, user_receipt_account)) = position_receipts(
+                    user_deposit,
+                    ctx.accounts.receipt_mint.as_ref(),
+                    ctx.accounts.user_receipt_account.as_ref(),
+                )? {
+                    burn_receipts(
+                        receipt_mint,
+                        user_receipt_account,
+                        &ctx.accounts.user_wallet,
+                        &ctx.accounts.token_program,
+                        shares,
+                    )?;
+                }
+            }
+        }
+
+        // If interest + deposit not enough, use wallet
+        let from_wallet = amount.saturating_sub(from_interest + from_deposit);
+
+        // A merchant with an active sweep can't dodge it by leaving their loan out
+        let mut merchant_credit = load_merchant_credit(&ctx.accounts.merchant_credit)?;
+        if let Some(sweep_loan) = merchant_credit
+            .as_ref()
+            .and_then(|credit| credit.sweep_loan)
+        {
+            require!(
+                ctx.accounts
+                    .merchant_loan
+                    .as_ref()
+                    .is_some_and(|loan| loan.loan_index == sweep_loan),
+                ErrorCode::MissingSweepLoan
+            );
+        }
+
+        // Sweep the merchant's opted-in share of the payment into their loan
+        let mut to_loan = 0;
+        let mut to_reserve = 0;
+        if let Some(merchant_loan) = &mut ctx.accounts.merchant_loan {
+            let merchant_credit = merchant_credit
+                .as_mut()
+                .ok_or(ErrorCode::MissingMerchantCredit)?;
+            if merchant_loan.sweep_percent > 0 && is_loan_outstanding(merchant_loan) {
+                let sweep_amount =
+                    (amount as u128 * merchant_loan.sweep_percent as u128 / 100) as u64;
+                let payment = settle_loan_payment(
+                    &mut ctx.accounts.pool_state,
+                    merchant_loan,
+                    merchant_credit,
+                    sweep_amount,
+                    token_mint,
+                    current_timestamp,
+                )?;
+                to_loan = payment.amount;
+                to_reserve = payment.to_reserve;
+
+                if to_loan > 0 {
+                    emit!(RepaymentEvent {
+                        merchant,
+                        token_mint,
+                        loan_index: merchant_loan.loan_index,
+                        amount: payment.amount,
+                        to_late_fees: payment.to_late_fees,
+                        to_interest: payment.to_interest,
+                        to_principal: payment.to_principal,
+                        to_reserve,
+                        remaining_principal: merchant_loan.principal,
+                        next_due_date: merchant_loan.next_due_date,
+                        timestamp: current_timestamp,
+                    });
+                }
+            }
+        }
+        if let Some(merchant_credit) = &merchant_credit {
+            store_merchant_credit(&ctx.accounts.merchant_credit, merchant_credit)?;
+        }
+        let to_merchant = amount - to_loan;
+
+        // Pool funds already sit in the vault, so they cover the loan share first
+        let from_pool = from_interest + from_deposit;
+        let pool_to_merchant = from_pool.saturating_sub(to_loan);
+        let wallet_to_pool = to_loan.saturating_sub(from_pool);
+        let wallet_to_merchant = from_wallet - wallet_to_pool;
+
+        // Process transfers based on source allocation
+
+        // Transfer from wallet if needed
+        if wallet_to_merchant > 0 {
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.merchant_token_account.to_account_info(),
+                    authority: ctx.accounts.user_wallet.to_account_info(),
+                },
+            );
+
+            token::transfer(transfer_ctx, wallet_to_merchant)?;
+        }
+
+        // Transfer the rest of the loan share from wallet to pool vault
+        if wallet_to_pool > 0 {
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.pool_vault.to_account_info(),
+                    authority: ctx.accounts.user_wallet.to_account_info(),
+                },
+            );
+
+            token::transfer(transfer_ctx, wallet_to_pool)?;
+        }
+
+        let bump = ctx.accounts.pool_state.bump;
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        // Transfer from pool if using interest or deposit
+        if pool_to_merchant > 0 {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: ctx.accounts.merchant_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_state.to_account_info(),
+                },
+                signer_seeds,
+            );
+
+            token::transfer(transfer_ctx, pool_to_merchant)?;
+        }
+
+        // Move the reserve's cut of the swept interest to the reserve vault
+        if to_reserve > 0 {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: ctx.accounts.reserve_vault.to_account_info(),
+                    authority: ctx.accounts.pool_state.to_account_info(),
+                },
+                signer_seeds,
+            );
+
+            token::transfer(transfer_ctx, to_reserve)?;
+        }
+
+        // Customer payments count toward the merchant's trust score
+        // They are not booked as revenue: a second wallet could cycle the same funds back to the
+        // merchant for free, so only subscription sales raise the borrow limit
+        if let Some(trust_score) = &ctx.accounts.trust_score {
+            record_trust_event(
+                ctx.accounts.merchant_registry_program.to_account_info(),
+                ctx.accounts.registry_state.to_account_info(),
+                trust_score.to_account_info(),
+                ctx.accounts.trust_score_authority.to_account_info(),
+                ctx.bumps.trust_score_authority,
+                TrustEvent::Payment { amount },
+            )?;
+        }
+
+        // Emit payment event
+        emit!(PaymentEvent {
+            user,
+            merchant,
+            token_mint,
+            total_amount: amount,
+            from_interest,
+            from_deposit,
+            from_wallet,
+            to_merchant,
+            to_loan,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Merchant opts in to repaying their loan from a share of incoming payments
+    pub fn set_repayment_sweep(ctx: Context<SetRepaymentSweep>, sweep_percent: u8) -> Result<()> {
+        require!(sweep_percent <= 100, ErrorCode::InvalidSweepPercentage);
+
+        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        let merchant_credit = &mut ctx.accounts.merchant_credit;
+        let loan_index = merchant_loan.loan_index;
+
+        // One loan at a time carries the sweep, and every payment must bring it along
+        if sweep_percent > 0 {
+            require!(is_loan_outstanding(merchant_loan), ErrorCode::LoanNotActive);
+            require!(
+                !matches!(merchant_credit.sweep_loan, Some(index) if index != loan_index),
+                ErrorCode::SweepAlreadyActive
+            );
+            merchant_credit.sweep_loan = Some(loan_index);
+        } else if merchant_credit.sweep_loan == Some(loan_index) {
+            merchant_credit.sweep_loan = None;
+        }
+
+        let previous_sweep_percent = merchant_loan.sweep_percent;
+        merchant_loan.sweep_percent = sweep_percent;
+
+        emit!(RepaymentSweepUpdatedEvent {
+            merchant: ctx.accounts.merchant_wallet.key(),
+            token_mint: ctx.accounts.token_mint.key(),
+            loan_index: merchant_loan.loan_index,
+            previous_sweep_percent,
+            sweep_percent,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Merchant borrows funds from the pool
+    pub fn borrow(ctx: Context<Borrow>, amount: u64, term_days: u16) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_BORROWS),
+            ErrorCode::ActionPaused
+        );
+        require!(
+            term_days > 0 && term_days <= ctx.accounts.pool_state.loan_policy.max_term_days,
+            ErrorCode::InvalidLoanTerm
+        );
+
+        // Verify merchant is registered and verified
+        let merchant_account = &ctx.accounts.merchant_account;
+        require!(merchant_account.verified, ErrorCode::MerchantNotVerified);
+
+        // Read the merchant's trust score (1-5) and revenue from the registry to derive the borrow limit
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let trust_score = ctx
+            .accounts
+            .trust_score
+            .calculate_score(current_timestamp)
+            .clamp(MIN_TRUST_SCORE, MAX_TRUST_SCORE);
+        let monthly_revenue = ctx
+            .accounts
+            .revenue_ledger
+            .trailing_monthly_revenue(current_timestamp);
+        let borrow_limit = calculate_borrow_limit(trust_score, monthly_revenue);
+
+        // Bring the pool up to date before issuing the loan
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        // Merchants behind on any loan can't draw more until they catch up
+        let merchant_credit = &mut ctx.accounts.merchant_credit;
+        require!(
+            merchant_credit.loans_past_due == 0 && merchant_credit.written_off_loans == 0,
+            ErrorCode::LoanNotInGoodStanding
+        );
+
+        // Charge the origination fee, either out of the disbursement or on top of the principal
+        let loan_policy = pool_state.loan_policy;
+        let origination_fee =
+            (amount as u128 * loan_policy.origination_fee_bps as u128 / BASIS_POINTS) as u64;
+        let (principal, disbursement) = match loan_policy.origination_fee_mode {
+            OriginationFeeMode::Deducted => (amount, amount - origination_fee),
+            OriginationFeeMode::Financed => (amount.saturating_add(origination_fee), amount),
+        };
+
+        // Check if merchant can borrow this amount on top of their open loans
+        let total_borrowed_after = merchant_credit
+            .outstanding_principal
+            .saturating_add(principal);
+        require!(
+            total_borrowed_after <= borrow_limit,
+            ErrorCode::ExceedsBorrowLimit
+        );
+
+        // Check if there's enough liquidity in the vault
+        let fee_to_reserve = (origination_fee as u128
+            * loan_policy.origination_reserve_percent as u128
+            / 100) as u64;
+        let vault_outflow = disbursement.saturating_add(fee_to_reserve);
+        require!(
+            ctx.accounts.pool_vault.amount >= vault_outflow,
+            ErrorCode::InsufficientLiquidity
+        );
+
+        // Split the principal into equal installments over the term
+        let installment_interval_days = loan_policy.payment_interval_days;
+        let installment_count = (term_days as u64).div_ceil(installment_interval_days as u64);
+        let installment_amount = principal.div_ceil(installment_count);
+
+        // Each drawdown gets its own loan account, numbered by the merchant's loan counter
+        let loan_index = merchant_credit.loan_count;
+        merchant_credit.merchant = ctx.accounts.merchant_wallet.key();
+        merchant_credit.token_mint = ctx.accounts.token_mint.key();
+        merchant_credit.loan_count = loan_index.saturating_add(1);
+        merchant_credit.open_loans = merchant_credit.open_loans.saturating_add(1);
+        merchant_credit.outstanding_principal = total_borrowed_after;
+
+        // Update merchant loan account
+        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        merchant_loan.merchant = ctx.accounts.merchant_wallet.key();
+        merchant_loan.loan_index = loan_index;
+        merchant_loan.principal = principal;
+        merchant_loan.accrued_interest = 0;
+        merchant_loan.borrow_index = pool_state.borrow_index;
+        merchant_loan.issue_date = current_timestamp;
+        merchant_loan.last_repayment_date = current_timestamp;
+        merchant_loan.term_days = term_days;
+        merchant_loan.installment_interval_days = installment_interval_days;
+        merchant_loan.installment_amount = installment_amount;
+        merchant_loan.maturity_date = current_timestamp + term_days as i64 * SECONDS_PER_DAY;
+        merchant_loan.next_due_date = std::cmp::min(
+            current_timestamp + installment_interval_days as i64 * SECONDS_PER_DAY,
+            merchant_loan.maturity_date,
+        );
+        merchant_loan.late_fees = 0;
+        merchant_loan.last_late_fee_date = current_timestamp;
+        merchant_loan.days_past_due = 0;
+        merchant_loan.status = LoanStatus::Active;
+
+        // Update pool state
+        pool_state.total_borrowed = pool_state.total_borrowed.saturating_add(principal);
+        check_liquidity(pool_state, ctx.accounts.pool_vault.amount - vault_outflow)?;
+
+        // The reserve takes its cut of the fee and the rest is owed to the treasury
+        let fee_to_treasury = origination_fee - fee_to_reserve;
+        pool_state.accrued_protocol_fees = pool_state
+            .accrued_protocol_fees
+            .saturating_add(fee_to_treasury);
+
+        // Transfer tokens from pool vault to merchant
+        let bump = ctx.accounts.pool_state.bump;
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.merchant_token_account.to_account_info(),
+                authority: ctx.accounts.pool_state.to_account_info(),
+            },
+            signer_seeds,
+        );
+
+        token::transfer(transfer_ctx, disbursement)?;
+
+        // Transfer the reserve's share of the fee from pool vault to reserve vault
+        if fee_to_reserve > 0 {
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    to: ctx.accounts.reserve_vault.to_account_info(),
+                    authority: ctx.accounts.pool_state.to_account_info(),
+                },
+                signer_seeds,
+            );
+
+            token::transfer(transfer_ctx, fee_to_reserve)?;
+        }
+
+        // Emit borrow event
+        emit!(BorrowEvent {
+            merchant: ctx.accounts.merchant_wallet.key(),
+            token_mint: ctx.accounts.token_mint.key(),
+            loan_index: ctx.accounts.merchant_loan.loan_index,
+            amount,
+            principal,
+            disbursed: disbursement,
+            origination_fee,
+            fee_to_treasury,
+            fee_to_reserve,
+            trust_score,
+            monthly_revenue,
+            borrow_limit,
+            current_outstanding: total_borrowed_after,
+            term_days,
+            installment_amount,
+            maturity_date: ctx.accounts.merchant_loan.maturity_date,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Merchant repays loan
+    pub fn repay_loan(ctx: Context<RepayLoan>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        require!(is_loan_outstanding(merchant_loan), ErrorCode::LoanNotActive);
+
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let on_time = calculate_days_past_due(merchant_loan, current_timestamp) == 0;
+        let payment = settle_loan_payment(
+            &mut ctx.accounts.pool_state,
+            merchant_loan,
+            &mut ctx.accounts.merchant_credit,
+            amount,
+            ctx.accounts.token_mint.key(),
+            current_timestamp,
+        )?;
+        require!(payment.amount > 0, ErrorCode::InvalidAmount);
+        let repayment_amount = payment.amount;
+        let reserve_amount = payment.to_reserve;
+
+        // Repayment punctuality feeds the merchant's trust score
+        record_trust_event(
+            ctx.accounts.merchant_registry_program.to_account_info(),
+            ctx.accounts.registry_state.to_account_info(),
+            ctx.accounts.trust_score.to_account_info(),
+            ctx.accounts.trust_score_authority.to_account_info(),
+            ctx.bumps.trust_score_authority,
+            TrustEvent::Repayment {
+                on_time,
+                principal: payment.to_principal,
+            },
+        )?;
+
+        // Transfer tokens from merchant to pool vault
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.merchant_token_account.to_account_info(),
+                to: ctx.accounts.pool_vault.to_account_info(),
+                authority: ctx.accounts.merchant_wallet.to_account_info(),
+            },
+        );
+
+        token::transfer(transfer_ctx, repayment_amount - reserve_amount)?;
+
+        // Transfer the reserve's cut from merchant to reserve vault
+        if reserve_amount > 0 {
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.merchant_token_account.to_account_info(),
+                    to: ctx.accounts.reserve_vault.to_account_info(),
+                    authority: ctx.accounts.merchant_wallet.to_account_info(),
+                },
+            );
+
+            token::transfer(transfer_ctx, reserve_amount)?;
+        }
+
+        // Emit repayment event
+        emit!(RepaymentEvent {
+            merchant: ctx.accounts.merchant_wallet.key(),
+            token_mint: ctx.accounts.token_mint.key(),
+            loan_index: ctx.accounts.merchant_loan.loan_index,
+            amount: repayment_amount,
+            to_late_fees: payment.to_late_fees,
+            to_interest: payment.to_interest,
+            to_principal: payment.to_principal,
+            to_reserve: reserve_amount,
+            remaining_principal: ctx.accounts.merchant_loan.principal,
+            next_due_date: ctx.accounts.merchant_loan.next_due_date,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Draw on the reserve to pay down a defaulted loan - only callable by governance
+    pub fn cover_loan_default(ctx: Context<CoverLoanDefault>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Bring the pool and loan up to date before applying the payment
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        accrue_interest(pool_state, current_timestamp)?;
+
+        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        let (
+            cover_amount,
+            late_fee_payment,
+            interest_payment,
+            principal_payment,
+            remaining_outstanding,
+        ) = if merchant_loan.status == LoanStatus::WrittenOff {
+            // Depositors already took the loss; the reserve makes them whole again
+            let cover_amount = std::cmp::min(amount, merchant_loan.written_off_amount);
+            require!(cover_amount > 0, ErrorCode::InvalidAmount);
+
+            merchant_loan.written_off_amount = merchant_loan
+                .written_off_amount
+                .saturating_sub(cover_amount);
+            let total_deposited = pool_state.total_deposited.saturating_add(cover_amount);
+            rebase_total_deposited(pool_state, total_deposited)?;
+
+            (cover_amount, 0, 0, 0, merchant_loan.written_off_amount)
+        } else {
+            require!(is_loan_outstanding(merchant_loan), ErrorCode::LoanNotActive);
+            bring_loan_current(
+                pool_state,
+                merchant_loan,
+                ctx.accounts.token_mint.key(),
+                current_timestamp,
+            )?;
+
+            let total_outstanding = get_outstanding_loan_amount(merchant_loan);
+            let cover_amount = std::cmp::min(amount, total_outstanding);
+            require!(cover_amount > 0, ErrorCode::InvalidAmount);
+
+            // The reserve pays the loan down exactly as the merchant would have
+            let previous_status = merchant_loan.status;
+            let (late_fee_payment, interest_payment, principal_payment) =
+                apply_loan_payment(merchant_loan, cover_amount, current_timestamp);
+            pool_state.total_borrowed = pool_state.total_borrowed.saturating_sub(cover_amount);
+            update_merchant_credit(
+                &mut ctx.accounts.merchant_credit,
+                merchant_loan,
+                previous_status,
+                principal_payment,
+            );
+            emit_loan_status_change(
+                merchant_loan,
+                previous_status,
+                ctx.accounts.token_mint.key(),
+                current_timestamp,
+            );
+
+            (
+                cover_amount,
+                late_fee_payment,
+                interest_payment,
+                principal_payment,
+                total_outstanding - cover_amount,
+            )
+        };
+        require!(
+            ctx.accounts.reserve_vault.amount >= cover_amount,
+            ErrorCode::InsufficientReserve
+        );
+
+        // Transfer tokens from reserve vault to pool vault
+        let bump = ctx.accounts.pool_state.bump;
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let seeds = &[b"pool_state".as_ref(), token_mint_key.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.reserve_vault.to_account_info(),
+                to: ctx.accounts.pool_vault.to_account_info(),
+                authority: ctx.accounts.pool_state.to_account_info(),
+            },
+            signer_seeds,
+        );
+
+        token::transfer(transfer_ctx, cover_amount)?;
+
+        emit!(ReserveCoverageEvent {
+            merchant: ctx.accounts.merchant_loan.merchant,
+            token_mint: ctx.accounts.token_mint.key(),
+            loan_index: ctx.accounts.merchant_loan.loan_index,
+            amount: cover_amount,
+            to_late_fees: late_fee_payment,
+            to_interest: interest_payment,
+            to_principal: principal_payment,
+            remaining_outstanding,
+            reserve_balance: ctx.accounts.reserve_vault.amount - cover_amount,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Advance a loan that has fallen behind on payments - callable by anyone
+    pub fn mark_delinquent(ctx: Context<MarkDelinquent>) -> Result<()> {
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        require!(is_loan_outstanding(merchant_loan), ErrorCode::LoanNotActive);
+
+        // Missed installments are charged even if the merchant never repays
+        bring_loan_current(
+            pool_state,
+            merchant_loan,
+            ctx.accounts.token_mint.key(),
+            current_timestamp,
+        )?;
+
+        let loan_policy = &pool_state.loan_policy;
+        let days_past_due = calculate_days_past_due(merchant_loan, current_timestamp);
+        let next_status = match merchant_loan.status {
+            LoanStatus::Active if days_past_due >= loan_policy.delinquency_grace_days => {
+                LoanStatus::Delinquent
+            }
+            LoanStatus::Delinquent if days_past_due >= loan_policy.default_grace_days => {
+                LoanStatus::Defaulted
+            }
+            _ => return err!(ErrorCode::LoanNotPastDue),
+        };
+
+        let previous_status = merchant_loan.status;
+        merchant_loan.days_past_due = days_past_due;
+        merchant_loan.status = next_status;
+        update_merchant_credit(
+            &mut ctx.accounts.merchant_credit,
+            merchant_loan,
+            previous_status,
+            0,
+        );
+        emit_loan_status_change(
+            merchant_loan,
+            previous_status,
+            ctx.accounts.token_mint.key(),
+            current_timestamp,
+        );
+
+        // Falling behind on a loan counts against the merchant's trust score
+        let event = if next_status == LoanStatus::Defaulted {
+            TrustEvent::Default
+        } else {
+            TrustEvent::Delinquency
+        };
+        record_trust_event(
+            ctx.accounts.merchant_registry_program.to_account_info(),
+            ctx.accounts.registry_state.to_account_info(),
+            ctx.accounts.trust_score.to_account_info(),
+            ctx.accounts.trust_score_authority.to_account_info(),
+            ctx.bumps.trust_score_authority,
+            event,
+        )?;
+
+        Ok(())
+    }
+
+    // Write off a defaulted loan, socializing the loss across depositors - only callable by authority
+    pub fn write_off_loan(ctx: Context<WriteOffLoan>) -> Result<()> {
+        let merchant_loan = &mut ctx.accounts.merchant_loan;
+        require!(
+            merchant_loan.status == LoanStatus::Defaulted,
+            ErrorCode::LoanNotDefaulted
+        );
+
+        // Bring the pool and loan up to date so the full balance is written off
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let pool_state = &mut ctx.accounts.pool_state;
+        bring_loan_current(
+            pool_state,
+            merchant_loan,
+            ctx.accounts.token_mint.key(),
+            current_timestamp,
+        )?;
+
+        let previous_status = merchant_loan.status;
+        let written_off_principal = merchant_loan.principal;
+        let (outstanding, fee_reversal, depositor_loss) =
+            socialize_loan_loss(pool_state, merchant_loan)?;
+        update_merchant_credit(
+            &mut ctx.accounts.merchant_credit,
+            merchant_loan,
+            previous_status,
+            written_off_principal,
+        );
+        emit_loan_status_change(
+            merchant_loan,
+            previous_status,
+            ctx.accounts.token_mint.key(),
+            current_timestamp,
+        );
+
+        emit!(LoanWrittenOffEvent {
+            merchant: merchant_loan.merchant,
+            token_mint: ctx.accounts.token_mint.key(),
+            loan_index: merchant_loan.loan_index,
+            amount: outstanding,
+            depositor_loss,
+            protocol_fee_reversal: fee_reversal,
+            supply_index: pool_state.supply_index,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Get the current deposit APR
+    pub fn get_deposit_apr(ctx: Context<GetInterestRate>) -> Result<u64> {
+        let pool_state = &ctx.accounts.pool_state;
+        let borrow_apr = calculate_borrow_apr(pool_state);
+        let utilization_rate = calculate_utilization_rate(pool_state);
+
+        // deposit_apr = (borrow_apr * utilization) * (1 - protocol_fee_percent)
+        let deposit_apr = (borrow_apr as u128)
+            .saturating_mul(utilization_rate as u128)
+            .saturating_div(BASIS_POINTS)
+            .saturating_mul(100 - pool_state.protocol_fee_percent as u128)
+            .saturating_div(100)
+            .try_into()
+            .unwrap_or(0);
+
+        Ok(deposit_apr)
+    }
+
+    // Get the current borrow APR
+    pub fn get_borrow_apr(ctx: Context<GetInterestRate>) -> Result<u64> {
+        let pool_state = &ctx.accounts.pool_state;
+        let borrow_apr = calculate_borrow_apr(pool_state);
+
+        Ok(borrow_apr)
+    }
+}
+
+// Helper function to accrue borrow interest into the pool indexes
+// Borrowers owe the full interest; depositors receive it net of the protocol fee
+fn accrue_interest(pool_state: &mut PoolState, current_timestamp: i64) -> Result<()> {
+    if current_timestamp <= pool_state.last_accrual_timestamp {
+        return Ok(());
+    }
+
+    // Calculate time elapsed in seconds
+    let time_elapsed = (current_timestamp - pool_state.last_accrual_timestamp) as u128;
+    pool_state.last_accrual_timestamp = current_timestamp;
+
+    if pool_state.total_borrowed == 0 {
+        return Ok(());
+    }
+
+    // Calculate borrow APR in basis points (1/100 of 1%)
+    let borrow_apr = calculate_borrow_apr(pool_state);
+
+    // Interest factor over the elapsed period, scaled by INDEX_SCALE
+    let interest_factor = (borrow_apr as u128)
+        .checked_mul(time_elapsed)
+        .and_then(|v| v.checked_mul(INDEX_SCALE))
+        .ok_or(ErrorCode::MathOverflow)?
+        / (BASIS_POINTS * SECONDS_PER_YEAR);
+
+    let borrow_index_growth = pool_state
+        .borrow_index
+        .checked_mul(interest_factor)
+        .ok_or(ErrorCode::MathOverflow)?
+        / INDEX_SCALE;
+    pool_state.borrow_index = pool_state
+        .borrow_index
+        .checked_add(borrow_index_growth)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let interest = (pool_state.total_borrowed as u128)
+        .checked_mul(interest_factor)
+        .ok_or(ErrorCode::MathOverflow)?
+        / INDEX_SCALE;
+    let depositor_interest = interest * (100 - pool_state.protocol_fee_percent as u128) / 100;
+
+    // Grow the value of every share by the depositors' portion of the interest
+    let total_deposited = u64::try_from(pool_state.total_deposited as u128 + depositor_interest)
+        .map_err(|_| ErrorCode::MathOverflow)?;
+    rebase_total_deposited(pool_state, total_deposited)?;
+
+    pool_state.total_borrowed = u64::try_from(pool_state.total_borrowed as u128 + interest)
+        .map_err(|_| ErrorCode::MathOverflow)?;
+
+    // The protocol fee is the spread between what borrowers owe and depositors earn
+    let protocol_interest = interest - depositor_interest;
+    pool_state.accrued_protocol_fees =
+        u64::try_from(pool_state.accrued_protocol_fees as u128 + protocol_interest)
+            .map_err(|_| ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+// Helper function to roll a loan's balance forward to the current borrow index
+// Late fees count toward total_borrowed, so they compound with the rest of the balance
+fn accrue_loan_interest(merchant_loan: &mut MerchantLoanAccount, borrow_index: u128) -> Result<()> {
+    let balance = merchant_loan
+        .principal
+        .saturating_add(merchant_loan.accrued_interest)
+        .saturating_add(merchant_loan.late_fees) as u128;
+
+    if balance > 0 && merchant_loan.borrow_index > 0 && borrow_index > merchant_loan.borrow_index {
+        let new_balance = balance
+            .checked_mul(borrow_index)
+            .ok_or(ErrorCode::MathOverflow)?
+            / merchant_loan.borrow_index;
+        let interest = u64::try_from(new_balance - balance).map_err(|_| ErrorCode::MathOverflow)?;
+        merchant_loan.accrued_interest = merchant_loan.accrued_interest.saturating_add(interest);
+    }
+
+    merchant_loan.borrow_index = borrow_index;
+
+    Ok(())
+}
+
+// Helper function to convert an underlying amount into pool shares
+fn amount_to_shares(amount: u64, supply_index: u128, round_up: bool) -> Result<u64> {
+    let scaled = (amount as u128)
+        .checked_mul(INDEX_SCALE)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(supply_index > 0, ErrorCode::PoolInsolvent);
+    let shares = if round_up {
+        scaled.div_ceil(supply_index)
+    } else {
+        scaled / supply_index
+    };
+
+    u64::try_from(shares).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+// Helper function to convert pool shares into their underlying amount
+fn shares_to_amount(shares: u64, supply_index: u128) -> Result<u64> {
+    let amount = (shares as u128)
+        .checked_mul(supply_index)
+        .ok_or(ErrorCode::MathOverflow)?
+        / INDEX_SCALE;
+
+    u64::try_from(amount).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+// Helper function to enforce the pool's liquidity policy once funds leave the vault
+// Takes the vault balance after the outgoing transfer
+fn check_liquidity(pool_state: &PoolState, vault_balance: u64) -> Result<()> {
+    let liquidity_policy = &pool_state.liquidity_policy;
+    require!(
+        calculate_deployment_rate(pool_state) <= liquidity_policy.max_utilization_bps as u64,
+        ErrorCode::MaxUtilizationExceeded
+    );
+
+    let min_liquidity = pool_state.total_deposited as u128
+        * liquidity_policy.min_liquidity_bps as u128
+        / BASIS_POINTS;
+    require!(
+        vault_balance as u128 >= min_liquidity,
+        ErrorCode::MinLiquidityBreached
+    );
+
+    Ok(())
+}
+
+// Helper function to find how much can leave the vault without breaking the liquidity policy
+fn calculate_available_liquidity(pool_state: &PoolState, vault_balance: u64) -> u64 {
+    let liquidity_policy = &pool_state.liquidity_policy;
+    let total_deposited = pool_state.total_deposited as u128;
+
+    // Deposits must stay large enough to keep loans and strategy allocations under the cap
+    let deployed = pool_state.total_borrowed as u128 + pool_state.total_allocated as u128;
+    let min_deposits =
+        (deployed * BASIS_POINTS).div_ceil(liquidity_policy.max_utilization_bps as u128);
+    let utilization_room = total_deposited.saturating_sub(min_deposits);
+
+    // The vault must keep its minimum share of whatever deposits remain
+    let min_liquidity_bps = liquidity_policy.min_liquidity_bps as u128;
+    let liquidity_room = if min_liquidity_bps >= BASIS_POINTS {
+        0
+    } else {
+        (vault_balance as u128 * BASIS_POINTS).saturating_sub(total_deposited * min_liquidity_bps)
+            / (BASIS_POINTS - min_liquidity_bps)
+    };
+
+    std::cmp::min(
+        std::cmp::min(utilization_room, liquidity_room),
+        vault_balance as u128,
+    ) as u64
+}
+
+// Helper function to calculate utilization rate (in basis points)
+fn calculate_utilization_rate(pool_state: &PoolState) -> u64 {
+    if pool_state.total_deposited == 0 {
+        return 0;
+    }
+
+    let utilization_rate =
+        (pool_state.total_borrowed as u128) * BASIS_POINTS / (pool_state.total_deposited as u128);
+
+    std::cmp::min(utilization_rate, BASIS_POINTS) as u64
+}
+
+// Helper function to calculate how much of the deposits is out on loan or in strategies (in basis points)
+// Only loans drive the borrow rate; the liquidity policy caps both together
+fn calculate_deployment_rate(pool_state: &PoolState) -> u64 {
+    if pool_state.total_deposited == 0 {
+        return 0;
+    }
+
+    let deployed = pool_state.total_borrowed as u128 + pool_state.total_allocated as u128;
+    let deployment_rate = deployed * BASIS_POINTS / (pool_state.total_deposited as u128);
+
+    std::cmp::min(deployment_rate, BASIS_POINTS) as u64
+}
+
+// Helper function to calculate borrow APR using a two-slope (kinked) model
+// Below the kink:  base_rate + utilization / optimal * slope
+// Above the kink:  base_rate + slope + (utilization - optimal) / (1 - optimal) * slope_2
+// All rates are in basis points (1/100 of 1%) and capped at max_borrow_rate
+fn calculate_borrow_apr(pool_state: &PoolState) -> u64 {
+    let rate_model = &pool_state.rate_model;
+    let utilization_rate = calculate_utilization_rate(pool_state) as u128;
+    let optimal_utilization = rate_model.optimal_utilization as u128;
+
+    let variable_rate = if utilization_rate <= optimal_utilization {
+        utilization_rate * rate_model.utilization_slope as u128 / optimal_utilization
+    } else {
+        let excess_utilization = utilization_rate - optimal_utilization;
+        let excess_range = BASIS_POINTS - optimal_utilization;
+        rate_model.utilization_slope as u128
+            + excess_utilization * rate_model.utilization_slope_2 as u128 / excess_range
+    };
+
+    let borrow_apr = (rate_model.base_rate as u128).saturating_add(variable_rate);
+
+    std::cmp::min(borrow_apr, rate_model.max_borrow_rate as u128) as u64
+}
+
+// Helper function to record merchant activity against their trust score in the registry
+// The pool's trust score authority PDA signs as the registry's score authority
+fn record_trust_event<'info>(
+    merchant_registry_program: AccountInfo<'info>,
+    registry_state: AccountInfo<'info>,
+    trust_score: AccountInfo<'info>,
+    trust_score_authority: AccountInfo<'info>,
+    bump: u8,
+    event: TrustEvent,
+) -> Result<()> {
+    let seeds = &[b"trust_score_authority".as_ref(), &[bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        merchant_registry_program,
+        RecordTrustEvent {
+            registry_state,
+            trust_score,
+            score_authority: trust_score_authority,
+        },
+        signer_seeds,
+    );
+
+    merchant_registry::cpi::record_trust_event(cpi_ctx, event)
+}
+
+// Helper function to check whether a loan still has a balance to collect
+fn is_loan_outstanding(merchant_loan: &MerchantLoanAccount) -> bool {
+    matches!(
+        merchant_loan.status,
+        LoanStatus::Active | LoanStatus::Delinquent | LoanStatus::Defaulted
+    )
+}
+
+// Helper function to check if a loan status counts as behind on payments
+fn is_loan_past_due(status: LoanStatus) -> bool {
+    matches!(status, LoanStatus::Delinquent | LoanStatus::Defaulted)
+}
+
+// Helper function to get outstanding loan amount
+// Expects the loan to have been rolled forward with accrue_loan_interest
+fn get_outstanding_loan_amount(merchant_loan: &MerchantLoanAccount) -> u64 {
+    if !is_loan_outstanding(merchant_loan) {
+        return 0;
+    }
+
+    merchant_loan
+        .principal
+        .saturating_add(merchant_loan.accrued_interest)
+        .saturating_add(merchant_loan.late_fees)
+}
+
+// Helper function to count whole days since the earliest unpaid installment fell due
+fn calculate_days_past_due(merchant_loan: &MerchantLoanAccount, current_timestamp: i64) -> u16 {
+    if current_timestamp <= merchant_loan.next_due_date {
+        return 0;
+    }
+
+    let days = (current_timestamp - merchant_loan.next_due_date) / SECONDS_PER_DAY;
+    std::cmp::min(days, u16::MAX as i64) as u16
+}
+
+// Helper function to get the installment due date following the given one
+fn next_installment_date(merchant_loan: &MerchantLoanAccount, due_date: i64) -> i64 {
+    std::cmp::min(
+        due_date + merchant_loan.installment_interval_days as i64 * SECONDS_PER_DAY,
+        merchant_loan.maturity_date,
+    )
+}
+
+// Helper function to get the principal the schedule allows to remain after a due date
+fn scheduled_principal_after(merchant_loan: &MerchantLoanAccount, due_date: i64) -> u64 {
+    if due_date >= merchant_loan.maturity_date {
+        return 0;
+    }
+
+    let interval = merchant_loan.installment_interval_days as i64 * SECONDS_PER_DAY;
+    let remaining_installments = (merchant_loan.maturity_date - due_date + interval - 1) / interval;
+    merchant_loan
+        .installment_amount
+        .saturating_mul(remaining_installments as u64)
+}
+
+// Helper function to check whether the installment at next_due_date has been met
+fn is_installment_paid(merchant_loan: &MerchantLoanAccount) -> bool {
+    merchant_loan.late_fees == 0
+        && merchant_loan.accrued_interest == 0
+        && merchant_loan.principal
+            <= scheduled_principal_after(merchant_loan, merchant_loan.next_due_date)
+}
+
+// Helper function to charge late fees for installments missed since the last check
+// Late fees are booked as pool income and split with the protocol like interest
+// Helper function to bring the pool and a loan up to date, charging any missed installments
+// The loan is rolled to the current borrow index first, so late fees only compound from when
+// they are charged, just as they do in total_borrowed
+fn bring_loan_current(
+    pool_state: &mut PoolState,
+    merchant_loan: &mut MerchantLoanAccount,
+    token_mint: Pubkey,
+    current_timestamp: i64,
+) -> Result<u64> {
+    accrue_interest(pool_state, current_timestamp)?;
+    accrue_loan_interest(merchant_loan, pool_state.borrow_index)?;
+    charge_late_fees(pool_state, merchant_loan, token_mint, current_timestamp)
+}
+
+fn charge_late_fees(
+    pool_state: &mut PoolState,
+    merchant_loan: &mut MerchantLoanAccount,
+    token_mint: Pubkey,
+    current_timestamp: i64,
+) -> Result<u64> {
+    let late_fee_bps = pool_state.loan_policy.late_fee_bps;
+    let mut missed_installments: u16 = 0;
+    let mut due_date = merchant_loan.next_due_date;
+
+    // Each missed due date is charged once, however long it stays unpaid
+    while due_date < current_timestamp {
+        if due_date > merchant_loan.last_late_fee_date {
+            merchant_loan.last_late_fee_date = due_date;
+            missed_installments = missed_installments.saturating_add(1);
+        }
+        if due_date >= merchant_loan.maturity_date {
+            break;
+        }
+        due_date = next_installment_date(merchant_loan, due_date);
+    }
+
+    let late_fee = (merchant_loan.installment_amount as u128
+        * late_fee_bps as u128
+        * missed_installments as u128
+        / BASIS_POINTS) as u64;
+    if late_fee == 0 {
+        return Ok(0);
+    }
+
+    merchant_loan.late_fees = merchant_loan.late_fees.saturating_add(late_fee);
+    pool_state.total_borrowed = pool_state.total_borrowed.saturating_add(late_fee);
+
+    let protocol_fee = (late_fee as u128 * pool_state.protocol_fee_percent as u128 / 100) as u64;
+    pool_state.accrued_protocol_fees = pool_state
+        .accrued_protocol_fees
+        .saturating_add(protocol_fee);
+    let total_deposited = pool_state
+        .total_deposited
+        .saturating_add(late_fee - protocol_fee);
+    rebase_total_deposited(pool_state, total_deposited)?;
+
+    emit!(LateFeeChargedEvent {
+        merchant: merchant_loan.merchant,
+        token_mint,
+        loan_index: merchant_loan.loan_index,
+        amount: late_fee,
+        missed_installments,
+        total_late_fees: merchant_loan.late_fees,
+        due_date: merchant_loan.next_due_date,
+        timestamp: current_timestamp,
+    });
+
+    Ok(late_fee)
+}
+
+// Helper function to apply a payment to a loan: late fees, then interest, then principal
+// Returns the (late fees, interest, principal) split; the caller adjusts pool totals
+fn apply_loan_payment(
+    merchant_loan: &mut MerchantLoanAccount,
+    amount: u64,
+    current_timestamp: i64,
+) -> (u64, u64, u64) {
+    let late_fee_payment = std::cmp::min(amount, merchant_loan.late_fees);
+    let interest_payment = std::cmp::min(amount - late_fee_payment, merchant_loan.accrued_interest);
+    let principal_payment = std::cmp::min(
+        amount - late_fee_payment - interest_payment,
+        merchant_loan.principal,
+    );
+
+    merchant_loan.late_fees -= late_fee_payment;
+    merchant_loan.accrued_interest -= interest_payment;
+    merchant_loan.principal -= principal_payment;
+    merchant_loan.last_repayment_date = current_timestamp;
+
+    if merchant_loan.principal == 0
+        && merchant_loan.accrued_interest == 0
+        && merchant_loan.late_fees == 0
+    {
+        // If loan fully repaid, update status
+        merchant_loan.status = LoanStatus::Repaid;
+        merchant_loan.days_past_due = 0;
+        return (late_fee_payment, interest_payment, principal_payment);
+    }
+
+    // Move past every installment this payment satisfied, including ones paid ahead
+    while merchant_loan.next_due_date < merchant_loan.maturity_date
+        && is_installment_paid(merchant_loan)
+    {
+        merchant_loan.next_due_date =
+            next_installment_date(merchant_loan, merchant_loan.next_due_date);
+    }
+
+    // Catching up on every missed installment brings the loan current again
+    merchant_loan.days_past_due = calculate_days_past_due(merchant_loan, current_timestamp);
+    if merchant_loan.days_past_due == 0 {
+        merchant_loan.status = LoanStatus::Active;
+    }
+
+    (late_fee_payment, interest_payment, principal_payment)
+}
+
+// Breakdown of a loan payment once it has been applied to the loan and pool
+struct LoanPayment {
+    amount: u64,
+    to_late_fees: u64,
+    to_interest: u64,
+    to_principal: u64,
+    to_reserve: u64, // Part of the payment owed to the reserve vault
+}
+
+// Helper function to apply a merchant's payment to their loan and the pool totals
+// Caps the payment at the outstanding balance; the caller moves the tokens
+fn settle_loan_payment(
+    pool_state: &mut PoolState,
+    merchant_loan: &mut MerchantLoanAccount,
+    merchant_credit: &mut MerchantCreditAccount,
+    amount: u64,
+    token_mint: Pubkey,
+    current_timestamp: i64,
+) -> Result<LoanPayment> {
+    // Bring the pool and loan up to date before applying the payment
+    bring_loan_current(pool_state, merchant_loan, token_mint, current_timestamp)?;
+
+    let total_outstanding = get_outstanding_loan_amount(merchant_loan);
+    let repayment_amount = std::cmp::min(amount, total_outstanding);
+    if repayment_amount == 0 {
+        return Ok(LoanPayment {
+            amount: 0,
+            to_late_fees: 0,
+            to_interest: 0,
+            to_principal: 0,
+            to_reserve: 0,
+        });
+    }
+
+    // Apply repayment to late fees and interest first, then principal
+    let previous_status = merchant_loan.status;
+    let (late_fee_payment, interest_payment, principal_payment) =
+        apply_loan_payment(merchant_loan, repayment_amount, current_timestamp);
+    update_merchant_credit(
+        merchant_credit,
+        merchant_loan,
+        previous_status,
+        principal_payment,
+    );
+    emit_loan_status_change(
+        merchant_loan,
+        previous_status,
+        token_mint,
+        current_timestamp,
+    );
+
+    // Accrued interest is already counted in total borrowed, so both parts reduce it
+    pool_state.total_borrowed = pool_state.total_borrowed.saturating_sub(repayment_amount);
+
+    // Route the reserve's cut of the interest and late fees out of the protocol's share
+    let reserve_amount = std::cmp::min(
+        ((interest_payment as u128 + late_fee_payment as u128)
+            * pool_state.reserve_fee_percent as u128
+            / 100) as u64,
+        pool_state.accrued_protocol_fees,
+    );
+    pool_state.accrued_protocol_fees = pool_state
+        .accrued_protocol_fees
+        .saturating_sub(reserve_amount);
+
+    Ok(LoanPayment {
+        amount: repayment_amount,
+        to_late_fees: late_fee_payment,
+        to_interest: interest_payment,
+        to_principal: principal_payment,
+        to_reserve: reserve_amount,
+    })
+}
+
+// Helper function to keep a merchant's exposure in step with one of their loans
+// Called after a loan's principal or status changes
+fn update_merchant_credit(
+    merchant_credit: &mut MerchantCreditAccount,
+    merchant_loan: &MerchantLoanAccount,
+    previous_status: LoanStatus,
+    principal_reduction: u64,
+) {
+    merchant_credit.outstanding_principal = merchant_credit
+        .outstanding_principal
+        .saturating_sub(principal_reduction);
+
+    if merchant_loan.status == previous_status {
+        return;
+    }
+
+    let was_past_due = is_loan_past_due(previous_status);
+    let is_past_due = is_loan_past_due(merchant_loan.status);
+    if is_past_due && !was_past_due {
+        merchant_credit.loans_past_due = merchant_credit.loans_past_due.saturating_add(1);
+    } else if was_past_due && !is_past_due {
+        merchant_credit.loans_past_due = merchant_credit.loans_past_due.saturating_sub(1);
+    }
+
+    if !is_loan_outstanding(merchant_loan) {
+        merchant_credit.open_loans = merchant_credit.open_loans.saturating_sub(1);
+        if merchant_credit.sweep_loan == Some(merchant_loan.loan_index) {
+            merchant_credit.sweep_loan = None;
+        }
+    }
+    if merchant_loan.status == LoanStatus::WrittenOff {
+        merchant_credit.written_off_loans = merchant_credit.written_off_loans.saturating_add(1);
+    }
+}
+
+// Helper function to read a merchant's credit account, which doesn't exist before their first loan
+fn load_merchant_credit(account: &AccountInfo) -> Result<Option<MerchantCreditAccount>> {
+    if account.owner != &crate::ID || account.data_is_empty() {
+        return Ok(None);
+    }
+    let merchant_credit =
+        MerchantCreditAccount::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+    Ok(Some(merchant_credit))
+}
+
+// Helper function to write back a credit account read with load_merchant_credit
+fn store_merchant_credit(
+    account: &AccountInfo,
+    merchant_credit: &MerchantCreditAccount,
+) -> Result<()> {
+    let mut data = account.try_borrow_mut_data()?;
+    let mut writer: &mut [u8] = &mut data[..];
+    merchant_credit.try_serialize(&mut writer)
+}
+
+// Helper function to emit an event when a loan moves between statuses
+fn emit_loan_status_change(
+    merchant_loan: &MerchantLoanAccount,
+    previous_status: LoanStatus,
+    token_mint: Pubkey,
+    current_timestamp: i64,
+) {
+    if merchant_loan.status == previous_status {
+        return;
+    }
+
+    emit!(LoanStatusChangedEvent {
+        merchant: merchant_loan.merchant,
+        token_mint,
+        loan_index: merchant_loan.loan_index,
+        previous_status,
+        status: merchant_loan.status,
+        days_past_due: merchant_loan.days_past_due,
+        outstanding: merchant_loan
+            .principal
+            .saturating_add(merchant_loan.accrued_interest)
+            .saturating_add(merchant_loan.late_fees),
+        timestamp: current_timestamp,
+    });
+}
+
+// Helper function to change total deposits without minting or burning shares
+// Helper function to get the supply index ready for a deposit
+fn prepare_supply_index(pool_state: &mut PoolState) -> Result<()> {
+    // Shares left over after losses wiped out every deposit would take any new deposit with them
+    require!(
+        pool_state.total_deposited > 0 || pool_state.total_shares == 0,
+        ErrorCode::PoolInsolvent
+    );
+    // A pool emptied out entirely starts over at 1:1
+    if pool_state.total_deposited == 0 && pool_state.total_shares == 0 {
+        pool_state.supply_index = INDEX_SCALE;
+    }
+
+    Ok(())
+}
+
+// Helper function to take a defaulted loan off the books, returning the outstanding
+// balance, the protocol fee given back and the loss passed on to depositors
+fn socialize_loan_loss(
+    pool_state: &mut PoolState,
+    merchant_loan: &mut MerchantLoanAccount,
+) -> Result<(u64, u64, u64)> {
+    let outstanding = get_outstanding_loan_amount(merchant_loan);
+    pool_state.total_borrowed = pool_state.total_borrowed.saturating_sub(outstanding);
+
+    // The protocol gives back its fee on interest and late fees that will never be paid
+    let fee_reversal = std::cmp::min(
+        ((merchant_loan.accrued_interest as u128 + merchant_loan.late_fees as u128)
+            * pool_state.protocol_fee_percent as u128
+            / 100) as u64,
+        pool_state.accrued_protocol_fees,
+    );
+    pool_state.accrued_protocol_fees = pool_state
+        .accrued_protocol_fees
+        .saturating_sub(fee_reversal);
+
+    // Depositors absorb the rest through a lower supply index
+    let depositor_loss = std::cmp::min(
+        outstanding.saturating_sub(fee_reversal),
+        pool_state.total_deposited,
+    );
+    let total_deposited = pool_state.total_deposited - depositor_loss;
+    rebase_total_deposited(pool_state, total_deposited)?;
+
+    merchant_loan.written_off_amount = depositor_loss;
+    merchant_loan.principal = 0;
+    merchant_loan.accrued_interest = 0;
+    merchant_loan.late_fees = 0;
+    merchant_loan.status = LoanStatus::WrittenOff;
+
+    Ok((outstanding, fee_reversal, depositor_loss))
+}
+
+// Every share absorbs the gain or loss in proportion
+fn rebase_total_deposited(pool_state: &mut PoolState, total_deposited: u64) -> Result<()> {
+    if pool_state.total_shares > 0 && pool_state.total_deposited > 0 {
+        let supply_index = pool_state
+            .supply_index
+            .checked_mul(total_deposited as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / pool_state.total_deposited as u128;
+        pool_state.supply_index = supply_index.max(MIN_SUPPLY_INDEX);
+    }
+
+    pool_state.total_deposited = total_deposited;
+
+    Ok(())
+}
+
+// Helper function to mint LP receipt tokens for shares added to a wallet position
+fn mint_receipts<'info>(
+    pool_state: &Account<'info, PoolState>,
+    receipt_mint: &Account<'info, Mint>,
+    receipt_account: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    shares: u64,
+) -> Result<()> {
+    let seeds = &[
+        b"pool_state".as_ref(),
+        pool_state.token_mint.as_ref(),
+        &[pool_state.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let mint_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        MintTo {
+            mint: receipt_mint.to_account_info(),
+            to: receipt_account.to_account_info(),
+            authority: pool_state.to_account_info(),
+        },
+        signer_seeds,
+    );
+
+    token::mint_to(mint_ctx, shares)
+}
+
+// Helper function to burn the LP receipt tokens backing shares that leave the pool or a position
+fn burn_receipts<'info>(
+    receipt_mint: &Account<'info, Mint>,
+    receipt_account: &Account<'info, TokenAccount>,
+    owner: &Signer<'info>,
+    token_program: &Program<'info, Token>,
+    shares: u64,
+) -> Result<()> {
+    let burn_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        Burn {
+            mint: receipt_mint.to_account_info(),
+            from: receipt_account.to_account_info(),
+            authority: owner.to_account_info(),
+        },
+    );
+
+    token::burn(burn_ctx, shares)
+}
+
+// Helper function to pick out the receipt accounts a position's shares are backed by
+// NFT positions are represented by their NFT and carry no receipt tokens
+fn position_receipts<'a, 'info>(
+    user_deposit: &UserDepositAccount,
+    receipt_mint: Option<&'a Account<'info, Mint>>,
+    receipt_account: Option<&'a Account<'info, TokenAccount>>,
+) -> Result<Option<(&'a Account<'info, Mint>, &'a Account<'info, TokenAccount>)>> {
+    if user_deposit.position_mint != Pubkey::default() {
+        return Ok(None);
+    }
+
+    match (receipt_mint, receipt_account) {
+        (Some(receipt_mint), Some(receipt_account)) => Ok(Some((receipt_mint, receipt_account))),
+        _ => err!(ErrorCode::MissingReceiptAccount),
+    }
+}
+
+// Helper function to make a wallet position's receipt tokens the source of truth for its shares
+// Receipts are transferable, so shares whose receipts were sent away leave the position with their
+// share of the principal; returns the principal released
+fn reconcile_receipts<'info>(
+    user_deposit: &mut UserDepositAccount,
+    receipt_mint: Option<&Account<'info, Mint>>,
+    receipt_account: Option<&Account<'info, TokenAccount>>,
+) -> Result<u64> {
+    let receipt_account = match position_receipts(user_deposit, receipt_mint, receipt_account)? {
+        Some((receipt_mint, receipt_account)) => {
+            require!(
+                receipt_account.mint == receipt_mint.key(),
+                ErrorCode::InvalidReceiptMint
+            );
+            require!(
+                receipt_account.owner == user_deposit.user,
+                ErrorCode::UnauthorizedAccess
+            );
+            receipt_account
+        }
+        None => return Ok(0),
+    };
+    if receipt_account.amount >= user_deposit.shares {
+        return Ok(0);
+    }
+
+    let remaining_principal = (user_deposit.deposited_amount as u128
+        * receipt_account.amount as u128
+        / user_deposit.shares as u128) as u64;
+    let released = user_deposit.deposited_amount - remaining_principal;
+    user_deposit.shares = receipt_account.amount;
+    user_deposit.deposited_amount = remaining_principal;
+
+    Ok(released)
+}
+
+// Helper function to write a position's principal down to what its shares are worth after a loss
+// Returns the principal written off, which stops counting toward the depositor's cap
+fn write_down_principal(user_deposit: &mut UserDepositAccount, position_value: u64) -> u64 {
+    let written_down = user_deposit.deposited_amount.saturating_sub(position_value);
+    user_deposit.deposited_amount -= written_down;
+    written_down
+}
+
+// Helper function to move an NFT position's principal from the wallet it is charged to onto its holder
+// NFTs change hands outside the pool, so the charge catches up the next time the holder deposits
+// or anyone calls reattribute_position; until then withdrawals release the previous wallet's cap
+fn charge_position_to_holder(
+    position: &mut UserDepositAccount,
+    holder: Pubkey,
+    previous_depositor: Option<&mut DepositorAccount>,
+    depositor: &mut DepositorAccount,
+) -> Result<()> {
+    if position.user == holder || position.user == Pubkey::default() {
+        return Ok(());
+    }
+
+    let previous_depositor = previous_depositor.ok_or(ErrorCode::MissingDepositor)?;
+    require_keys_eq!(
+        previous_depositor.user,
+        position.user,
+        ErrorCode::InvalidDepositor
+    );
+    previous_depositor.deposited_amount = previous_depositor
+        .deposited_amount
+        .saturating_sub(position.deposited_amount);
+    depositor.deposited_amount = depositor
+        .deposited_amount
+        .saturating_add(position.deposited_amount);
+    position.user = holder;
+
+    Ok(())
+}
+
+// Helper function to check the signer controls a deposit position
+// Wallet positions belong to their depositor; NFT positions belong to whoever holds the NFT
+fn authorize_position(
+    user_deposit: &UserDepositAccount,
+    signer: Pubkey,
+    token_mint: Pubkey,
+    position_token_account: Option<&Account<TokenAccount>>,
+) -> Result<()> {
+    require!(
+        user_deposit.token_mint == token_mint,
+        ErrorCode::InvalidTokenMint
+    );
+
+    if user_deposit.position_mint == Pubkey::default() {
+        require!(user_deposit.user == signer, ErrorCode::UnauthorizedAccess);
+    } else {
+        let position_token_account =
+            position_token_account.ok_or(ErrorCode::MissingPositionToken)?;
+        require!(
+            position_token_account.mint == user_deposit.position_mint
+                && position_token_account.owner == signer
+                && position_token_account.amount == 1,
+            ErrorCode::UnauthorizedAccess
+        );
+    }
+
+    Ok(())
+}
+
+// Helper function to burn an NFT position's token and close its account once nothing is left to withdraw
+// Rounding dust left in the position goes back to the pool; returns the principal written off with it
+fn close_empty_position<'info>(
+    pool_state: &mut PoolState,
+    user_deposit: &mut Account<'info, UserDepositAccount>,
+    position_mint: Option<&Account<'info, Mint>>,
+    position_token_account: Option<&Account<'info, TokenAccount>>,
+    holder: &Signer<'info>,
+    token_program: &Program<'info, Token>,
+    current_timestamp: i64,
+) -> Result<u64> {
+    if user_deposit.position_mint == Pubkey::default()
+        || shares_to_amount(user_deposit.shares, pool_state.supply_index)? > 0
+    {
+        return Ok(0);
+    }
+
+    let position_mint = position_mint.ok_or(ErrorCode::MissingPositionToken)?;
+    let position_token_account = position_token_account.ok_or(ErrorCode::MissingPositionToken)?;
+    require!(
+        position_mint.key() == user_deposit.position_mint,
+        ErrorCode::InvalidPosition
+    );
+
+    let burn_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        Burn {
+            mint: position_mint.to_account_info(),
+            from: position_token_account.to_account_info(),
+            authority: holder.to_account_info(),
+        },
+    );
+
+    token::burn(burn_ctx, 1)?;
+    pool_state.total_shares = pool_state.total_shares.saturating_sub(user_deposit.shares);
+
+    emit!(PositionClosedEvent {
+        holder: holder.key(),
+        token_mint: user_deposit.token_mint,
+        position_mint: user_deposit.position_mint,
+        timestamp: current_timestamp,
+    });
+
+    let remaining_principal = user_deposit.deposited_amount;
+    user_deposit.close(holder.to_account_info())?;
+    Ok(remaining_principal)
+}
+
+// Helper function to split realized strategy yield between depositors and the protocol
+// Returns the protocol's share
+fn book_strategy_yield(pool_state: &mut PoolState, amount: u64) -> Result<u64> {
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    let protocol_fee = (amount as u128 * pool_state.protocol_fee_percent as u128 / 100) as u64;
+    let total_deposited = pool_state
+        .total_deposited
+        .checked_add(amount - protocol_fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    rebase_total_deposited(pool_state, total_deposited)?;
+    pool_state.accrued_protocol_fees = pool_state
+        .accrued_protocol_fees
+        .checked_add(protocol_fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(protocol_fee)
+}
+
+// Helper function to encode a strategy adapter instruction
+// Strategies are Anchor programs, so each instruction starts with its 8-byte discriminator
+fn strategy_instruction_data(name: &str, amount: Option<u64>) -> Vec<u8> {
+    let mut data = hash(format!("global:{}", name).as_bytes()).to_bytes()[..8].to_vec();
+    if let Some(amount) = amount {
+        data.extend_from_slice(&amount.to_le_bytes());
+    }
+    data
+}
+
+// Helper function to mark a strategy's position to the value it reports
+// `received` is what the strategy just paid out against `returned_principal` of its allocation.
+// A strategy counts toward depositors' funds at its reported value, capped at its principal so
+// profit is only booked once harvested. Gains first restore earlier write-downs.
+// Returns the protocol's share of any profit and the loss written off depositors
+fn mark_strategy_value(
+    pool_state: &mut PoolState,
+    strategy: &mut StrategyAccount,
+    received: u64,
+    returned_principal: u64,
+    value: u64,
+) -> Result<(u64, u64)> {
+    let written_down = strategy.allocated.saturating_sub(strategy.marked_value);
+    strategy.allocated = strategy.allocated.saturating_sub(returned_principal);
+    pool_state.total_allocated = pool_state
+        .total_allocated
+        .saturating_sub(returned_principal);
+
+    let previous_value = strategy.marked_value;
+    strategy.marked_value = value.min(strategy.allocated);
+    let current_value = received.saturating_add(strategy.marked_value);
+
+    if current_value < previous_value {
+        let loss = previous_value - current_value;
+        let total_deposited = pool_state.total_deposited.saturating_sub(loss);
+        rebase_total_deposited(pool_state, total_deposited)?;
+        return Ok((0, loss));
+    }
+
+    let gain = current_value - previous_value;
+    let recovered = gain.min(written_down);
+    if recovered > 0 {
+        let total_deposited = pool_state
+            .total_deposited
+            .checked_add(recovered)
+            .ok_or(ErrorCode::MathOverflow)?;
+        rebase_total_deposited(pool_state, total_deposited)?;
+    }
+    let protocol_fee = book_strategy_yield(pool_state, gain - recovered)?;
+
+    Ok((protocol_fee, 0))
+}
+
+// Helper function to call a strategy adapter instruction with the strategy authority as signer
+// Every adapter takes the strategy authority, its token account and the token program first,
+// followed by the strategy's own accounts. The pool state never signs for a strategy, so a
+// strategy can only reach the funds staged in its token account.
+fn invoke_strategy<'info>(
+    accounts: &ManageStrategy<'info>,
+    strategy_accounts: &[AccountInfo<'info>],
+    data: Vec<u8>,
+) -> Result<()> {
+    let strategy_authority = accounts.strategy_authority.to_account_info();
+    let strategy_token_account = accounts.strategy_token_account.to_account_info();
+    let token_program = accounts.token_program.to_account_info();
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(strategy_authority.key(), true),
+        AccountMeta::new(strategy_token_account.key(), false),
+        AccountMeta::new_readonly(token_program.key(), false),
+    ];
+    let mut account_infos = vec![strategy_authority, strategy_token_account, token_program];
+    for account in strategy_accounts {
+        metas.push(AccountMeta {
+            pubkey: account.key(),
+            is_signer: account.is_signer,
+            is_writable: account.is_writable,
+        });
+        account_infos.push(account.clone());
+    }
+    account_infos.push(accounts.strategy_program.to_account_info());
+
+    let instruction = Instruction {
+        program_id: accounts.strategy_program.key(),
+        accounts: metas,
+        data,
+    };
+    let strategy_key = accounts.strategy.key();
+    let seeds = &[
+        b"strategy_authority".as_ref(),
+        strategy_key.as_ref(),
+        &[accounts.strategy.authority_bump],
+    ];
+    invoke_signed(&instruction, &account_infos, &[&seeds[..]])?;
+
+    Ok(())
+}
+
+// Helper function to ask a strategy what its position is worth
+// The adapter's report_value instruction returns the value as a little-endian u64
+fn read_strategy_value<'info>(
+    accounts: &ManageStrategy<'info>,
+    strategy_accounts: &[AccountInfo<'info>],
+) -> Result<u64> {
+    invoke_strategy(
+        accounts,
+        strategy_accounts,
+        strategy_instruction_data("report_value", None),
+    )?;
+
+    let (program_id, data) = get_return_data().ok_or(ErrorCode::StrategyValueUnavailable)?;
+    require!(
+        program_id == accounts.strategy_program.key(),
+        ErrorCode::StrategyValueUnavailable
+    );
+    let value: [u8; 8] = data
+        .try_into()
+        .map_err(|_| error!(ErrorCode::StrategyValueUnavailable))?;
+
+    Ok(u64::from_le_bytes(value))
+}
+
+// Helper function to move everything a strategy paid out into the pool vault
+// Returns the amount moved
+fn sweep_strategy_token_account(accounts: &mut ManageStrategy) -> Result<u64> {
+    accounts.strategy_token_account.reload()?;
+    let amount = accounts.strategy_token_account.amount;
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    let strategy_key = accounts.strategy.key();
+    let seeds = &[
+        b"strategy_authority".as_ref(),
+        strategy_key.as_ref(),
+        &[accounts.strategy.authority_bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        accounts.token_program.to_account_info(),
+        Transfer {
+            from: accounts.strategy_token_account.to_account_info(),
+            to: accounts.pool_vault.to_account_info(),
+            authority: accounts.strategy_authority.to_account_info(),
+        },
+        signer_seeds,
+    );
+
+    token::transfer(transfer_ctx, amount)?;
+
+    Ok(amount)
+}
+
+// Helper function to calculate borrow limit
+// Revenue only comes from subscription sales, which the payer can't cycle back to the merchant
+fn calculate_borrow_limit(trust_score: u8, monthly_revenue: u64) -> u64 {
+    // Trust score 1: can borrow 10% of their monthly revenue
+    // Trust score 5: can borrow 50% of their monthly revenue
+    let trust_percent = trust_score as u128 * 10;
+    (monthly_revenue as u128 * trust_percent / 100) as u64
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + PoolState::SPACE,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"pool_vault", token_mint.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_state,
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"reserve_vault", token_mint.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_state,
+    )]
+    pub reserve_vault: Account<'info, TokenAccount>,
+    // LP receipt token, one per pool share
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"receipt_mint", token_mint.key().as_ref()],
+        bump,
+        mint::decimals = token_mint.decimals,
+        mint::authority = pool_state,
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+    // Token account that receives collected protocol fees
+    #[account(
+        constraint = treasury.mint == token_mint.key() @ ErrorCode::InvalidTokenMint
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateRateModel<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", pool_state.token_mint.as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLoanPolicy<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", pool_state.token_mint.as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLiquidityPolicy<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", pool_state.token_mint.as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDepositLimits<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", pool_state.token_mint.as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CollectProtocolFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault,
+        has_one = treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub treasury: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    // Wallet-keyed position; omitted when depositing into a position NFT
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserDepositAccount::SPACE,
+        seeds = [
+            b"user_deposit", 
+            user.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_deposit: Option<Account<'info, UserDepositAccount>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    // NFT-keyed position; a fresh mint keypair opens a new position
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserDepositAccount::SPACE,
+        seeds = [
+            b"position",
+            position_mint
+                .ok_or(error!(ErrorCode::MissingPositionToken))?
+                .key()
+                .as_ref()
+        ],
+        bump
+    )]
+    pub position: Option<Account<'info, UserDepositAccount>>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        mint::decimals = 0,
+        mint::authority = pool_state,
+    )]
+    pub position_mint: Option<Account<'info, Mint>>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = position_mint,
+        associated_token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+    // Principal counts against the depositing wallet
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + DepositorAccount::SPACE,
+        seeds = [b"depositor", user.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, DepositorAccount>,
+    // Required when an NFT position is still charged to a previous holder
+    #[account(
+        mut,
+        seeds = [
+            b"depositor",
+            previous_depositor.user.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub previous_depositor: Option<Account<'info, DepositorAccount>>,
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault
+    )]
+    pub pool_state: Account<'info, PoolState>,
+
+    // Receipt tokens are minted for wallet positions only
+    #[account(
+        mut,
+        address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Option<Account<'info, Mint>>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = user,
+    )]
+    pub user_receipt_account: Option<Account<'info, TokenAccount>>,
+
+    // Pause switches are recorded in the merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
+
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    // A wallet position or an NFT position, checked against the signer in the handler
+    #[account(mut)]
+    pub user_deposit: Account<'info, UserDepositAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"depositor",
+            user_deposit.user.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub depositor: Account<'info, DepositorAccount>,
+    // Required for NFT positions; the NFT is burned once the position is empty
+    #[account(mut)]
+    pub position_mint: Option<Account<'info, Mint>>,
+    #[account(mut)]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault
+    )]
+    pub pool_state: Account<'info, PoolState>,
+
+    // Required for wallet positions, whose receipt tokens are burned for the shares
+    #[account(
+        mut,
+        address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Option<Account<'info, Mint>>,
+    #[account(mut)]
+    pub user_receipt_account: Option<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CalculateEarned<'info> {
+    /// CHECK: The wallet, or NFT holder, the position is checked against in the handler
+    pub user_wallet: UncheckedAccount<'info>,
+    #[account(
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    // A wallet position or an NFT position
+    pub user_deposit: Account<'info, UserDepositAccount>,
+    // Required for NFT positions, holding the position NFT
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+    // Required for wallet positions, holding the receipts backing the position's shares
+    #[account(address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint)]
+    pub receipt_mint: Option<Account<'info, Mint>>,
+    pub user_receipt_account: Option<Account<'info, TokenAccount>>,
+    pub token_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawEarned<'info> {
+    // A wallet position or an NFT position, checked against the signer in the handler
+    #[account(mut)]
+    pub user_deposit: Account<'info, UserDepositAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"depositor",
+            user_deposit.user.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub depositor: Account<'info, DepositorAccount>,
+    // Required for NFT positions; the NFT is burned once the position is empty
+    #[account(mut)]
+    pub position_mint: Option<Account<'info, Mint>>,
+    #[account(mut)]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault
+    )]
+    pub pool_state: Account<'info, PoolState>,
 
-        Ok(deposit_apr)
-    }
+    // Required for wallet positions, whose receipt tokens are burned for the shares
+    #[account(
+        mut,
+        address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Option<Account<'info, Mint>>,
+    #[account(mut)]
+    pub user_receipt_account: Option<Account<'info, TokenAccount>>,
 
-    // Get the current borrow APR
-    pub fn get_borrow_apr(ctx: Context<GetInterestRate>) -> Result<u64> {
-        let pool_state = &ctx.accounts.pool_state;
-        let borrow_apr = calculate_borrow_apr(
-            pool_state.base_rate,
-            pool_state.utilization_slope,
-            pool_state.total_deposited,
-            pool_state.total_borrowed,
-        );
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
 
-        Ok(borrow_apr)
-    }
+    pub token_program: Program<'info, Token>,
 }
 
-// Helper function to calculate interest earned on deposits
-fn calculate_interest(
-    amount: u64,
-    last_calculation: i64,
-    current_timestamp: i64,
-    base_rate: u64,
-    utilization_slope: u64,
-    protocol_fee_percent: u8,
-    total_deposited: u64,
-    total_borrowed: u64,
-) -> u64 {
-    if amount == 0 || last_calculation >= current_timestamp {
-        return 0;
-    }
-
-    // Calculate time elapsed in seconds
-    let time_elapsed = (current_timestamp - last_calculation) as u64;
-
-    // Calculate borrow APR in basis points (1/100 of 1%)
-    let borrow_apr = calculate_borrow_apr(
-        base_rate,
-        utilization_slope,
-        total_deposited,
-        total_borrowed,
-    );
-
-    // Calculate utilization rate (in basis points)
-    let utilization_rate = if total_deposited == 0 {
-        0
-    } else {
-        ((total_borrowed as u128) * 10_000 / (total_deposited as u128)) as u64
-    };
+#[derive(Accounts)]
+pub struct ReattributePosition<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = position.token_mint == token_mint.key() @ ErrorCode::InvalidTokenMint,
+        constraint = position.position_mint != Pubkey::default() @ ErrorCode::InvalidPosition
+    )]
+    pub position: Account<'info, UserDepositAccount>,
+    #[account(token::mint = position.position_mint)]
+    pub position_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"depositor", position.user.as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub previous_depositor: Account<'info, DepositorAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + DepositorAccount::SPACE,
+        seeds = [
+            b"depositor",
+            position_token_account.owner.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub depositor: Account<'info, DepositorAccount>,
+    pub token_mint: Account<'info, Mint>,
+    pub system_program: Program<'info, System>,
+}
 
-    // deposit_apr = (borrow_apr * utilization) * (1 - protocol_fee_percent)
-    let deposit_apr = (borrow_apr as u128)
-        .saturating_mul(utilization_rate as u128)
-        .saturating_div(10_000)
-        .saturating_mul((100 - protocol_fee_percent as u128))
-        .saturating_div(100)
-        .try_into()
-        .unwrap_or(0);
+#[derive(Accounts)]
+pub struct RequestWithdrawal<'info> {
+    // Queued withdrawals are limited to the signer's wallet position
+    #[account(
+        mut,
+        seeds = [
+            b"user_deposit",
+            user.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump,
+        constraint = user_deposit.user == user.key() @ ErrorCode::UnauthorizedAccess
+    )]
+    pub user_deposit: Account<'info, UserDepositAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"depositor", user.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, DepositorAccount>,
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    // Read to check the pool can't pay the withdrawal instantly
+    #[account(address = pool_state.pool_vault @ ErrorCode::InvalidPoolVault)]
+    pub pool_vault: Account<'info, TokenAccount>,
 
-    // Convert APR to per-second rate
-    // deposit_apr is in basis points (1/100 of 1%), so we divide by 10000 * seconds_per_year
-    let seconds_per_year: u64 = 365 * 24 * 60 * 60;
-    let interest_rate_per_second = (deposit_apr as u128) / (10_000 * seconds_per_year as u128);
+    // Withdrawal request account, numbered by the pool's queue tail
+    #[account(
+        init,
+        payer = user,
+        space = 8 + WithdrawalRequest::SPACE,
+        seeds = [
+            b"withdrawal_request",
+            token_mint.key().as_ref(),
+            &pool_state.withdrawal_queue_tail.to_le_bytes()
+        ],
+        bump
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
 
-    // Calculate interest: principal * rate * time
-    let interest = (amount as u128)
-        .saturating_mul(interest_rate_per_second)
-        .saturating_mul(time_elapsed as u128)
-        .try_into()
-        .unwrap_or(0);
+    #[account(
+        mut,
+        address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub user_receipt_account: Account<'info, TokenAccount>,
 
-    interest
+    pub token_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
-// Helper function to calculate loan interest
-fn calculate_loan_interest(
-    principal: u64,
-    last_repayment: i64,
-    current_timestamp: i64,
-    base_rate: u64,
-    utilization_slope: u64,
-    total_deposited: u64,
-    total_borrowed: u64,
-) -> u64 {
-    if principal == 0 || last_repayment >= current_timestamp {
-        return 0;
-    }
-
-    // Calculate time elapsed in seconds
-    let time_elapsed = (current_timestamp - last_repayment) as u64;
-
-    // Calculate borrow APR in basis points (1/100 of 1%)
-    let borrow_apr = calculate_borrow_apr(
-        base_rate,
-        utilization_slope,
-        total_deposited,
-        total_borrowed,
-    );
+#[derive(Accounts)]
+pub struct CancelWithdrawalRequest<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"user_deposit",
+            user.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump,
+        constraint = user_deposit.user == user.key() @ ErrorCode::UnauthorizedAccess
+    )]
+    pub user_deposit: Account<'info, UserDepositAccount>,
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"depositor", user.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub depositor: Account<'info, DepositorAccount>,
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
 
-    // Convert APR to per-second rate
-    let seconds_per_year: u64 = 365 * 24 * 60 * 60;
-    let interest_rate_per_second = (borrow_apr as u128) / (10_000 * seconds_per_year as u128);
+    #[account(
+        mut,
+        seeds = [
+            b"withdrawal_request",
+            token_mint.key().as_ref(),
+            &withdrawal_request.request_id.to_le_bytes()
+        ],
+        bump,
+        constraint = withdrawal_request.user == user.key() @ ErrorCode::UnauthorizedAccess
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
 
-    // Calculate interest: principal * rate * time
-    let interest = (principal as u128)
-        .saturating_mul(interest_rate_per_second)
-        .saturating_mul(time_elapsed as u128)
-        .try_into()
-        .unwrap_or(0);
+    #[account(
+        mut,
+        address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub user_receipt_account: Account<'info, TokenAccount>,
 
-    interest
+    pub token_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
 }
 
-// Helper function to calculate borrow APR
-fn calculate_borrow_apr(
-    base_rate: u64,
-    utilization_slope: u64,
-    total_deposited: u64,
-    total_borrowed: u64,
-) -> u64 {
-    // Calculate utilization rate (in basis points)
-    let utilization_rate = if total_deposited == 0 {
-        0
-    } else {
-        ((total_borrowed as u128) * 10_000 / (total_deposited as u128)) as u64
-    };
+#[derive(Accounts)]
+pub struct ProcessWithdrawalQueue<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault
+    )]
+    pub pool_state: Account<'info, PoolState>,
 
-    // Calculate borrow APR: base_rate + (utilization_rate * slope)
-    // All rates are in basis points (1/100 of 1%)
-    base_rate.saturating_add(
-        (utilization_rate as u128)
-            .saturating_mul(utilization_slope as u128)
-            .saturating_div(10_000)
-            .try_into()
-            .unwrap_or(0),
-    )
-}
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
 
-// Helper function to calculate trust score (simplified for MVP)
-fn calculate_trust_score(merchant_account: &MerchantAccount) -> u8 {
-    // For MVP, we use a simplified trust score based on verification status
-    // In a real implementation, this would use merchant revenue history,
-    // repayment data, and other factors
-    if merchant_account.verified {
-        30 // Default 30% for verified merchants
-    } else {
-        0
-    }
+    pub token_program: Program<'info, Token>,
 }
 
-// Helper function to get outstanding loan amount
-fn get_outstanding_loan_amount(merchant_loan: &Account<MerchantLoanAccount>) -> u64 {
-    // Logic for handling a regular Account
-    if merchant_loan.status != LoanStatus::Active {
-        return 0;
-    }
-
-    // Calculate interest
-    let current_timestamp = Clock::get().unwrap().unix_timestamp;
-    let principal = merchant_loan.principal;
-    let last_repayment = merchant_loan.last_repayment_date;
-
-    // Simple interest calculation for demo
-    // In a real implementation, we would use a more complex formula
-    let days_elapsed = ((current_timestamp - last_repayment) / (24 * 60 * 60)) as u64;
-    let interest_rate = 5; // 5% annual interest rate
-    let interest = principal * interest_rate * days_elapsed / (365 * 100);
-
-    principal + interest
-}
+#[derive(Accounts)]
+pub struct RedeemReceipts<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault,
+        has_one = receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
 
-// Add a separate function for handling optional merchant loan accounts
-fn get_outstanding_loan_amount_option(merchant_loan: &Option<Account<MerchantLoanAccount>>) -> u64 {
-    match merchant_loan {
-        Some(loan) => get_outstanding_loan_amount(loan),
-        None => 0,
-    }
-}
+    #[account(mut)]
+    pub receipt_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
 
-// Add a helper function to calculate borrow limit
-fn calculate_borrow_limit(trust_score: u8, amount: u64) -> u64 {
-    // Simple formula: trust_score * amount / 10
-    // Trust score 1: can borrow 10% of their revenue
-    // Trust score 5: can borrow 50% of their revenue
-    let trust_percent = trust_score as u64 * 10;
-    (amount * trust_percent) / 100
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct ApproveStrategy<'info> {
+    #[account(
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
     #[account(
         init,
-        payer = payer,
-        space = 8 + PoolState::SPACE,
-        seeds = [b"pool_state"],
+        payer = authority,
+        space = 8 + StrategyAccount::SPACE,
+        seeds = [
+            b"strategy",
+            token_mint.key().as_ref(),
+            strategy_program.key().as_ref()
+        ],
         bump
     )]
-    pub pool_state: Account<'info, PoolState>,
+    pub strategy: Account<'info, StrategyAccount>,
+    /// CHECK: Only ever invoked through the strategy adapter interface
+    #[account(executable)]
+    pub strategy_program: UncheckedAccount<'info>,
+    /// CHECK: Signs strategy calls in place of the pool state; holds no data
+    #[account(seeds = [b"strategy_authority", strategy.key().as_ref()], bump)]
+    pub strategy_authority: UncheckedAccount<'info>,
+    // The only token account the strategy authority controls
     #[account(
         init,
-        payer = payer,
-        seeds = [b"pool_vault", token_mint.key().as_ref()],
+        payer = authority,
+        seeds = [b"strategy_token_account", strategy.key().as_ref()],
         bump,
         token::mint = token_mint,
-        token::authority = pool_state,
+        token::authority = strategy_authority,
     )]
-    pub pool_vault: Account<'info, TokenAccount>,
+    pub strategy_token_account: Account<'info, TokenAccount>,
     #[account(mut)]
-    pub payer: Signer<'info>,
+    pub authority: Signer<'info>,
     pub token_mint: Account<'info, Mint>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct Deposit<'info> {
+pub struct UpdateStrategy<'info> {
     #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + UserDepositAccount::SPACE,
+        seeds = [b"pool_state", pool_state.token_mint.as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    #[account(
+        mut,
         seeds = [
-            b"user_deposit", 
-            user.key().as_ref(),
-            token_mint.key().as_ref()
+            b"strategy",
+            pool_state.token_mint.as_ref(),
+            strategy.strategy_program.as_ref()
         ],
-        bump
+        bump = strategy.bump
     )]
-    pub user_deposit: Account<'info, UserDepositAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
+    pub strategy: Account<'info, StrategyAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageStrategy<'info> {
     #[account(
         mut,
-        seeds = [b"pool_state"],
-        bump = pool_state.bump
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault
     )]
     pub pool_state: Account<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [
+            b"strategy",
+            token_mint.key().as_ref(),
+            strategy_program.key().as_ref()
+        ],
+        bump = strategy.bump
+    )]
+    pub strategy: Account<'info, StrategyAccount>,
+    /// CHECK: Pinned by the strategy account's seeds
+    #[account(executable)]
+    pub strategy_program: UncheckedAccount<'info>,
+    /// CHECK: Signs strategy calls in place of the pool state; holds no data
+    #[account(
+        seeds = [b"strategy_authority", strategy.key().as_ref()],
+        bump = strategy.authority_bump
+    )]
+    pub strategy_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = strategy.strategy_token_account @ ErrorCode::InvalidStrategyTokenAccount
+    )]
+    pub strategy_token_account: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
 
     #[account(mut)]
     pub pool_vault: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
     pub token_mint: Account<'info, Mint>,
 
     pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
+pub struct PayViaPool<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+    /// CHECK: This is the merchant wallet
+    pub merchant_wallet: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault,
+        has_one = reserve_vault @ ErrorCode::InvalidReserveVault
+    )]
+    pub pool_state: Account<'info, PoolState>,
+
+    // User deposit account is optional because the user might not have a deposit
+    // Either a wallet position or an NFT position, checked against the signer in the handler
+    #[account(mut)]
+    pub user_deposit: Option<Account<'info, UserDepositAccount>>,
+    // Required when the payment spends principal, which no longer counts against the position's opener
+    #[account(
+        mut,
+        seeds = [
+            b"depositor",
+            user_deposit
+                .as_ref()
+                .ok_or(error!(ErrorCode::MissingDepositor))?
+                .user
+                .as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub depositor: Option<Account<'info, DepositorAccount>>,
+    // Required when paying from an NFT position
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+    // Required when paying from a wallet position, whose receipt tokens are burned for the shares
+    #[account(
+        mut,
+        address = pool_state.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Option<Account<'info, Mint>>,
+    #[account(mut)]
+    pub user_receipt_account: Option<Account<'info, TokenAccount>>,
+
+    // Merchant loan is optional because the merchant might not have borrowed
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_loan",
+            merchant_wallet.key().as_ref(),
+            token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
+        ],
+        bump
+    )]
+    pub merchant_loan: Option<Account<'info, MerchantLoanAccount>>,
+    // Always passed, even before the merchant's first loan, so an active sweep can't be skipped
+    /// CHECK: Address checked by seeds; read in the handler only once initialized
     #[account(
         mut,
         seeds = [
-            b"user_deposit", 
-            user.key().as_ref(),
+            b"merchant_credit",
+            merchant_wallet.key().as_ref(),
             token_mint.key().as_ref()
         ],
-        bump,
-        constraint = user_deposit.user == user.key() @ ErrorCode::UnauthorizedAccess
+        bump
     )]
-    pub user_deposit: Account<'info, UserDepositAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
+    pub merchant_credit: UncheckedAccount<'info>,
+
+    // Revenue and trust score bookkeeping; the merchant might not have set up either account
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
     #[account(
         mut,
-        seeds = [b"pool_state"],
-        bump = pool_state.bump
+        seeds = [b"trust-score", merchant_wallet.key().as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = trust_score.bump
     )]
-    pub pool_state: Account<'info, PoolState>,
+    pub trust_score: Option<Account<'info, TrustScore>>,
+    /// CHECK: PDA signer only, registered as the registry's score authority
+    #[account(seeds = [b"trust_score_authority"], bump)]
+    pub trust_score_authority: UncheckedAccount<'info>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
 
     #[account(mut)]
     pub pool_vault: Account<'info, TokenAccount>,
     #[account(mut)]
+    pub reserve_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
     pub user_token_account: Account<'info, TokenAccount>,
+    // Trust is credited to merchant_wallet, so the funds must land with it
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = merchant_wallet
+    )]
+    pub merchant_token_account: Account<'info, TokenAccount>,
     pub token_mint: Account<'info, Mint>,
 
     pub token_program: Program<'info, Token>,
@@ -718,41 +3853,34 @@ pub struct Withdraw<'info> {
 }
 
 #[derive(Accounts)]
-pub struct PayViaPool<'info> {
-    #[account(mut)]
-    pub user_wallet: Signer<'info>,
-    /// CHECK: This is the merchant wallet
-    pub merchant_wallet: UncheckedAccount<'info>,
+pub struct SetRepaymentSweep<'info> {
+    pub merchant_wallet: Signer<'info>,
 
     #[account(
         mut,
-        seeds = [b"pool_state"],
-        bump = pool_state.bump
+        seeds = [
+            b"merchant_loan",
+            merchant_wallet.key().as_ref(),
+            token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
+        ],
+        bump,
+        constraint = merchant_loan.merchant == merchant_wallet.key() @ ErrorCode::UnauthorizedAccess
     )]
-    pub pool_state: Account<'info, PoolState>,
+    pub merchant_loan: Account<'info, MerchantLoanAccount>,
 
-    // User deposit account is optional because the user might not have a deposit
     #[account(
         mut,
         seeds = [
-            b"user_deposit", 
-            user_wallet.key().as_ref(),
+            b"merchant_credit",
+            merchant_wallet.key().as_ref(),
             token_mint.key().as_ref()
         ],
-        bump,
+        bump
     )]
-    pub user_deposit: Option<Account<'info, UserDepositAccount>>,
+    pub merchant_credit: Account<'info, MerchantCreditAccount>,
 
-    #[account(mut)]
-    pub pool_vault: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub merchant_token_account: Account<'info, TokenAccount>,
     pub token_mint: Account<'info, Mint>,
-
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -762,24 +3890,65 @@ pub struct Borrow<'info> {
 
     #[account(
         mut,
-        seeds = [b"pool_state"],
-        bump = pool_state.bump
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault,
+        has_one = reserve_vault @ ErrorCode::InvalidReserveVault
     )]
     pub pool_state: Account<'info, PoolState>,
 
-    // Merchant verification via merchant registry
+    // Merchant verification, trust score and pause switches via merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    #[account(
+        seeds = [b"merchant", merchant_wallet.key().as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = merchant_account.bump
+    )]
     pub merchant_account: Account<'info, MerchantAccount>,
+    #[account(
+        seeds = [b"trust-score", merchant_wallet.key().as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = trust_score.bump
+    )]
+    pub trust_score: Account<'info, TrustScore>,
+    #[account(
+        seeds = [b"revenue-ledger", merchant_wallet.key().as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = revenue_ledger.bump
+    )]
+    pub revenue_ledger: Account<'info, RevenueLedger>,
     pub merchant_registry_program: Program<'info, MerchantRegistry>,
 
-    // Merchant loan account
+    // Merchant's exposure across all of their loans in this pool
     #[account(
         init_if_needed,
         payer = merchant_wallet,
+        space = 8 + MerchantCreditAccount::SPACE,
+        seeds = [
+            b"merchant_credit",
+            merchant_wallet.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub merchant_credit: Account<'info, MerchantCreditAccount>,
+
+    // Merchant loan account, numbered by the merchant's loan counter
+    #[account(
+        init,
+        payer = merchant_wallet,
         space = 8 + MerchantLoanAccount::SPACE,
         seeds = [
             b"merchant_loan", 
             merchant_wallet.key().as_ref(),
-            token_mint.key().as_ref()
+            token_mint.key().as_ref(),
+            &merchant_credit.loan_count.to_le_bytes()
         ],
         bump
     )]
@@ -788,6 +3957,8 @@ pub struct Borrow<'info> {
     #[account(mut)]
     pub pool_vault: Account<'info, TokenAccount>,
     #[account(mut)]
+    pub reserve_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
     pub merchant_token_account: Account<'info, TokenAccount>,
     pub token_mint: Account<'info, Mint>,
 
@@ -802,8 +3973,11 @@ pub struct RepayLoan<'info> {
 
     #[account(
         mut,
-        seeds = [b"pool_state"],
-        bump = pool_state.bump
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault,
+        has_one = reserve_vault @ ErrorCode::InvalidReserveVault
     )]
     pub pool_state: Account<'info, PoolState>,
 
@@ -812,93 +3986,578 @@ pub struct RepayLoan<'info> {
         seeds = [
             b"merchant_loan", 
             merchant_wallet.key().as_ref(),
-            token_mint.key().as_ref()
+            token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
         ],
         bump,
         constraint = merchant_loan.merchant == merchant_wallet.key() @ ErrorCode::UnauthorizedAccess
     )]
     pub merchant_loan: Account<'info, MerchantLoanAccount>,
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_credit",
+            merchant_wallet.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub merchant_credit: Account<'info, MerchantCreditAccount>,
+
+    // Trust score bookkeeping in the merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    #[account(
+        mut,
+        seeds = [b"trust-score", merchant_wallet.key().as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = trust_score.bump
+    )]
+    pub trust_score: Account<'info, TrustScore>,
+    /// CHECK: PDA signer only, registered as the registry's score authority
+    #[account(seeds = [b"trust_score_authority"], bump)]
+    pub trust_score_authority: UncheckedAccount<'info>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
 
     #[account(mut)]
     pub pool_vault: Account<'info, TokenAccount>,
     #[account(mut)]
+    pub reserve_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
     pub merchant_token_account: Account<'info, TokenAccount>,
     pub token_mint: Account<'info, Mint>,
 
-    pub token_program: Program<'info, Token>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CoverLoanDefault<'info> {
+    pub governance: Signer<'info>,
+
+    // Protocol governance is recorded in the merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump,
+        has_one = governance @ ErrorCode::UnauthorizedAccess
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
+
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint,
+        has_one = pool_vault @ ErrorCode::InvalidPoolVault,
+        has_one = reserve_vault @ ErrorCode::InvalidReserveVault
+    )]
+    pub pool_state: Account<'info, PoolState>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_loan",
+            merchant_loan.merchant.as_ref(),
+            token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
+        ],
+        bump
+    )]
+    pub merchant_loan: Account<'info, MerchantLoanAccount>,
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_credit",
+            merchant_loan.merchant.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub merchant_credit: Account<'info, MerchantCreditAccount>,
+
+    #[account(mut)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub reserve_vault: Account<'info, TokenAccount>,
+    pub token_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MarkDelinquent<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_loan",
+            merchant_loan.merchant.as_ref(),
+            token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
+        ],
+        bump
+    )]
+    pub merchant_loan: Account<'info, MerchantLoanAccount>,
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_credit",
+            merchant_loan.merchant.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub merchant_credit: Account<'info, MerchantCreditAccount>,
+
+    // Trust score bookkeeping in the merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    #[account(
+        mut,
+        seeds = [b"trust-score", merchant_loan.merchant.as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = trust_score.bump
+    )]
+    pub trust_score: Account<'info, TrustScore>,
+    /// CHECK: PDA signer only, registered as the registry's score authority
+    #[account(seeds = [b"trust_score_authority"], bump)]
+    pub trust_score_authority: UncheckedAccount<'info>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
+
+    pub token_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct WriteOffLoan<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool_state", token_mint.key().as_ref()],
+        bump = pool_state.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAccess,
+        has_one = token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub pool_state: Account<'info, PoolState>,
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_loan",
+            merchant_loan.merchant.as_ref(),
+            token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
+        ],
+        bump
+    )]
+    pub merchant_loan: Account<'info, MerchantLoanAccount>,
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_credit",
+            merchant_loan.merchant.as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub merchant_credit: Account<'info, MerchantCreditAccount>,
+
+    pub token_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct GetInterestRate<'info> {
+    #[account(
+        seeds = [b"pool_state", pool_state.token_mint.as_ref()],
+        bump = pool_state.bump
+    )]
+    pub pool_state: Account<'info, PoolState>,
+}
+
+#[account]
+pub struct PoolState {
+    pub authority: Pubkey,
+    pub token_mint: Pubkey,    // Asset this pool holds
+    pub pool_vault: Pubkey,    // Token account holding the pool's liquidity
+    pub treasury: Pubkey,      // Token account receiving protocol fees
+    pub reserve_vault: Pubkey, // Token account absorbing merchant defaults
+    pub receipt_mint: Pubkey,  // LP receipt token minted one-for-one with wallet position shares
+    pub total_deposited: u64,  // Underlying owed to depositors, including accrued interest
+    pub total_borrowed: u64,   // Outstanding loans, including accrued interest
+    pub total_shares: u64,
+    pub supply_index: u128, // Underlying value of one share, scaled by INDEX_SCALE
+    pub borrow_index: u128, // Cumulative borrow interest index, scaled by INDEX_SCALE
+    pub last_accrual_timestamp: i64,
+    pub rate_model: RateModel,             // Two-slope borrow rate model
+    pub protocol_fee_percent: u8,          // Protocol fee percentage (0-100)
+    pub accrued_protocol_fees: u64,        // Protocol's share of interest not yet collected
+    pub reserve_fee_percent: u8,           // Share of repaid loan interest sent to the reserve
+    pub loan_policy: LoanPolicy,           // Repayment cadence and delinquency grace periods
+    pub liquidity_policy: LiquidityPolicy, // Utilization cap and liquid reserve floor
+    pub deposit_limits: DepositLimits,     // TVL cap and per-deposit bounds
+    pub withdrawal_queue_head: u64,        // Id of the oldest request not yet filled or skipped
+    pub withdrawal_queue_tail: u64,        // Id the next withdrawal request will get
+    pub pending_withdrawals: u64,          // Requests still waiting to be filled
+    pub queued_shares: u64,                // Shares locked in pending requests
+    pub total_allocated: u64,              // Principal deployed to yield strategies
+    pub bump: u8,
+}
+
+impl PoolState {
+    pub const SPACE: usize = 32 + // authority
+                           32 + // token_mint
+                           32 + // pool_vault
+                           32 + // treasury
+                           32 + // reserve_vault
+                           32 + // receipt_mint
+                           8 +  // total_deposited
+                           8 +  // total_borrowed
+                           8 +  // total_shares
+                           16 + // supply_index
+                           16 + // borrow_index
+                           8 +  // last_accrual_timestamp
+                           RateModel::SPACE + // rate_model
+                           1 +  // protocol_fee_percent
+                           8 +  // accrued_protocol_fees
+                           1 +  // reserve_fee_percent
+                           LoanPolicy::SPACE + // loan_policy
+                           LiquidityPolicy::SPACE + // liquidity_policy
+                           DepositLimits::SPACE + // deposit_limits
+                           8 +  // withdrawal_queue_head
+                           8 +  // withdrawal_queue_tail
+                           8 +  // pending_withdrawals
+                           8 +  // queued_shares
+                           8 +  // total_allocated
+                           1; // bump
+}
+
+// Borrow rate model parameters, all in basis points (1/100 of 1%)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateModel {
+    pub base_rate: u64,           // Borrow APR at 0% utilization
+    pub utilization_slope: u64,   // Rate added between 0% and optimal utilization
+    pub optimal_utilization: u64, // Kink point, as a share of total deposits
+    pub utilization_slope_2: u64, // Rate added between optimal and 100% utilization
+    pub max_borrow_rate: u64,     // Borrow APR cap
+}
+
+impl RateModel {
+    pub const SPACE: usize = 8 + // base_rate
+                           8 +  // utilization_slope
+                           8 +  // optimal_utilization
+                           8 +  // utilization_slope_2
+                           8; // max_borrow_rate
+
+    pub fn validate(&self) -> Result<()> {
+        // The kink must sit strictly inside (0%, 100%) so both slopes have a range
+        require!(
+            self.optimal_utilization > 0 && (self.optimal_utilization as u128) < BASIS_POINTS,
+            ErrorCode::InvalidRateModel
+        );
+        // The second slope is the penalty for running the pool hot
+        require!(
+            self.utilization_slope_2 >= self.utilization_slope,
+            ErrorCode::InvalidRateModel
+        );
+        require!(
+            self.max_borrow_rate <= MAX_BORROW_RATE_CAP && self.base_rate <= self.max_borrow_rate,
+            ErrorCode::InvalidRateModel
+        );
+
+        Ok(())
+    }
+}
+
+// Loan repayment cadence, how long a missed payment is tolerated, and the fees charged
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoanPolicy {
+    pub payment_interval_days: u16,  // Installments fall due this often
+    pub delinquency_grace_days: u16, // Days past due before a loan can be marked delinquent
+    pub default_grace_days: u16,     // Days past due before a loan can be marked defaulted
+    pub max_term_days: u16,          // Longest term a merchant can borrow for
+    pub late_fee_bps: u16,           // Charged on each missed installment, in basis points
+    pub origination_fee_bps: u16,    // Charged on each loan at issue, in basis points
+    pub origination_reserve_percent: u8, // Share of the origination fee sent to the reserve
+    pub origination_fee_mode: OriginationFeeMode,
+}
+
+// How the origination fee is collected from the merchant
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OriginationFeeMode {
+    #[default]
+    Deducted, // Withheld from the amount disbursed
+    Financed, // Added to the loan principal
+}
+
+// How much of the pool can be lent out and how much must stay liquid in the vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LiquidityPolicy {
+    pub max_utilization_bps: u16, // Highest share of deposits that can be lent or allocated to strategies
+    pub min_liquidity_bps: u16,   // Share of deposits the vault must keep on hand
+}
+
+impl LiquidityPolicy {
+    pub const SPACE: usize = 2 + // max_utilization_bps
+                           2; // min_liquidity_bps
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.max_utilization_bps > 0 && (self.max_utilization_bps as u128) <= BASIS_POINTS,
+            ErrorCode::InvalidLiquidityPolicy
+        );
+        require!(
+            (self.min_liquidity_bps as u128) <= BASIS_POINTS,
+            ErrorCode::InvalidLiquidityPolicy
+        );
+
+        Ok(())
+    }
+}
+
+// Limits on how much can be deposited, raised gradually as the pool grows
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DepositLimits {
+    pub total_deposit_cap: u64, // Most the pool can hold in deposits
+    pub max_user_deposit: u64,  // Most principal a single depositor can hold
+    pub min_deposit: u64,       // Smallest amount a single deposit can add
+}
+
+impl DepositLimits {
+    pub const SPACE: usize = 8 + // total_deposit_cap
+                           8 +  // max_user_deposit
+                           8; // min_deposit
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.min_deposit > 0 && self.min_deposit <= self.max_user_deposit,
+            ErrorCode::InvalidDepositLimits
+        );
+        require!(
+            self.max_user_deposit <= self.total_deposit_cap,
+            ErrorCode::InvalidDepositLimits
+        );
+
+        Ok(())
+    }
+}
+
+impl LoanPolicy {
+    pub const SPACE: usize = 2 + // payment_interval_days
+                           2 +  // delinquency_grace_days
+                           2 +  // default_grace_days
+                           2 +  // max_term_days
+                           2 +  // late_fee_bps
+                           2 +  // origination_fee_bps
+                           1 +  // origination_reserve_percent
+                           1; // origination_fee_mode (enum)
+
+    pub fn validate(&self) -> Result<()> {
+        require!(self.payment_interval_days > 0, ErrorCode::InvalidLoanPolicy);
+        // A loan has to be delinquent for a while before it can default
+        require!(
+            self.default_grace_days > self.delinquency_grace_days,
+            ErrorCode::InvalidLoanPolicy
+        );
+        require!(
+            self.max_term_days >= self.payment_interval_days,
+            ErrorCode::InvalidLoanPolicy
+        );
+        require!(
+            (self.late_fee_bps as u128) <= BASIS_POINTS,
+            ErrorCode::InvalidLoanPolicy
+        );
+        require!(
+            (self.origination_fee_bps as u128) <= BASIS_POINTS,
+            ErrorCode::InvalidLoanPolicy
+        );
+        require!(
+            self.origination_reserve_percent <= 100,
+            ErrorCode::InvalidLoanPolicy
+        );
+
+        Ok(())
+    }
 }
 
-#[derive(Accounts)]
-pub struct GetInterestRate<'info> {
-    #[account(
-        seeds = [b"pool_state"],
-        bump = pool_state.bump
-    )]
-    pub pool_state: Account<'info, PoolState>,
+#[account]
+pub struct UserDepositAccount {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub shares: u64,           // Pool shares owned by the user
+    pub deposited_amount: u64, // Principal; anything the shares are worth above this is earned
+    pub deposit_date: i64,
+    pub position_mint: Pubkey, // NFT that owns this position; default for wallet positions
+}
+
+impl UserDepositAccount {
+    pub const SPACE: usize = 32 + // user
+                           32 + // token_mint
+                           8 +  // shares
+                           8 +  // deposited_amount
+                           8 +  // deposit_date
+                           32; // position_mint
 }
 
+// Principal a wallet has in the pool across every position charged to it, checked against max_user_deposit
 #[account]
-pub struct PoolState {
-    pub authority: Pubkey,
-    pub total_deposited: u64,
-    pub total_borrowed: u64,
-    pub base_rate: u64,         // Base interest rate in basis points (1/100 of 1%)
-    pub utilization_slope: u64, // Utilization rate multiplier in basis points
-    pub protocol_fee_percent: u8, // Protocol fee percentage (0-100)
-    pub bump: u8,
+pub struct DepositorAccount {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub deposited_amount: u64, // Principal across the wallet position and the NFT positions charged to it
 }
 
-impl PoolState {
-    pub const SPACE: usize = 32 + // authority
-                           8 +  // total_deposited
-                           8 +  // total_borrowed
-                           8 +  // base_rate
-                           8 +  // utilization_slope
-                           1 +  // protocol_fee_percent
-                           1; // bump
+impl DepositorAccount {
+    pub const SPACE: usize = 32 + // user
+                           32 + // token_mint
+                           8; // deposited_amount
 }
 
 #[account]
-pub struct UserDepositAccount {
+pub struct WithdrawalRequest {
     pub user: Pubkey,
     pub token_mint: Pubkey,
-    pub deposited_amount: u64,
-    pub interest_earned: u64,
-    pub last_interest_calculation: i64,
-    pub deposit_date: i64,
+    pub request_id: u64, // Position in the pool's withdrawal queue, part of the PDA seeds
+    pub shares: u64,     // Shares still locked in the request
+    pub principal: u64,  // Principal the locked shares were taken from
+    pub filled_amount: u64, // Paid out to the user so far
+    pub requested_at: i64,
+    pub status: WithdrawalStatus,
 }
 
-impl UserDepositAccount {
+impl WithdrawalRequest {
     pub const SPACE: usize = 32 + // user
                            32 + // token_mint
-                           8 +  // deposited_amount
-                           8 +  // interest_earned
-                           8 +  // last_interest_calculation
-                           8; // deposit_date
+                           8 +  // request_id
+                           8 +  // shares
+                           8 +  // principal
+                           8 +  // filled_amount
+                           8 +  // requested_at
+                           1; // status (enum)
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WithdrawalStatus {
+    Pending,
+    Filled,
+    Cancelled,
 }
 
 #[account]
 pub struct MerchantLoanAccount {
     pub merchant: Pubkey,
+    pub loan_index: u64, // Position in the merchant's loan sequence, part of the PDA seeds
     pub principal: u64,
+    pub accrued_interest: u64, // Interest owed as of the borrow_index snapshot
+    pub borrow_index: u128,    // Pool borrow index when the loan was last rolled forward
     pub issue_date: i64,
     pub last_repayment_date: i64,
+    pub term_days: u16,
+    pub installment_interval_days: u16, // Copied from the loan policy at issue
+    pub installment_amount: u64,        // Principal due each installment
+    pub maturity_date: i64,             // Final installment is due at this time
+    pub next_due_date: i64,             // Due date of the earliest unpaid installment
+    pub late_fees: u64,                 // Late fees charged and not yet paid
+    pub last_late_fee_date: i64,        // Latest due date a late fee has been charged for
+    pub days_past_due: u16,             // As of the last status check
+    pub written_off_amount: u64,        // Depositor loss not yet restored by the reserve
+    pub sweep_percent: u8, // Share of incoming pay_via_pool payments applied to the loan
     pub status: LoanStatus,
 }
 
 impl MerchantLoanAccount {
     pub const SPACE: usize = 32 + // merchant
+                           8 +  // loan_index
                            8 +  // principal
+                           8 +  // accrued_interest
+                           16 + // borrow_index
                            8 +  // issue_date
                            8 +  // last_repayment_date
+                           2 +  // term_days
+                           2 +  // installment_interval_days
+                           8 +  // installment_amount
+                           8 +  // maturity_date
+                           8 +  // next_due_date
+                           8 +  // late_fees
+                           8 +  // last_late_fee_date
+                           2 +  // days_past_due
+                           8 +  // written_off_amount
+                           1 +  // sweep_percent
                            1; // status (enum)
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+#[account]
+pub struct MerchantCreditAccount {
+    pub merchant: Pubkey,
+    pub token_mint: Pubkey,
+    pub loan_count: u64,            // Loans ever issued; the next loan's index
+    pub open_loans: u16,            // Loans not yet repaid or written off
+    pub outstanding_principal: u64, // Principal owed across all open loans
+    pub loans_past_due: u16,        // Open loans that are delinquent or defaulted
+    pub written_off_loans: u16,
+    pub sweep_loan: Option<u64>, // Loan every pay_via_pool payment must carry for its sweep
+}
+
+impl MerchantCreditAccount {
+    pub const SPACE: usize = 32 + // merchant
+                           32 + // token_mint
+                           8 +  // loan_count
+                           2 +  // open_loans
+                           8 +  // outstanding_principal
+                           2 +  // loans_past_due
+                           2 +  // written_off_loans
+                           9; // sweep_loan
+}
+
+// An approved yield strategy for one pool, and the funds deployed to it
+#[account]
+pub struct StrategyAccount {
+    pub token_mint: Pubkey,
+    pub strategy_program: Pubkey, // Program implementing the strategy adapter interface
+    pub allocation_cap: u64,      // Most principal the pool may deploy to this strategy
+    pub allocated: u64,           // Principal currently deployed
+    pub marked_value: u64,        // What the deployed principal counts for in total_deposited
+    pub total_harvested: u64,     // Lifetime yield collected into the pool vault
+    pub is_active: bool,          // Retired strategies take no new allocations
+    pub strategy_token_account: Pubkey, // Where the strategy takes funds from and pays them out
+    pub authority_bump: u8,       // Bump of the PDA that signs strategy calls
+    pub bump: u8,
+}
+
+impl StrategyAccount {
+    pub const SPACE: usize = 32 + // token_mint
+                           32 + // strategy_program
+                           8 +  // allocation_cap
+                           8 +  // allocated
+                           8 +  // marked_value
+                           8 +  // total_harvested
+                           1 +  // is_active
+                           32 + // strategy_token_account
+                           1 +  // authority_bump
+                           1; // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LoanStatus {
     Active,
     Repaid,
     WrittenOff,
+    Delinquent,
+    Defaulted,
 }
 
 // Events
@@ -906,20 +4565,91 @@ pub enum LoanStatus {
 pub struct DepositEvent {
     pub user: Pubkey,
     pub token_mint: Pubkey,
+    pub position_mint: Option<Pubkey>,
     pub amount: u64,
+    pub shares: u64,
     pub total_deposited: u64,
     pub timestamp: i64,
 }
 
+#[event]
+pub struct PositionReattributedEvent {
+    pub position_mint: Pubkey,
+    pub token_mint: Pubkey,
+    pub previous_user: Pubkey,
+    pub user: Pubkey,
+    pub deposited_amount: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct WithdrawEvent {
     pub user: Pubkey,
     pub token_mint: Pubkey,
     pub amount: u64,
+    pub shares: u64,
     pub remaining_deposit: u64,
     pub timestamp: i64,
 }
 
+#[event]
+pub struct WithdrawEarnedEvent {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub remaining_earned: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptsRedeemedEvent {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub shares: u64,
+    pub amount: u64,
+    pub supply_index: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionClosedEvent {
+    pub holder: Pubkey,
+    pub token_mint: Pubkey,
+    pub position_mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalRequestedEvent {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub request_id: u64,
+    pub amount: u64,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalFilledEvent {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub request_id: u64,
+    pub amount: u64,
+    pub shares: u64,
+    pub remaining_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalCancelledEvent {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub request_id: u64,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct PaymentEvent {
     pub user: Pubkey,
@@ -929,6 +4659,8 @@ pub struct PaymentEvent {
     pub from_interest: u64,
     pub from_deposit: u64,
     pub from_wallet: u64,
+    pub to_merchant: u64,
+    pub to_loan: u64,
     pub timestamp: i64,
 }
 
@@ -936,9 +4668,20 @@ pub struct PaymentEvent {
 pub struct BorrowEvent {
     pub merchant: Pubkey,
     pub token_mint: Pubkey,
+    pub loan_index: u64,
     pub amount: u64,
+    pub principal: u64,
+    pub disbursed: u64,
+    pub origination_fee: u64,
+    pub fee_to_treasury: u64,
+    pub fee_to_reserve: u64,
+    pub trust_score: u8,
+    pub monthly_revenue: u64,
     pub borrow_limit: u64,
     pub current_outstanding: u64,
+    pub term_days: u16,
+    pub installment_amount: u64,
+    pub maturity_date: i64,
     pub timestamp: i64,
 }
 
@@ -946,10 +4689,174 @@ pub struct BorrowEvent {
 pub struct RepaymentEvent {
     pub merchant: Pubkey,
     pub token_mint: Pubkey,
+    pub loan_index: u64,
     pub amount: u64,
+    pub to_late_fees: u64,
     pub to_interest: u64,
     pub to_principal: u64,
+    pub to_reserve: u64,
     pub remaining_principal: u64,
+    pub next_due_date: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RateModelUpdatedEvent {
+    pub token_mint: Pubkey,
+    pub authority: Pubkey,
+    pub previous_rate_model: RateModel,
+    pub rate_model: RateModel,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProtocolFeesCollectedEvent {
+    pub token_mint: Pubkey,
+    pub treasury: Pubkey,
+    pub amount: u64,
+    pub remaining_fees: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReserveCoverageEvent {
+    pub merchant: Pubkey,
+    pub token_mint: Pubkey,
+    pub loan_index: u64,
+    pub amount: u64,
+    pub to_late_fees: u64,
+    pub to_interest: u64,
+    pub to_principal: u64,
+    pub remaining_outstanding: u64,
+    pub reserve_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LoanPolicyUpdatedEvent {
+    pub token_mint: Pubkey,
+    pub authority: Pubkey,
+    pub previous_loan_policy: LoanPolicy,
+    pub loan_policy: LoanPolicy,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidityPolicyUpdatedEvent {
+    pub token_mint: Pubkey,
+    pub authority: Pubkey,
+    pub previous_liquidity_policy: LiquidityPolicy,
+    pub liquidity_policy: LiquidityPolicy,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositLimitsUpdatedEvent {
+    pub token_mint: Pubkey,
+    pub authority: Pubkey,
+    pub previous_deposit_limits: DepositLimits,
+    pub deposit_limits: DepositLimits,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LoanStatusChangedEvent {
+    pub merchant: Pubkey,
+    pub token_mint: Pubkey,
+    pub loan_index: u64,
+    pub previous_status: LoanStatus,
+    pub status: LoanStatus,
+    pub days_past_due: u16,
+    pub outstanding: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RepaymentSweepUpdatedEvent {
+    pub merchant: Pubkey,
+    pub token_mint: Pubkey,
+    pub loan_index: u64,
+    pub previous_sweep_percent: u8,
+    pub sweep_percent: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LateFeeChargedEvent {
+    pub merchant: Pubkey,
+    pub token_mint: Pubkey,
+    pub loan_index: u64,
+    pub amount: u64,
+    pub missed_installments: u16,
+    pub total_late_fees: u64,
+    pub due_date: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LoanWrittenOffEvent {
+    pub merchant: Pubkey,
+    pub token_mint: Pubkey,
+    pub loan_index: u64,
+    pub amount: u64,
+    pub depositor_loss: u64,
+    pub protocol_fee_reversal: u64,
+    pub supply_index: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StrategyUpdatedEvent {
+    pub token_mint: Pubkey,
+    pub strategy_program: Pubkey,
+    pub authority: Pubkey,
+    pub allocation_cap: u64,
+    pub is_active: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StrategyAllocatedEvent {
+    pub token_mint: Pubkey,
+    pub strategy_program: Pubkey,
+    pub amount: u64,
+    pub allocated: u64,
+    pub total_allocated: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StrategyHarvestedEvent {
+    pub token_mint: Pubkey,
+    pub strategy_program: Pubkey,
+    pub amount: u64,
+    pub protocol_fee: u64,
+    pub total_harvested: u64,
+    pub supply_index: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StrategyUnwoundEvent {
+    pub token_mint: Pubkey,
+    pub strategy_program: Pubkey,
+    pub amount: u64,
+    pub received: u64,
+    pub loss: u64,
+    pub allocated: u64,
+    pub total_allocated: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StrategyMarkedEvent {
+    pub token_mint: Pubkey,
+    pub strategy_program: Pubkey,
+    pub value: u64,
+    pub marked_value: u64,
+    pub loss: u64,
+    pub total_deposited: u64,
+    pub supply_index: u128,
     pub timestamp: i64,
 }
 
@@ -961,6 +4868,8 @@ pub enum ErrorCode {
     InvalidAmount,
     #[msg("Insufficient funds")]
     InsufficientFunds,
+    #[msg("Insufficient earned interest")]
+    InsufficientEarnings,
     #[msg("Insufficient liquidity in pool")]
     InsufficientLiquidity,
     #[msg("Merchant is not verified")]
@@ -971,4 +4880,361 @@ pub enum ErrorCode {
     LoanNotActive,
     #[msg("Invalid fee percentage")]
     InvalidFeePercentage,
+    #[msg("Math overflow")]
+    MathOverflow,
+    #[msg("Token mint does not belong to this pool")]
+    InvalidTokenMint,
+    #[msg("Vault does not belong to this pool")]
+    InvalidPoolVault,
+    #[msg("Invalid interest rate model")]
+    InvalidRateModel,
+    #[msg("Treasury does not belong to this pool")]
+    InvalidTreasury,
+    #[msg("Insufficient accrued protocol fees")]
+    InsufficientProtocolFees,
+    #[msg("Reserve vault does not belong to this pool")]
+    InvalidReserveVault,
+    #[msg("Insufficient funds in reserve")]
+    InsufficientReserve,
+    #[msg("Invalid loan policy")]
+    InvalidLoanPolicy,
+    #[msg("Loan is not in good standing")]
+    LoanNotInGoodStanding,
+    #[msg("Loan is not far enough past due")]
+    LoanNotPastDue,
+    #[msg("Loan has not defaulted")]
+    LoanNotDefaulted,
+    #[msg("Invalid loan term")]
+    InvalidLoanTerm,
+    #[msg("Merchant credit account is required with a merchant loan")]
+    MissingMerchantCredit,
+    #[msg("Invalid sweep percentage")]
+    InvalidSweepPercentage,
+    #[msg("The merchant's sweep loan must accompany the payment")]
+    MissingSweepLoan,
+    #[msg("Another loan already has a repayment sweep")]
+    SweepAlreadyActive,
+    #[msg("Invalid liquidity policy")]
+    InvalidLiquidityPolicy,
+    #[msg("Pool utilization would exceed its maximum")]
+    MaxUtilizationExceeded,
+    #[msg("Pool vault would fall below its minimum liquidity")]
+    MinLiquidityBreached,
+    #[msg("Withdrawal request is not pending")]
+    WithdrawalRequestNotPending,
+    #[msg("Withdrawal request is not next in the queue")]
+    InvalidWithdrawalRequest,
+    #[msg("Queued withdrawals must be filled first")]
+    WithdrawalQueueNotEmpty,
+    #[msg("This action is paused")]
+    ActionPaused,
+    #[msg("Invalid deposit limits")]
+    InvalidDepositLimits,
+    #[msg("Deposit is below the minimum")]
+    DepositTooSmall,
+    #[msg("Deposit would exceed the pool's deposit cap")]
+    DepositCapExceeded,
+    #[msg("Deposit would exceed the per-user limit")]
+    UserDepositLimitExceeded,
+    #[msg("Strategy is not active")]
+    StrategyInactive,
+    #[msg("Allocation would exceed the strategy's cap")]
+    StrategyCapExceeded,
+    #[msg("Strategy did not take the allocated amount")]
+    StrategyTransferMismatch,
+    #[msg("Amount exceeds the funds allocated to this strategy")]
+    InsufficientStrategyBalance,
+    #[msg("Deposit needs either a wallet position or a position NFT")]
+    InvalidPosition,
+    #[msg("Position NFT accounts are required for this position")]
+    MissingPositionToken,
+    #[msg("Receipt mint does not belong to this pool")]
+    InvalidReceiptMint,
+    #[msg("Receipt token accounts are required for this position")]
+    MissingReceiptAccount,
+    #[msg("Cannot pay yourself via the pool")]
+    SelfPayment,
+    #[msg("Depositor account is required when spending principal")]
+    MissingDepositor,
+    #[msg("Pool deposits were wiped out while shares remain")]
+    PoolInsolvent,
+    #[msg("Pool can pay this withdrawal now; use withdraw instead")]
+    InstantWithdrawalAvailable,
+    #[msg("Withdrawal request and user token accounts must be writable")]
+    WithdrawalAccountNotWritable,
+    #[msg("Withdrawal request is not owned by the liquidity pool program")]
+    InvalidWithdrawalRequestOwner,
+    #[msg("Withdrawal request belongs to another pool")]
+    WithdrawalRequestWrongPool,
+    #[msg("Token account does not belong to the withdrawal request's user")]
+    InvalidWithdrawalDestination,
+    #[msg("Depositor account is not the one the position is charged to")]
+    InvalidDepositor,
+    #[msg("Position is already charged to the wallet holding its NFT")]
+    PositionAlreadyAttributed,
+    #[msg("Strategy token account does not belong to this strategy")]
+    InvalidStrategyTokenAccount,
+    #[msg("Strategy did not report its value")]
+    StrategyValueUnavailable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An all-zero pool, filled in per test
+    fn pool(total_deposited: u64, total_shares: u64) -> PoolState {
+        let data = [0u8; 8 + PoolState::SPACE];
+        let mut pool_state = PoolState::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        pool_state.total_deposited = total_deposited;
+        pool_state.total_shares = total_shares;
+        pool_state.supply_index = INDEX_SCALE;
+        pool_state
+    }
+
+    #[test]
+    fn rebase_spreads_a_loss_across_shares() {
+        let mut pool_state = pool(1_000, 1_000);
+        rebase_total_deposited(&mut pool_state, 750).unwrap();
+
+        assert_eq!(pool_state.total_deposited, 750);
+        assert_eq!(pool_state.supply_index, INDEX_SCALE * 3 / 4);
+        assert_eq!(
+            shares_to_amount(1_000, pool_state.supply_index).unwrap(),
+            750
+        );
+    }
+
+    #[test]
+    fn rebase_to_zero_keeps_the_index_priceable() {
+        let mut pool_state = pool(1_000, 1_000);
+        rebase_total_deposited(&mut pool_state, 0).unwrap();
+
+        assert_eq!(pool_state.total_deposited, 0);
+        assert_eq!(pool_state.supply_index, MIN_SUPPLY_INDEX);
+        assert_eq!(shares_to_amount(1_000, pool_state.supply_index).unwrap(), 0);
+        // Pricing shares errors instead of dividing by zero
+        assert!(amount_to_shares(1, pool_state.supply_index, false).is_ok());
+        assert!(amount_to_shares(1, 0, true).is_err());
+    }
+
+    #[test]
+    fn writing_off_every_deposit_blocks_new_deposits() {
+        let mut pool_state = pool(1_000, 1_000);
+        pool_state.total_borrowed = 1_000;
+        let mut merchant_loan = loan(1_000);
+        merchant_loan.status = LoanStatus::Defaulted;
+
+        let (outstanding, fee_reversal, depositor_loss) =
+            socialize_loan_loss(&mut pool_state, &mut merchant_loan).unwrap();
+        assert_eq!(
+            (outstanding, fee_reversal, depositor_loss),
+            (1_000, 0, 1_000)
+        );
+        assert_eq!(pool_state.total_borrowed, 0);
+        assert_eq!(pool_state.total_deposited, 0);
+        assert_eq!(pool_state.supply_index, MIN_SUPPLY_INDEX);
+        assert_eq!(merchant_loan.written_off_amount, 1_000);
+
+        // The leftover shares are worthless, so a deposit is refused rather than diluted
+        assert!(prepare_supply_index(&mut pool_state).is_err());
+
+        // Once the reserve restores the loss the pool prices shares again
+        rebase_total_deposited(&mut pool_state, 1_000).unwrap();
+        assert!(prepare_supply_index(&mut pool_state).is_ok());
+    }
+
+    #[test]
+    fn a_loss_writes_principal_down_to_the_position_value() {
+        let mut pool_state = pool(1_000, 1_000);
+        let data = [0u8; 8 + UserDepositAccount::SPACE];
+        let mut user_deposit =
+            UserDepositAccount::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        user_deposit.shares = 1_000;
+        user_deposit.deposited_amount = 1_000;
+        rebase_total_deposited(&mut pool_state, 750).unwrap();
+
+        let position_value =
+            shares_to_amount(user_deposit.shares, pool_state.supply_index).unwrap();
+        assert_eq!(write_down_principal(&mut user_deposit, position_value), 250);
+        // Everything the shares are still worth can be withdrawn as principal
+        assert_eq!(user_deposit.deposited_amount, position_value);
+
+        // A position that gained is left alone
+        rebase_total_deposited(&mut pool_state, 1_000).unwrap();
+        let position_value =
+            shares_to_amount(user_deposit.shares, pool_state.supply_index).unwrap();
+        assert_eq!(write_down_principal(&mut user_deposit, position_value), 0);
+        assert_eq!(user_deposit.deposited_amount, 750);
+    }
+
+    // A strategy with `allocated` deployed, counted in the pool at `marked_value`
+    fn deployed_strategy(
+        pool_state: &mut PoolState,
+        allocated: u64,
+        marked_value: u64,
+    ) -> StrategyAccount {
+        let data = [0u8; 8 + StrategyAccount::SPACE];
+        let mut strategy = StrategyAccount::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        strategy.allocated = allocated;
+        strategy.marked_value = marked_value;
+        pool_state.total_allocated = allocated;
+        strategy
+    }
+
+    #[test]
+    fn strategy_marks_write_down_losses_and_leave_profit_for_harvest() {
+        let mut pool_state = pool(1_000, 1_000);
+        pool_state.protocol_fee_percent = 10;
+        let mut strategy = deployed_strategy(&mut pool_state, 400, 400);
+
+        // A reported loss is written off depositors right away
+        assert_eq!(
+            mark_strategy_value(&mut pool_state, &mut strategy, 0, 0, 300).unwrap(),
+            (0, 100)
+        );
+        assert_eq!(pool_state.total_deposited, 900);
+        assert_eq!(strategy.marked_value, 300);
+
+        // A recovery restores the write-down, but value above principal waits for harvest
+        assert_eq!(
+            mark_strategy_value(&mut pool_state, &mut strategy, 0, 0, 450).unwrap(),
+            (0, 0)
+        );
+        assert_eq!(pool_state.total_deposited, 1_000);
+        assert_eq!(strategy.marked_value, 400);
+        assert_eq!(pool_state.accrued_protocol_fees, 0);
+
+        // Harvested profit is booked as yield, less the protocol fee
+        assert_eq!(
+            mark_strategy_value(&mut pool_state, &mut strategy, 50, 0, 400).unwrap(),
+            (5, 0)
+        );
+        assert_eq!(pool_state.total_deposited, 1_045);
+        assert_eq!(pool_state.accrued_protocol_fees, 5);
+    }
+
+    #[test]
+    fn unwinding_a_strategy_settles_against_its_marked_value() {
+        // Paying back less than the marked share of the principal is a further loss
+        let mut pool_state = pool(1_000, 1_000);
+        let mut strategy = deployed_strategy(&mut pool_state, 400, 400);
+        assert_eq!(
+            mark_strategy_value(&mut pool_state, &mut strategy, 150, 200, 200).unwrap(),
+            (0, 50)
+        );
+        assert_eq!(pool_state.total_deposited, 950);
+        assert_eq!(strategy.allocated, 200);
+        assert_eq!(strategy.marked_value, 200);
+        assert_eq!(pool_state.total_allocated, 200);
+
+        // Getting back more than a written-down strategy was marked at recovers the difference
+        let mut pool_state = pool(900, 1_000);
+        let mut strategy = deployed_strategy(&mut pool_state, 400, 300);
+        assert_eq!(
+            mark_strategy_value(&mut pool_state, &mut strategy, 350, 400, 0).unwrap(),
+            (0, 0)
+        );
+        assert_eq!(pool_state.total_deposited, 950);
+        assert_eq!(strategy.allocated, 0);
+        assert_eq!(strategy.marked_value, 0);
+        assert_eq!(pool_state.total_allocated, 0);
+    }
+
+    #[test]
+    fn a_transferred_position_moves_onto_the_holders_cap() {
+        let data = [0u8; 8 + UserDepositAccount::SPACE];
+        let mut position = UserDepositAccount::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        let opener = Pubkey::new_unique();
+        let holder = Pubkey::new_unique();
+        position.user = opener;
+        position.deposited_amount = 400;
+        let data = [0u8; 8 + DepositorAccount::SPACE];
+        let mut previous_depositor =
+            DepositorAccount::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        previous_depositor.user = opener;
+        previous_depositor.deposited_amount = 1_000;
+        let mut depositor = DepositorAccount::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        depositor.user = holder;
+
+        // The previous wallet's account has to come along
+        assert!(charge_position_to_holder(&mut position, holder, None, &mut depositor).is_err());
+
+        charge_position_to_holder(
+            &mut position,
+            holder,
+            Some(&mut previous_depositor),
+            &mut depositor,
+        )
+        .unwrap();
+        assert_eq!(previous_depositor.deposited_amount, 600);
+        assert_eq!(depositor.deposited_amount, 400);
+        assert_eq!(position.user, holder);
+
+        // Already charged to the holder, so nothing moves again
+        charge_position_to_holder(&mut position, holder, None, &mut depositor).unwrap();
+        assert_eq!(depositor.deposited_amount, 400);
+    }
+
+    #[test]
+    fn an_emptied_pool_starts_over_at_par() {
+        let mut pool_state = pool(0, 0);
+        pool_state.supply_index = MIN_SUPPLY_INDEX;
+        prepare_supply_index(&mut pool_state).unwrap();
+
+        assert_eq!(pool_state.supply_index, INDEX_SCALE);
+    }
+
+    // A loan taken out at par and never repaid
+    fn loan(principal: u64) -> MerchantLoanAccount {
+        let data = [0u8; 8 + MerchantLoanAccount::SPACE];
+        let mut merchant_loan =
+            MerchantLoanAccount::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        merchant_loan.principal = principal;
+        merchant_loan.borrow_index = INDEX_SCALE;
+        merchant_loan.status = LoanStatus::Active;
+        merchant_loan
+    }
+
+    #[test]
+    fn late_fees_leave_total_borrowed_matching_the_loan() {
+        let now = 100 * SECONDS_PER_DAY;
+        let mut pool_state = pool(10_000, 10_000);
+        pool_state.loan_policy.late_fee_bps = 200;
+        // The pool already accrued 10% on the loan the loan itself has not been rolled for
+        pool_state.borrow_index = INDEX_SCALE * 11 / 10;
+        pool_state.total_borrowed = 1_100;
+        pool_state.last_accrual_timestamp = now;
+
+        let mut merchant_loan = loan(1_000);
+        merchant_loan.installment_amount = 500;
+        merchant_loan.installment_interval_days = 30;
+        merchant_loan.next_due_date = now - 10 * SECONDS_PER_DAY;
+        merchant_loan.maturity_date = now + 20 * SECONDS_PER_DAY;
+
+        let late_fee =
+            bring_loan_current(&mut pool_state, &mut merchant_loan, Pubkey::default(), now)
+                .unwrap();
+        assert_eq!(late_fee, 10);
+        assert_eq!(
+            pool_state.total_borrowed,
+            get_outstanding_loan_amount(&merchant_loan)
+        );
+
+        // Rolling the loan again at the same index must not grow the fee a second time
+        accrue_loan_interest(&mut merchant_loan, pool_state.borrow_index).unwrap();
+        assert_eq!(
+            pool_state.total_borrowed,
+            get_outstanding_loan_amount(&merchant_loan)
+        );
+    }
+
+    #[test]
+    fn rebase_of_an_empty_pool_only_sets_deposits() {
+        let mut pool_state = pool(0, 0);
+        rebase_total_deposited(&mut pool_state, 500).unwrap();
+
+        assert_eq!(pool_state.total_deposited, 500);
+        assert_eq!(pool_state.supply_index, INDEX_SCALE);
+    }
 }
@@ -1,7 +1,34 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program;
 
 declare_id!("9MCcaFZBat4AcRvQmt5GxunDrBYN7yGgnBWGRPVETvrE");
 
+// Trust scores are bounded to this range
+pub const MIN_TRUST_SCORE: u8 = 1;
+pub const MAX_TRUST_SCORE: u8 = 5;
+// Payment volume that earns full payment activity points, in token base units (100,000 at 6 decimals)
+pub const TRUST_PAYMENT_VOLUME_TARGET: u64 = 100_000_000_000;
+
+// Revenue is bucketed into 30-day months, keeping the last year
+pub const SECONDS_PER_MONTH: i64 = 30 * 24 * 60 * 60;
+pub const REVENUE_LEDGER_MONTHS: usize = 12;
+// Borrow limits use average monthly revenue over this many months, including the current one
+pub const REVENUE_WINDOW_MONTHS: u32 = 3;
+
+// Actions the guardian can pause, as bits of RegistryState::paused_actions
+pub const PAUSE_DEPOSITS: u8 = 1 << 0;
+pub const PAUSE_BORROWS: u8 = 1 << 1;
+pub const PAUSE_SUBSCRIBES: u8 = 1 << 2;
+pub const PAUSE_REDEMPTIONS: u8 = 1 << 3;
+pub const PAUSE_PAYMENTS: u8 = 1 << 4;
+pub const PAUSE_REGISTRATIONS: u8 = 1 << 5; // New merchants and subscription programs
+pub const PAUSE_ALL: u8 = PAUSE_DEPOSITS
+    | PAUSE_BORROWS
+    | PAUSE_SUBSCRIBES
+    | PAUSE_REDEMPTIONS
+    | PAUSE_PAYMENTS
+    | PAUSE_REGISTRATIONS;
+
 #[program]
 pub mod merchant_registry {
     use super::*;
@@ -12,16 +39,69 @@ pub mod merchant_registry {
         registry_state.authority = ctx.accounts.authority.key();
         registry_state.governance = ctx.accounts.governance.key();
         registry_state.fee = fee;
+        registry_state.score_authority = Pubkey::default();
+        registry_state.revenue_authority = Pubkey::default();
+        registry_state.guardian = Pubkey::default();
+        registry_state.paused_actions = 0;
         registry_state.bump = ctx.bumps.registry_state;
 
         Ok(())
     }
 
+    // Grow a registry created with the legacy layout; the appended fields start zeroed,
+    // so no authorities or guardian are set and nothing is paused
+    pub fn migrate_registry_state(ctx: Context<MigrateRegistryState>) -> Result<()> {
+        let registry_state = ctx.accounts.registry_state.to_account_info();
+        require!(
+            registry_state.data_len() == 8 + RegistryState::LEGACY_SPACE,
+            MerchantRegistryError::AlreadyMigrated
+        );
+
+        // Read the authority straight from the legacy bytes, which can't deserialize as RegistryState
+        {
+            let data = registry_state.try_borrow_data()?;
+            require!(
+                data[..8] == *RegistryState::DISCRIMINATOR,
+                MerchantRegistryError::InvalidRegistryState
+            );
+            let authority = Pubkey::try_from(&data[8..40])
+                .map_err(|_| error!(MerchantRegistryError::InvalidRegistryState))?;
+            require!(
+                authority == ctx.accounts.authority.key(),
+                MerchantRegistryError::Unauthorized
+            );
+        }
+
+        // Top up rent for the larger account before growing it
+        let new_len = 8 + RegistryState::SPACE;
+        let rent_shortfall = Rent::get()?
+            .minimum_balance(new_len)
+            .saturating_sub(registry_state.lamports());
+        if rent_shortfall > 0 {
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: registry_state.clone(),
+                },
+            );
+            system_program::transfer(transfer_ctx, rent_shortfall)?;
+        }
+        registry_state.realloc(new_len, true)?;
+
+        Ok(())
+    }
+
     // Register a new merchant
     pub fn register_merchant(
         ctx: Context<RegisterMerchant>,
         merchant_info: MerchantInfo,
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_REGISTRATIONS),
+            MerchantRegistryError::ActionPaused
+        );
+
         let merchant_account = &mut ctx.accounts.merchant_account;
         merchant_account.merchant_wallet = ctx.accounts.merchant_wallet.key();
         merchant_account.info = merchant_info;
@@ -54,6 +134,147 @@ pub mod merchant_registry {
         Ok(())
     }
 
+    // Set the account allowed to record trust score activity - only callable by authority
+    pub fn set_score_authority(
+        ctx: Context<SetRecordingAuthority>,
+        score_authority: Pubkey,
+    ) -> Result<()> {
+        let registry_state = &mut ctx.accounts.registry_state;
+        registry_state.score_authority = score_authority;
+
+        Ok(())
+    }
+
+    // Set the second account allowed to record merchant revenue - only callable by authority
+    pub fn set_revenue_authority(
+        ctx: Context<SetRecordingAuthority>,
+        revenue_authority: Pubkey,
+    ) -> Result<()> {
+        let registry_state = &mut ctx.accounts.registry_state;
+        registry_state.revenue_authority = revenue_authority;
+
+        Ok(())
+    }
+
+    // Set the account allowed to pause protocol actions - only callable by governance
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+        let registry_state = &mut ctx.accounts.registry_state;
+        registry_state.guardian = guardian;
+
+        Ok(())
+    }
+
+    // Pause protocol actions across every program - callable by the guardian or governance
+    pub fn pause(ctx: Context<Pause>, actions: u8) -> Result<()> {
+        require!(
+            actions != 0 && actions & !PAUSE_ALL == 0,
+            MerchantRegistryError::InvalidPauseActions
+        );
+
+        let registry_state = &mut ctx.accounts.registry_state;
+        let previous_paused_actions = registry_state.paused_actions;
+        registry_state.paused_actions |= actions;
+
+        emit!(PausedActionsUpdatedEvent {
+            updated_by: ctx.accounts.signer.key(),
+            previous_paused_actions,
+            paused_actions: registry_state.paused_actions,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Resume paused protocol actions - only callable by governance
+    pub fn unpause(ctx: Context<Unpause>, actions: u8) -> Result<()> {
+        require!(
+            actions != 0 && actions & !PAUSE_ALL == 0,
+            MerchantRegistryError::InvalidPauseActions
+        );
+
+        let registry_state = &mut ctx.accounts.registry_state;
+        let previous_paused_actions = registry_state.paused_actions;
+        registry_state.paused_actions &= !actions;
+
+        emit!(PausedActionsUpdatedEvent {
+            updated_by: ctx.accounts.governance.key(),
+            previous_paused_actions,
+            paused_actions: registry_state.paused_actions,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Create the revenue ledger for a registered merchant
+    pub fn initialize_revenue_ledger(ctx: Context<InitializeRevenueLedger>) -> Result<()> {
+        let revenue_ledger = &mut ctx.accounts.revenue_ledger;
+        revenue_ledger.merchant_wallet = ctx.accounts.merchant_account.merchant_wallet;
+        revenue_ledger.updated_at = Clock::get()?.unix_timestamp;
+        revenue_ledger.current_month = month_index(revenue_ledger.updated_at);
+        revenue_ledger.bump = ctx.bumps.revenue_ledger;
+
+        Ok(())
+    }
+
+    // Record merchant revenue - only callable by the revenue authority
+    pub fn record_revenue(ctx: Context<RecordRevenue>, amount: u64) -> Result<()> {
+        let revenue_ledger = &mut ctx.accounts.revenue_ledger;
+        revenue_ledger.record(amount, Clock::get()?.unix_timestamp);
+
+        Ok(())
+    }
+
+    // Create the trust score account for a registered merchant
+    pub fn initialize_trust_score(ctx: Context<InitializeTrustScore>) -> Result<()> {
+        let merchant_account = &ctx.accounts.merchant_account;
+        let trust_score = &mut ctx.accounts.trust_score;
+        trust_score.merchant_wallet = merchant_account.merchant_wallet;
+        trust_score.merchant_since = merchant_account.created_at;
+        trust_score.updated_at = Clock::get()?.unix_timestamp;
+        trust_score.bump = ctx.bumps.trust_score;
+        trust_score.score = trust_score.calculate_score(trust_score.updated_at);
+
+        Ok(())
+    }
+
+    // Record merchant activity against their trust score - only callable by the score authority
+    pub fn record_trust_event(ctx: Context<RecordTrustEvent>, event: TrustEvent) -> Result<()> {
+        let trust_score = &mut ctx.accounts.trust_score;
+        match event {
+            TrustEvent::Payment { amount } => {
+                trust_score.payment_volume = trust_score.payment_volume.saturating_add(amount);
+                trust_score.payment_count = trust_score.payment_count.saturating_add(1);
+            }
+            TrustEvent::Repayment {
+                on_time: true,
+                principal,
+            } => {
+                trust_score.on_time_repayments = trust_score.on_time_repayments.saturating_add(1);
+                trust_score.on_time_principal =
+                    trust_score.on_time_principal.saturating_add(principal);
+            }
+            TrustEvent::Repayment {
+                on_time: false,
+                principal,
+            } => {
+                trust_score.late_repayments = trust_score.late_repayments.saturating_add(1);
+                trust_score.late_principal = trust_score.late_principal.saturating_add(principal);
+            }
+            TrustEvent::Delinquency => {
+                trust_score.delinquencies = trust_score.delinquencies.saturating_add(1);
+            }
+            TrustEvent::Default => {
+                trust_score.defaults = trust_score.defaults.saturating_add(1);
+            }
+        }
+
+        trust_score.updated_at = Clock::get()?.unix_timestamp;
+        trust_score.score = trust_score.calculate_score(trust_score.updated_at);
+
+        Ok(())
+    }
+
     // Register a subscription program for a merchant
     pub fn register_program(ctx: Context<RegisterProgram>, _program_id: Pubkey) -> Result<()> {
         let merchant_account = &mut ctx.accounts.merchant_account;
@@ -78,6 +299,14 @@ pub enum MerchantRegistryError {
     Unauthorized,
     #[msg("Invalid merchant info")]
     InvalidMerchantInfo,
+    #[msg("This action is paused")]
+    ActionPaused,
+    #[msg("Invalid pause actions")]
+    InvalidPauseActions,
+    #[msg("Registry state already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Invalid registry state account")]
+    InvalidRegistryState,
 }
 
 // Merchant information struct
@@ -89,13 +318,47 @@ pub struct MerchantInfo {
     pub website: [u8; 64],  // UTF-8 bytes, fixed size
 }
 
+// Merchant activity that feeds the trust score
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrustEvent {
+    Payment { amount: u64 }, // Customer payment received
+    // Loan repayment, whether the loan was current and the principal it repaid
+    Repayment { on_time: bool, principal: u64 },
+    Delinquency, // Loan marked delinquent
+    Default,     // Loan marked defaulted
+}
+
 // Registry state account
+// Fields after bump were added later; migrate_registry_state grows registries created without them
 #[account]
 pub struct RegistryState {
-    pub authority: Pubkey,  // Protocol authority
-    pub governance: Pubkey, // DAO/multisig
-    pub fee: u8,            // Protocol fee in percentage
-    pub bump: u8,           // PDA bump
+    pub authority: Pubkey,         // Protocol authority
+    pub governance: Pubkey,        // DAO/multisig
+    pub fee: u8,                   // Protocol fee in percentage
+    pub bump: u8,                  // PDA bump
+    pub score_authority: Pubkey,   // Signer allowed to record trust events (liquidity pool PDA)
+    pub revenue_authority: Pubkey, // Signer allowed to record revenue (subscription manager PDA)
+    pub guardian: Pubkey,          // Signer allowed to pause actions; only governance unpauses
+    pub paused_actions: u8,        // Bitmask of PAUSE_* actions currently halted
+}
+
+impl RegistryState {
+    pub const SPACE: usize = 32 + 32 + 1 + 1 + 32 + 32 + 32 + 1;
+    // authority + governance + fee + bump, the layout before the fields above were appended
+    pub const LEGACY_SPACE: usize = 32 + 32 + 1 + 1;
+
+    pub fn is_paused(&self, action: u8) -> bool {
+        self.paused_actions & action != 0
+    }
+}
+
+// Emitted whenever the set of paused actions changes
+#[event]
+pub struct PausedActionsUpdatedEvent {
+    pub updated_by: Pubkey,
+    pub previous_paused_actions: u8,
+    pub paused_actions: u8,
+    pub timestamp: i64,
 }
 
 // Merchant account
@@ -109,13 +372,116 @@ pub struct MerchantAccount {
     pub bump: u8,                // PDA bump
 }
 
+// Merchant trust score account
+#[account]
+pub struct TrustScore {
+    pub merchant_wallet: Pubkey, // Merchant wallet address
+    pub payment_volume: u64,     // Total customer payments received
+    pub payment_count: u32,      // Number of customer payments received
+    pub on_time_repayments: u32, // Repayments made while the loan was current
+    pub late_repayments: u32,    // Repayments made while the loan was past due
+    pub on_time_principal: u64,  // Principal repaid while the loan was current
+    pub late_principal: u64,     // Principal repaid while the loan was past due
+    pub delinquencies: u16,      // Loans marked delinquent
+    pub defaults: u16,           // Loans marked defaulted
+    pub merchant_since: i64,     // Merchant registration timestamp
+    pub score: u8,               // Trust score (1-5) as of updated_at
+    pub updated_at: i64,         // Unix timestamp
+    pub bump: u8,                // PDA bump
+}
+
+impl TrustScore {
+    pub const SPACE: usize = 32 + 8 + 4 + 4 + 4 + 8 + 8 + 2 + 2 + 8 + 1 + 8 + 1;
+
+    // Score out of 100 points, mapped onto the 1-5 range:
+    // account age (20), payment volume (20), repayment punctuality (30), credit standing (30)
+    pub fn calculate_score(&self, current_timestamp: i64) -> u8 {
+        let age_days = (current_timestamp.saturating_sub(self.merchant_since) / 86_400).max(0);
+        let age_points = age_days.min(365) as u64 * 20 / 365;
+
+        // Weighted by volume, so splitting payments into dust earns nothing extra
+        let payment_points = (self.payment_volume.min(TRUST_PAYMENT_VOLUME_TARGET) as u128 * 20
+            / TRUST_PAYMENT_VOLUME_TARGET as u128) as u64;
+
+        // Weighted by principal repaid, so interest-only or dust repayments carry no weight
+        // Merchants with no repayment history get the benefit of the doubt for a third
+        let repaid_principal = self.on_time_principal as u128 + self.late_principal as u128;
+        let punctuality_points = (self.on_time_principal as u128 * 30)
+            .checked_div(repaid_principal)
+            .map_or(10, |points| points as u64);
+
+        // Borrow limits are enforced when a loan is issued, so exposure can only outgrow the
+        // limit through missed payments; delinquencies and defaults stand in for that check
+        let compliance_points = 30u64
+            .saturating_sub(self.delinquencies as u64 * 10)
+            .saturating_sub(self.defaults as u64 * 30);
+
+        let points = age_points + payment_points + punctuality_points + compliance_points;
+        let score =
+            MIN_TRUST_SCORE as u64 + points * (MAX_TRUST_SCORE - MIN_TRUST_SCORE) as u64 / 100;
+        score.clamp(MIN_TRUST_SCORE as u64, MAX_TRUST_SCORE as u64) as u8
+    }
+}
+
+// Merchant revenue ledger account
+#[account]
+pub struct RevenueLedger {
+    pub merchant_wallet: Pubkey, // Merchant wallet address
+    pub monthly_revenue: [u64; REVENUE_LEDGER_MONTHS], // Ring buffer indexed by month
+    pub current_month: u32,      // Month index of the latest entry
+    pub total_revenue: u64,      // Lifetime revenue
+    pub updated_at: i64,         // Unix timestamp
+    pub bump: u8,                // PDA bump
+}
+
+impl RevenueLedger {
+    pub const SPACE: usize = 32 + 8 * REVENUE_LEDGER_MONTHS + 4 + 8 + 8 + 1;
+
+    pub fn record(&mut self, amount: u64, current_timestamp: i64) {
+        let month = month_index(current_timestamp);
+
+        // Clear the buckets of any months that passed without revenue
+        if month > self.current_month {
+            let elapsed = (month - self.current_month) as usize;
+            for offset in 1..=elapsed.min(REVENUE_LEDGER_MONTHS) {
+                let bucket = (self.current_month as usize + offset) % REVENUE_LEDGER_MONTHS;
+                self.monthly_revenue[bucket] = 0;
+            }
+            self.current_month = month;
+        }
+
+        let bucket = self.current_month as usize % REVENUE_LEDGER_MONTHS;
+        self.monthly_revenue[bucket] = self.monthly_revenue[bucket].saturating_add(amount);
+        self.total_revenue = self.total_revenue.saturating_add(amount);
+        self.updated_at = current_timestamp;
+    }
+
+    // Average monthly revenue over the trailing window; months without revenue count as zero
+    pub fn trailing_monthly_revenue(&self, current_timestamp: i64) -> u64 {
+        let month = month_index(current_timestamp);
+        let total: u64 = (0..REVENUE_WINDOW_MONTHS)
+            .filter_map(|offset| month.checked_sub(offset))
+            .filter(|m| *m <= self.current_month)
+            .filter(|m| ((self.current_month - m) as usize) < REVENUE_LEDGER_MONTHS)
+            .map(|m| self.monthly_revenue[m as usize % REVENUE_LEDGER_MONTHS])
+            .fold(0u64, |sum, revenue| sum.saturating_add(revenue));
+
+        total / REVENUE_WINDOW_MONTHS as u64
+    }
+}
+
+// Helper function to get the 30-day month a timestamp falls in
+pub fn month_index(timestamp: i64) -> u32 {
+    (timestamp.max(0) / SECONDS_PER_MONTH) as u32
+}
+
 // Initialize context
 #[derive(Accounts)]
 pub struct Initialize<'info> {
     #[account(
         init,
         payer = authority,
-        space = 8 + 32 + 32 + 1 + 1, // discriminator + authority + governance + fee + bump
+        space = 8 + RegistryState::SPACE,
         seeds = [b"registry-state"],
         bump
     )]
@@ -130,6 +496,24 @@ pub struct Initialize<'info> {
     pub system_program: Program<'info, System>,
 }
 
+// Migrate registry state context
+#[derive(Accounts)]
+pub struct MigrateRegistryState<'info> {
+    /// CHECK: Legacy layout doesn't deserialize as RegistryState; discriminator and authority are checked in the handler
+    #[account(
+        mut,
+        seeds = [b"registry-state"],
+        bump,
+        owner = crate::ID
+    )]
+    pub registry_state: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
 // Register merchant context
 #[derive(Accounts)]
 pub struct RegisterMerchant<'info> {
@@ -145,6 +529,12 @@ pub struct RegisterMerchant<'info> {
     /// CHECK: This is the merchant wallet
     pub merchant_wallet: UncheckedAccount<'info>,
 
+    #[account(
+        seeds = [b"registry-state"],
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
     #[account(mut)]
     pub payer: Signer<'info>,
 
@@ -185,6 +575,153 @@ pub struct UpdateMerchantInfo<'info> {
     pub merchant_wallet: Signer<'info>,
 }
 
+// Set recording authority context
+#[derive(Accounts)]
+pub struct SetRecordingAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"registry-state"],
+        bump = registry_state.bump,
+        has_one = authority @ MerchantRegistryError::Unauthorized
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    pub authority: Signer<'info>,
+}
+
+// Set guardian context
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    #[account(
+        mut,
+        seeds = [b"registry-state"],
+        bump = registry_state.bump,
+        has_one = governance @ MerchantRegistryError::Unauthorized
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    pub governance: Signer<'info>,
+}
+
+// Pause context
+#[derive(Accounts)]
+pub struct Pause<'info> {
+    #[account(
+        mut,
+        seeds = [b"registry-state"],
+        bump = registry_state.bump,
+        constraint = signer.key() == registry_state.guardian
+            || signer.key() == registry_state.governance
+            @ MerchantRegistryError::Unauthorized
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    pub signer: Signer<'info>,
+}
+
+// Unpause context
+#[derive(Accounts)]
+pub struct Unpause<'info> {
+    #[account(
+        mut,
+        seeds = [b"registry-state"],
+        bump = registry_state.bump,
+        has_one = governance @ MerchantRegistryError::Unauthorized
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    pub governance: Signer<'info>,
+}
+
+// Initialize revenue ledger context
+#[derive(Accounts)]
+pub struct InitializeRevenueLedger<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + RevenueLedger::SPACE,
+        seeds = [b"revenue-ledger", merchant_account.merchant_wallet.as_ref()],
+        bump
+    )]
+    pub revenue_ledger: Account<'info, RevenueLedger>,
+
+    #[account(
+        seeds = [b"merchant", merchant_account.merchant_wallet.as_ref()],
+        bump = merchant_account.bump
+    )]
+    pub merchant_account: Account<'info, MerchantAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Record revenue context
+#[derive(Accounts)]
+pub struct RecordRevenue<'info> {
+    #[account(
+        seeds = [b"registry-state"],
+        bump = registry_state.bump,
+        constraint = recorder.key() == registry_state.revenue_authority
+            @ MerchantRegistryError::Unauthorized
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    #[account(
+        mut,
+        seeds = [b"revenue-ledger", revenue_ledger.merchant_wallet.as_ref()],
+        bump = revenue_ledger.bump
+    )]
+    pub revenue_ledger: Account<'info, RevenueLedger>,
+
+    pub recorder: Signer<'info>,
+}
+
+// Initialize trust score context
+#[derive(Accounts)]
+pub struct InitializeTrustScore<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + TrustScore::SPACE,
+        seeds = [b"trust-score", merchant_account.merchant_wallet.as_ref()],
+        bump
+    )]
+    pub trust_score: Account<'info, TrustScore>,
+
+    #[account(
+        seeds = [b"merchant", merchant_account.merchant_wallet.as_ref()],
+        bump = merchant_account.bump
+    )]
+    pub merchant_account: Account<'info, MerchantAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Record trust event context
+#[derive(Accounts)]
+pub struct RecordTrustEvent<'info> {
+    #[account(
+        seeds = [b"registry-state"],
+        bump = registry_state.bump,
+        has_one = score_authority @ MerchantRegistryError::Unauthorized
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    #[account(
+        mut,
+        seeds = [b"trust-score", trust_score.merchant_wallet.as_ref()],
+        bump = trust_score.bump
+    )]
+    pub trust_score: Account<'info, TrustScore>,
+
+    pub score_authority: Signer<'info>,
+}
+
 // Register program context
 #[derive(Accounts)]
 pub struct RegisterProgram<'info> {
@@ -196,3 +733,41 @@ pub struct RegisterProgram<'info> {
 
     pub program_authority: Signer<'info>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trust_score() -> TrustScore {
+        let data = [0u8; 8 + TrustScore::SPACE];
+        TrustScore::try_deserialize_unchecked(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn dust_payments_earn_no_payment_points() {
+        let mut dust = trust_score();
+        dust.payment_count = 1_000;
+        dust.payment_volume = 1_000;
+        let mut quiet = trust_score();
+        quiet.payment_count = 1;
+        quiet.payment_volume = TRUST_PAYMENT_VOLUME_TARGET;
+
+        assert_eq!(dust.calculate_score(0), trust_score().calculate_score(0));
+        assert!(quiet.calculate_score(0) > dust.calculate_score(0));
+    }
+
+    #[test]
+    fn punctuality_follows_the_principal_repaid() {
+        // Many on-time dust repayments don't outweigh one late repayment of the loan
+        let mut score = trust_score();
+        score.on_time_repayments = 100;
+        score.on_time_principal = 100;
+        score.late_repayments = 1;
+        score.late_principal = 1_000_000;
+        let mut late = trust_score();
+        late.late_repayments = 1;
+        late.late_principal = 1_000_000;
+
+        assert_eq!(score.calculate_score(0), late.calculate_score(0));
+    }
+}
@@ -0,0 +1,225 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Burn, Mint, Token, TokenAccount, Transfer};
+
+declare_id!("GngeAyF2jmAgS4UFs2JPK3zpJJMEJzSAgBUo3ZGMjwC7");
+
+// Local stand-in for a yield protocol, implementing the liquidity pool's strategy adapter interface
+// Yield is simulated by transferring tokens straight into the strategy vault, losses by simulate_loss
+#[program]
+pub mod mock_strategy {
+    use super::*;
+
+    // Open the strategy for a single depositor (the pool's strategy authority PDA)
+    pub fn initialize(ctx: Context<Initialize>, depositor: Pubkey) -> Result<()> {
+        let strategy_state = &mut ctx.accounts.strategy_state;
+        strategy_state.depositor = depositor;
+        strategy_state.token_mint = ctx.accounts.token_mint.key();
+        strategy_state.strategy_vault = ctx.accounts.strategy_vault.key();
+        strategy_state.principal = 0;
+        strategy_state.bump = ctx.bumps.strategy_state;
+
+        Ok(())
+    }
+
+    // Adapter: pull funds from the depositor into the strategy
+    pub fn deposit(ctx: Context<StrategyAction>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.depositor_token_account.to_account_info(),
+                to: ctx.accounts.strategy_vault.to_account_info(),
+                authority: ctx.accounts.depositor.to_account_info(),
+            },
+        );
+
+        token::transfer(transfer_ctx, amount)?;
+
+        let strategy_state = &mut ctx.accounts.strategy_state;
+        strategy_state.principal = strategy_state.principal.saturating_add(amount);
+
+        Ok(())
+    }
+
+    // Adapter: return principal to the depositor
+    // After a loss the vault can hold less than principal, and pays out what it has left
+    pub fn withdraw(ctx: Context<StrategyAction>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            ctx.accounts.strategy_state.principal >= amount,
+            ErrorCode::InsufficientPrincipal
+        );
+
+        ctx.accounts.strategy_state.principal =
+            ctx.accounts.strategy_state.principal.saturating_sub(amount);
+        let payout = amount.min(ctx.accounts.strategy_vault.amount);
+        if payout == 0 {
+            return Ok(());
+        }
+
+        transfer_from_vault(&ctx, payout)
+    }
+
+    // Adapter: send everything the vault holds above principal to the depositor
+    pub fn harvest(ctx: Context<StrategyAction>) -> Result<()> {
+        let profit = ctx
+            .accounts
+            .strategy_vault
+            .amount
+            .saturating_sub(ctx.accounts.strategy_state.principal);
+        if profit == 0 {
+            return Ok(());
+        }
+
+        transfer_from_vault(&ctx, profit)
+    }
+
+    // Adapter: report what the depositor's position is worth
+    pub fn report_value(ctx: Context<StrategyAction>) -> Result<u64> {
+        Ok(ctx.accounts.strategy_vault.amount)
+    }
+
+    // Test hook: burn funds from the strategy vault to simulate a loss
+    pub fn simulate_loss(ctx: Context<SimulateLoss>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let token_mint = ctx.accounts.token_mint.key();
+        let seeds = &[
+            b"strategy_state".as_ref(),
+            token_mint.as_ref(),
+            &[ctx.accounts.strategy_state.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let burn_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.token_mint.to_account_info(),
+                from: ctx.accounts.strategy_vault.to_account_info(),
+                authority: ctx.accounts.strategy_state.to_account_info(),
+            },
+            signer_seeds,
+        );
+
+        token::burn(burn_ctx, amount)
+    }
+}
+
+// Helper function to move tokens from the strategy vault back to the depositor
+fn transfer_from_vault(ctx: &Context<StrategyAction>, amount: u64) -> Result<()> {
+    let token_mint = ctx.accounts.strategy_state.token_mint;
+    let seeds = &[
+        b"strategy_state".as_ref(),
+        token_mint.as_ref(),
+        &[ctx.accounts.strategy_state.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.strategy_vault.to_account_info(),
+            to: ctx.accounts.depositor_token_account.to_account_info(),
+            authority: ctx.accounts.strategy_state.to_account_info(),
+        },
+        signer_seeds,
+    );
+
+    token::transfer(transfer_ctx, amount)
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + StrategyState::SPACE,
+        seeds = [b"strategy_state", token_mint.key().as_ref()],
+        bump
+    )]
+    pub strategy_state: Account<'info, StrategyState>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"strategy_vault", token_mint.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = strategy_state,
+    )]
+    pub strategy_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Account order follows the adapter interface: depositor, depositor token account, token program,
+// then the strategy's own accounts
+#[derive(Accounts)]
+pub struct StrategyAction<'info> {
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = depositor_token_account.mint == strategy_state.token_mint @ ErrorCode::InvalidTokenAccount
+    )]
+    pub depositor_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+
+    #[account(
+        mut,
+        seeds = [b"strategy_state", strategy_state.token_mint.as_ref()],
+        bump = strategy_state.bump,
+        has_one = depositor @ ErrorCode::UnauthorizedAccess,
+        has_one = strategy_vault @ ErrorCode::InvalidTokenAccount
+    )]
+    pub strategy_state: Account<'info, StrategyState>,
+    #[account(mut)]
+    pub strategy_vault: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SimulateLoss<'info> {
+    #[account(
+        seeds = [b"strategy_state", token_mint.key().as_ref()],
+        bump = strategy_state.bump,
+        has_one = strategy_vault @ ErrorCode::InvalidTokenAccount
+    )]
+    pub strategy_state: Account<'info, StrategyState>,
+    #[account(mut)]
+    pub strategy_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[account]
+pub struct StrategyState {
+    pub depositor: Pubkey,      // Only account allowed to move funds in and out
+    pub token_mint: Pubkey,     // Asset this strategy holds
+    pub strategy_vault: Pubkey, // Token account holding deposits and simulated yield
+    pub principal: u64,         // Deposits not yet withdrawn
+    pub bump: u8,
+}
+
+impl StrategyState {
+    pub const SPACE: usize = 32 + // depositor
+                           32 + // token_mint
+                           32 + // strategy_vault
+                           8 +  // principal
+                           1; // bump
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Withdrawal exceeds deposited principal")]
+    InsufficientPrincipal,
+    #[msg("Invalid token account")]
+    InvalidTokenAccount,
+    #[msg("Unauthorized access")]
+    UnauthorizedAccess,
+}
@@ -0,0 +1,286 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, CreateAccount};
+use anchor_spl::token::{Mint, ID as TOKEN_PROGRAM_ID};
+
+// Deployed at the Metaplex Token Metadata address so the local validator needs no mainnet clone
+declare_id!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+// Token Metadata instruction tags and limits this stand-in understands
+pub const CREATE_METADATA_ACCOUNT_V3: u8 = 33;
+pub const UPDATE_METADATA_ACCOUNT_V2: u8 = 15;
+pub const METADATA_V1_KEY: u8 = 4;
+pub const MAX_NAME_LENGTH: usize = 32;
+pub const MAX_SYMBOL_LENGTH: usize = 10;
+pub const MAX_URI_LENGTH: usize = 200;
+pub const MAX_METADATA_LEN: usize = 679;
+
+// Local stand-in for Metaplex Token Metadata, covering the two instructions the subscription
+// manager uses. Instructions arrive in Metaplex's wire format (a one-byte tag plus Borsh args)
+// rather than Anchor's, so everything is handled by the fallback.
+#[program]
+pub mod mock_token_metadata {
+    use super::*;
+
+    pub fn fallback<'info>(
+        program_id: &Pubkey,
+        accounts: &'info [AccountInfo<'info>],
+        data: &[u8],
+    ) -> Result<()> {
+        let (tag, args) = data
+            .split_first()
+            .ok_or(error!(ErrorCode::UnsupportedInstruction))?;
+
+        match *tag {
+            CREATE_METADATA_ACCOUNT_V3 => create_metadata(
+                program_id,
+                accounts,
+                CreateMetadataAccountArgsV3::try_from_slice(args)?,
+            ),
+            UPDATE_METADATA_ACCOUNT_V2 => update_metadata(
+                program_id,
+                accounts,
+                UpdateMetadataAccountArgsV2::try_from_slice(args)?,
+            ),
+            _ => err!(ErrorCode::UnsupportedInstruction),
+        }
+    }
+}
+
+// Accounts: metadata, mint, mint authority, payer, update authority, system program, [rent]
+fn create_metadata<'info>(
+    program_id: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+    args: CreateMetadataAccountArgsV3,
+) -> Result<()> {
+    require!(accounts.len() >= 6, ErrorCode::NotEnoughAccounts);
+    let metadata = &accounts[0];
+    let mint = &accounts[1];
+    let mint_authority = &accounts[2];
+    let payer = &accounts[3];
+    let update_authority = &accounts[4];
+    let system_program = &accounts[5];
+
+    let (metadata_key, bump) = Pubkey::find_program_address(
+        &[b"metadata", program_id.as_ref(), mint.key.as_ref()],
+        program_id,
+    );
+    require!(
+        metadata.key() == metadata_key,
+        ErrorCode::InvalidMetadataAccount
+    );
+    require!(metadata.data_is_empty(), ErrorCode::AlreadyInitialized);
+
+    // Only the mint's authority may describe it
+    require!(*mint.owner == TOKEN_PROGRAM_ID, ErrorCode::InvalidMint);
+    let mint_state = Mint::try_deserialize(&mut &mint.try_borrow_data()?[..])?;
+    require!(
+        mint_authority.is_signer && mint_state.mint_authority == Some(mint_authority.key()).into(),
+        ErrorCode::InvalidMintAuthority
+    );
+    validate_data(&args.data)?;
+
+    let seeds = &[
+        b"metadata".as_ref(),
+        program_id.as_ref(),
+        mint.key.as_ref(),
+        &[bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let create_ctx = CpiContext::new_with_signer(
+        system_program.clone(),
+        CreateAccount {
+            from: payer.clone(),
+            to: metadata.clone(),
+        },
+        signer_seeds,
+    );
+    system_program::create_account(
+        create_ctx,
+        Rent::get()?.minimum_balance(MAX_METADATA_LEN),
+        MAX_METADATA_LEN as u64,
+        program_id,
+    )?;
+
+    let record = MetadataRecord {
+        key: METADATA_V1_KEY,
+        update_authority: update_authority.key(),
+        mint: mint.key(),
+        name: args.data.name,
+        symbol: args.data.symbol,
+        uri: args.data.uri,
+        seller_fee_basis_points: args.data.seller_fee_basis_points,
+        creators: args.data.creators,
+        primary_sale_happened: false,
+        is_mutable: args.is_mutable,
+    };
+    write_record(metadata, &record)
+}
+
+// Accounts: metadata, update authority
+fn update_metadata<'info>(
+    program_id: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+    args: UpdateMetadataAccountArgsV2,
+) -> Result<()> {
+    require!(accounts.len() >= 2, ErrorCode::NotEnoughAccounts);
+    let metadata = &accounts[0];
+    let update_authority = &accounts[1];
+
+    require!(
+        metadata.owner == program_id,
+        ErrorCode::InvalidMetadataAccount
+    );
+    let mut record = MetadataRecord::deserialize(&mut &metadata.try_borrow_data()?[..])?;
+    require!(
+        record.key == METADATA_V1_KEY,
+        ErrorCode::InvalidMetadataAccount
+    );
+    require!(
+        update_authority.is_signer && update_authority.key() == record.update_authority,
+        ErrorCode::InvalidUpdateAuthority
+    );
+    require!(record.is_mutable, ErrorCode::DataIsImmutable);
+
+    if let Some(data) = args.data {
+        validate_data(&data)?;
+        record.name = data.name;
+        record.symbol = data.symbol;
+        record.uri = data.uri;
+        record.seller_fee_basis_points = data.seller_fee_basis_points;
+        record.creators = data.creators;
+    }
+    if let Some(new_update_authority) = args.new_update_authority {
+        record.update_authority = new_update_authority;
+    }
+    if let Some(primary_sale_happened) = args.primary_sale_happened {
+        // Like Metaplex, a primary sale can be recorded but never undone
+        record.primary_sale_happened |= primary_sale_happened;
+    }
+    if let Some(is_mutable) = args.is_mutable {
+        record.is_mutable = is_mutable;
+    }
+
+    write_record(metadata, &record)
+}
+
+// Helper function to enforce Token Metadata's field limits
+fn validate_data(data: &DataV2) -> Result<()> {
+    require!(data.name.len() <= MAX_NAME_LENGTH, ErrorCode::NameTooLong);
+    require!(
+        data.symbol.len() <= MAX_SYMBOL_LENGTH,
+        ErrorCode::SymbolTooLong
+    );
+    require!(data.uri.len() <= MAX_URI_LENGTH, ErrorCode::UriTooLong);
+    Ok(())
+}
+
+// Helper function to rewrite the whole metadata account, clearing bytes left by longer values
+fn write_record(metadata: &AccountInfo, record: &MetadataRecord) -> Result<()> {
+    let mut data = metadata.try_borrow_mut_data()?;
+    data.fill(0);
+    let mut writer: &mut [u8] = &mut data[..];
+    record.serialize(&mut writer)?;
+    Ok(())
+}
+
+// Stored layout: the same leading fields as a Metaplex metadata account, with strings unpadded
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct MetadataRecord {
+    pub key: u8,
+    pub update_authority: Pubkey,
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub creators: Option<Vec<Creator>>,
+    pub primary_sale_happened: bool,
+    pub is_mutable: bool,
+}
+
+// Instruction argument types, mirroring the Token Metadata wire format
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct CreateMetadataAccountArgsV3 {
+    pub data: DataV2,
+    pub is_mutable: bool,
+    pub collection_details: Option<CollectionDetails>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct UpdateMetadataAccountArgsV2 {
+    pub data: Option<DataV2>,
+    pub new_update_authority: Option<Pubkey>,
+    pub primary_sale_happened: Option<bool>,
+    pub is_mutable: Option<bool>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct DataV2 {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub creators: Option<Vec<Creator>>,
+    pub collection: Option<Collection>,
+    pub uses: Option<Uses>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct Creator {
+    pub address: Pubkey,
+    pub verified: bool,
+    pub share: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct Collection {
+    pub verified: bool,
+    pub key: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct Uses {
+    pub use_method: UseMethod,
+    pub remaining: u64,
+    pub total: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub enum UseMethod {
+    Burn,
+    Multiple,
+    Single,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub enum CollectionDetails {
+    V1 { size: u64 },
+    V2 { padding: [u8; 8] },
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Instruction is not supported by the stand-in")]
+    UnsupportedInstruction,
+    #[msg("Not enough accounts")]
+    NotEnoughAccounts,
+    #[msg("Invalid metadata account")]
+    InvalidMetadataAccount,
+    #[msg("Metadata account already initialized")]
+    AlreadyInitialized,
+    #[msg("Invalid mint")]
+    InvalidMint,
+    #[msg("Mint authority must sign")]
+    InvalidMintAuthority,
+    #[msg("Update authority must sign")]
+    InvalidUpdateAuthority,
+    #[msg("Metadata is immutable")]
+    DataIsImmutable,
+    #[msg("Name is too long")]
+    NameTooLong,
+    #[msg("Symbol is too long")]
+    SymbolTooLong,
+    #[msg("URI is too long")]
+    UriTooLong,
+}
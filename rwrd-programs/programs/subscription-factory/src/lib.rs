@@ -1,10 +1,10 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::Token;
 
 // Importing merchant registry for CPI
 use merchant_registry::{
     cpi::accounts::RegisterProgram as MerchantRegisterProgram,
-    cpi::register_program as merchant_register_program, program::MerchantRegistry,
+    cpi::register_program as merchant_register_program, program::MerchantRegistry, RegistryState,
+    PAUSE_REGISTRATIONS,
 };
 
 declare_id!("AmZj2VQDPnsNaUBHQXAiifMhaKWDLgQ1GmgXDGTjY5Lw");
@@ -30,12 +30,17 @@ pub mod subscription_factory {
         duration_days: u16,
         redemption_quota: u16,
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_REGISTRATIONS),
+            ErrorCode::ActionPaused
+        );
+
         // Validate parameters
         require!(subscription_price > 0, ErrorCode::InvalidPrice);
         require!(duration_days > 0, ErrorCode::InvalidDuration);
         require!(redemption_quota > 0, ErrorCode::InvalidQuota);
         require!(
-            program_name.len() > 0 && program_name.len() <= 50,
+            !program_name.is_empty() && program_name.len() <= 50,
             ErrorCode::InvalidProgramName
         );
 
@@ -148,7 +153,13 @@ pub struct CreateSubscriptionProgram<'info> {
     )]
     pub factory_state: Account<'info, FactoryState>,
 
-    // Merchant Registry Program accounts for CPI
+    // Merchant Registry Program accounts for the pause switch and CPI
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
     /// CHECK: This is verified in the CPI call
     #[account(mut)]
     pub merchant_account: UncheckedAccount<'info>,
@@ -240,4 +251,6 @@ pub enum ErrorCode {
     InvalidQuota,
     #[msg("Invalid program name")]
     InvalidProgramName,
+    #[msg("This action is paused")]
+    ActionPaused,
 }
@@ -1,24 +1,74 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    ed25519_program,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
 use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::metadata::{
+    create_metadata_accounts_v3, mpl_token_metadata::types::DataV2, update_metadata_accounts_v2,
+    CreateMetadataAccountsV3, Metadata, UpdateMetadataAccountsV2,
+};
 use anchor_spl::token;
-use anchor_spl::token::{Mint, Token, TokenAccount, Transfer as SplTransfer};
+use anchor_spl::token::{Mint, MintTo, Token, TokenAccount, Transfer as SplTransfer};
 
 // Importing from subscription factory for context
-use subscription_factory::{program::SubscriptionFactory, SubscriptionProgram};
+use subscription_factory::SubscriptionProgram;
 
 // Importing from liquidity pool for payments
 use liquidity_pool::{cpi::accounts::PayViaPool, cpi::pay_via_pool, program::LiquidityPool};
 
+// Importing from merchant registry to record subscription sales as merchant revenue
+use merchant_registry::{
+    cpi::accounts::RecordRevenue, cpi::record_revenue, program::MerchantRegistry, RegistryState,
+    RevenueLedger, PAUSE_REDEMPTIONS, PAUSE_SUBSCRIBES,
+};
+
 declare_id!("ES4jrcNmiwq87RFZ8dXhbXdc6aYSrwQDoJ8CyGsyjNF8");
 
+// Subscription NFT metadata limits (Token Metadata caps names at 32 characters and URIs at 200)
+// The base URI leaves room for the mint and the attribute query appended to it
+pub const NFT_SYMBOL: &str = "RWRD";
+pub const MAX_NFT_NAME_LENGTH: usize = 32;
+pub const MAX_METADATA_BASE_URI_LENGTH: usize = 64;
+
+// Domain prefix of the redemption message merchants sign
+pub const REDEMPTION_MESSAGE_PREFIX: &[u8] = b"rwrd:redeem";
+
+// Redemption vouchers: the version this program accepts, the domain prefix merchants sign,
+// the QR string prefix and how many nonces behind the newest one each subscription still tracks
+pub const VOUCHER_VERSION: u8 = 1;
+pub const VOUCHER_MESSAGE_PREFIX: &[u8] = b"rwrd:voucher";
+pub const VOUCHER_QR_PREFIX: &str = "RWRD:";
+pub const VOUCHER_NONCE_WINDOW: usize = 1024;
+// RFC 4648 Base32 alphabet; every character is valid in QR alphanumeric mode
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Ed25519 native program instruction layout: signature count, padding, then one set of offsets
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_SIZE: usize = 14;
+const ED25519_PUBKEY_SIZE: usize = 32;
+const ED25519_SIGNATURE_SIZE: usize = 64;
+// Instruction index meaning "this instruction" in the Ed25519 offsets
+const ED25519_CURRENT_INSTRUCTION: u16 = u16::MAX;
+
 #[program]
 pub mod subscription_manager {
     use super::*;
 
     // Initialize the manager state
-    pub fn initialize(ctx: Context<Initialize>, authority: Pubkey) -> Result<()> {
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        authority: Pubkey,
+        metadata_base_uri: String, // Subscription NFT metadata is served from <base>/<mint>
+    ) -> Result<()> {
+        require!(
+            metadata_base_uri.len() <= MAX_METADATA_BASE_URI_LENGTH,
+            ErrorCode::MetadataUriTooLong
+        );
+
         let manager_state = &mut ctx.accounts.manager_state;
         manager_state.authority = authority;
+        manager_state.metadata_base_uri = metadata_base_uri;
         manager_state.bump = ctx.bumps.manager_state;
 
         Ok(())
@@ -29,6 +79,11 @@ pub mod subscription_manager {
         ctx: Context<Subscribe>,
         payment_amount: u64, // Amount to pay
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_SUBSCRIBES),
+            ErrorCode::ActionPaused
+        );
+
         // 1. Verify the subscription program is active
         require!(
             ctx.accounts.subscription_program.is_active,
@@ -67,8 +122,57 @@ pub mod subscription_manager {
         let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
         token::transfer(cpi_ctx, payment_amount)?;
 
-        // 6. Mint NFT to user (for MVP we're omitting actual Metaplex CPI)
-        // Instead, for MVP simplicity, we'll just emit an event
+        // Record the sale in the merchant's revenue ledger
+        record_sale(
+            &ctx.accounts.merchant_registry_program,
+            &ctx.accounts.registry_state,
+            ctx.accounts.revenue_ledger.as_ref(),
+            &ctx.accounts.revenue_authority,
+            ctx.bumps.revenue_authority,
+            payment_amount,
+        )?;
+
+        // 6. Mint the subscription NFT to the user and attach its metadata
+        // The manager keeps mint authority and mints exactly one token per subscription
+        let seeds = &[
+            b"manager_state".as_ref(),
+            &[ctx.accounts.manager_state.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let mint_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.nft_mint.to_account_info(),
+                to: ctx.accounts.user_nft_account.to_account_info(),
+                authority: ctx.accounts.manager_state.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token::mint_to(mint_ctx, 1)?;
+
+        let metadata_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_metadata_program.to_account_info(),
+            CreateMetadataAccountsV3 {
+                metadata: ctx.accounts.nft_metadata.to_account_info(),
+                mint: ctx.accounts.nft_mint.to_account_info(),
+                mint_authority: ctx.accounts.manager_state.to_account_info(),
+                payer: ctx.accounts.user.to_account_info(),
+                update_authority: ctx.accounts.manager_state.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                rent: ctx.accounts.rent.to_account_info(),
+            },
+            signer_seeds,
+        );
+        let data = subscription_metadata(
+            &ctx.accounts.subscription_program.program_name,
+            &ctx.accounts.manager_state.metadata_base_uri,
+            subscription,
+            current_timestamp,
+        );
+        // Left mutable so the manager can keep the metadata in step with the subscription
+        create_metadata_accounts_v3(metadata_ctx, data, true, true, None)?;
+
         emit!(SubscriptionCreatedEvent {
             subscription_id: subscription.key(),
             user: subscription.user,
@@ -86,51 +190,138 @@ pub mod subscription_manager {
         redemption_amount: u16,
         merchant_signature: [u8; 64], // Signature from merchant authorizing the redemption
     ) -> Result<()> {
-        // Verify the subscription is still valid
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_REDEMPTIONS),
+            ErrorCode::ActionPaused
+        );
+
         let subscription = &mut ctx.accounts.subscription;
         let current_timestamp = Clock::get()?.unix_timestamp;
 
         require!(
-            current_timestamp <= subscription.expiry_timestamp,
-            ErrorCode::ExpiredSubscription
+            ctx.accounts.merchant_wallet.key() == ctx.accounts.subscription_program.merchant,
+            ErrorCode::UnauthorizedMerchant
         );
+
+        // The merchant must have signed this exact redemption; the Ed25519 native program checks
+        // the signature itself in the instruction just before this one
+        let message = redemption_message(&subscription.key(), subscription, redemption_amount);
+        verify_merchant_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.subscription_program.merchant,
+            &merchant_signature,
+            &message,
+        )?;
+
+        apply_redemption(subscription, redemption_amount, current_timestamp)?;
+
+        // Emit redemption event
+        emit!(RedemptionEvent {
+            subscription_id: subscription.key(),
+            user: ctx.accounts.signer.key(),
+            program_id: subscription.program_id,
+            redemption_amount,
+            remaining_quota: subscription.remaining_quota,
+            timestamp: current_timestamp,
+        });
+
+        // Reflect the new remaining quota and last redemption on the NFT
+        sync_nft_metadata(
+            &ctx.accounts.manager_state,
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.token_metadata_program,
+            subscription_metadata(
+                &ctx.accounts.subscription_program.program_name,
+                &ctx.accounts.manager_state.metadata_base_uri,
+                &ctx.accounts.subscription,
+                current_timestamp,
+            ),
+        )
+    }
+
+    // Redeem with a voucher a merchant terminal signed and the customer scanned from a QR code
+    // Each voucher nonce can be consumed once per subscription
+    pub fn redeem_voucher(
+        ctx: Context<RedeemVoucher>,
+        voucher: RedemptionVoucher,
+        merchant_signature: [u8; 64], // Merchant's signature over the voucher's signing message
+    ) -> Result<()> {
         require!(
-            subscription.remaining_quota >= redemption_amount,
-            ErrorCode::InsufficientQuota
+            !ctx.accounts.registry_state.is_paused(PAUSE_REDEMPTIONS),
+            ErrorCode::ActionPaused
+        );
+        require!(
+            voucher.version == VOUCHER_VERSION,
+            ErrorCode::UnsupportedVoucherVersion
         );
 
-        // For an MVP, we'll just check that the merchant matches
-        // In a production environment, we would verify the merchant_signature
-        // by recovering the signer and comparing with merchant_wallet
+        let subscription = &mut ctx.accounts.subscription;
+        let current_timestamp = Clock::get()?.unix_timestamp;
+
+        // The voucher must be for this subscription and still within its validity window
         require!(
-            ctx.accounts.merchant_wallet.key() == ctx.accounts.subscription_program.merchant,
-            ErrorCode::UnauthorizedMerchant
+            voucher.subscription == subscription.key()
+                && voucher.program_id == subscription.program_id,
+            ErrorCode::VoucherSubscriptionMismatch
+        );
+        require!(
+            current_timestamp <= voucher.expires_at,
+            ErrorCode::VoucherExpired
         );
 
-        // Update the subscription metadata
-        subscription.remaining_quota = subscription
-            .remaining_quota
-            .saturating_sub(redemption_amount);
-        subscription.last_redeemed_at = current_timestamp;
+        verify_merchant_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.subscription_program.merchant,
+            &merchant_signature,
+            &voucher.signing_message(),
+        )?;
+
+        // Consume the nonce so the voucher can't be replayed
+        let voucher_nonces = &mut ctx.accounts.voucher_nonces;
+        if voucher_nonces.subscription == Pubkey::default() {
+            voucher_nonces.subscription = subscription.key();
+            voucher_nonces.bump = ctx.bumps.voucher_nonces;
+        }
+        voucher_nonces.consume(voucher.nonce)?;
+
+        apply_redemption(subscription, voucher.amount, current_timestamp)?;
 
-        // Emit redemption event
         emit!(RedemptionEvent {
             subscription_id: subscription.key(),
-            user: subscription.user,
+            user: ctx.accounts.signer.key(),
             program_id: subscription.program_id,
-            redemption_amount,
+            redemption_amount: voucher.amount,
             remaining_quota: subscription.remaining_quota,
             timestamp: current_timestamp,
         });
 
-        // In a real implementation, we would also update the NFT metadata
-        // via Metaplex CPI to reflect the updated remaining quota
+        emit!(VoucherRedeemedEvent {
+            subscription_id: subscription.key(),
+            nonce: voucher.nonce,
+            amount: voucher.amount,
+            timestamp: current_timestamp,
+        });
 
-        Ok(())
+        sync_nft_metadata(
+            &ctx.accounts.manager_state,
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.token_metadata_program,
+            subscription_metadata(
+                &ctx.accounts.subscription_program.program_name,
+                &ctx.accounts.manager_state.metadata_base_uri,
+                &ctx.accounts.subscription,
+                current_timestamp,
+            ),
+        )
     }
 
     // Renew an existing subscription
     pub fn renew_subscription(ctx: Context<RenewSubscription>, payment_amount: u64) -> Result<()> {
+        require!(
+            !ctx.accounts.registry_state.is_paused(PAUSE_SUBSCRIBES),
+            ErrorCode::ActionPaused
+        );
+
         // 1. Verify the subscription program is active
         require!(
             ctx.accounts.subscription_program.is_active,
@@ -163,11 +354,43 @@ pub mod subscription_manager {
             user_wallet: ctx.accounts.user.to_account_info(),
             merchant_wallet: ctx.accounts.merchant_wallet.to_account_info(),
             pool_state: ctx.accounts.pool_state.to_account_info(),
-            user_deposit: match &ctx.accounts.user_deposit {
-                Some(acct) => Some(acct.to_account_info()),
-                None => None,
-            },
+            user_deposit: ctx
+                .accounts
+                .user_deposit
+                .as_ref()
+                .map(|acct| acct.to_account_info()),
+            depositor: ctx
+                .accounts
+                .depositor
+                .as_ref()
+                .map(|acct| acct.to_account_info()),
+            position_token_account: None,
+            receipt_mint: ctx
+                .accounts
+                .receipt_mint
+                .as_ref()
+                .map(|acct| acct.to_account_info()),
+            user_receipt_account: ctx
+                .accounts
+                .user_receipt_account
+                .as_ref()
+                .map(|acct| acct.to_account_info()),
+            merchant_loan: ctx
+                .accounts
+                .merchant_loan
+                .as_ref()
+                .map(|acct| acct.to_account_info()),
+            merchant_credit: ctx.accounts.merchant_credit.to_account_info(),
+            registry_state: ctx.accounts.registry_state.to_account_info(),
+            trust_score: ctx
+                .accounts
+                .trust_score
+                .as_ref()
+                .map(|acct| acct.to_account_info()),
+            trust_score_authority: ctx.accounts.trust_score_authority.to_account_info(),
+            merchant_registry_program: ctx.accounts.merchant_registry_program.to_account_info(),
             pool_vault: ctx.accounts.pool_vault.to_account_info(),
+            reserve_vault: ctx.accounts.reserve_vault.to_account_info(),
             user_token_account: ctx.accounts.user_token_account.to_account_info(),
             merchant_token_account: ctx.accounts.merchant_token_account.to_account_info(),
             token_mint: ctx.accounts.payment_token_mint.to_account_info(),
@@ -179,6 +402,16 @@ pub mod subscription_manager {
 
         pay_via_pool(cpi_ctx, payment_amount)?;
 
+        // The pool doesn't book payments as revenue, so the renewal records its own sale
+        record_sale(
+            &ctx.accounts.merchant_registry_program,
+            &ctx.accounts.registry_state,
+            ctx.accounts.revenue_ledger.as_ref(),
+            &ctx.accounts.revenue_authority,
+            ctx.bumps.revenue_authority,
+            payment_amount,
+        )?;
+
         // 5. Update subscription data
         subscription.expiry_timestamp = new_expiry;
         subscription.remaining_quota = ctx.accounts.subscription_program.redemption_quota;
@@ -186,16 +419,248 @@ pub mod subscription_manager {
         // Emit renewal event
         emit!(SubscriptionRenewedEvent {
             subscription_id: subscription.key(),
-            user: subscription.user,
+            user: ctx.accounts.user.key(),
             program_id: subscription.program_id,
             new_expiry_timestamp: subscription.expiry_timestamp,
             timestamp: current_timestamp,
         });
 
+        // Reflect the restored quota and new expiry on the NFT
+        sync_nft_metadata(
+            &ctx.accounts.manager_state,
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.token_metadata_program,
+            subscription_metadata(
+                &ctx.accounts.subscription_program.program_name,
+                &ctx.accounts.manager_state.metadata_base_uri,
+                &ctx.accounts.subscription,
+                current_timestamp,
+            ),
+        )
+    }
+
+    // Refresh a subscription NFT's metadata, e.g. to mark it expired once its expiry has passed
+    // Permissionless: the metadata is derived entirely from the subscription account
+    pub fn sync_metadata(ctx: Context<SyncMetadata>) -> Result<()> {
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        let subscription = &ctx.accounts.subscription;
+
+        sync_nft_metadata(
+            &ctx.accounts.manager_state,
+            &ctx.accounts.nft_metadata,
+            &ctx.accounts.token_metadata_program,
+            subscription_metadata(
+                &ctx.accounts.subscription_program.program_name,
+                &ctx.accounts.manager_state.metadata_base_uri,
+                subscription,
+                current_timestamp,
+            ),
+        )?;
+
+        emit!(MetadataSyncedEvent {
+            subscription_id: subscription.key(),
+            token_mint: subscription.token_mint,
+            remaining_quota: subscription.remaining_quota,
+            expiry_timestamp: subscription.expiry_timestamp,
+            expired: current_timestamp > subscription.expiry_timestamp,
+            timestamp: current_timestamp,
+        });
+
         Ok(())
     }
 }
 
+// Helper function to record a subscription sale in the merchant's revenue ledger, if they have one
+fn record_sale<'info>(
+    merchant_registry_program: &Program<'info, MerchantRegistry>,
+    registry_state: &Account<'info, RegistryState>,
+    revenue_ledger: Option<&Account<'info, RevenueLedger>>,
+    revenue_authority: &UncheckedAccount<'info>,
+    bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let Some(revenue_ledger) = revenue_ledger else {
+        return Ok(());
+    };
+    let seeds = &[b"revenue_authority".as_ref(), &[bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        merchant_registry_program.to_account_info(),
+        RecordRevenue {
+            registry_state: registry_state.to_account_info(),
+            revenue_ledger: revenue_ledger.to_account_info(),
+            recorder: revenue_authority.to_account_info(),
+        },
+        signer_seeds,
+    );
+    record_revenue(cpi_ctx, amount)
+}
+
+// Helper function to fit a subscription program's name into the NFT name field
+fn subscription_nft_name(program_name: &str) -> String {
+    program_name.chars().take(MAX_NFT_NAME_LENGTH).collect()
+}
+
+// Helper function to check a subscription can cover a redemption and record it
+fn apply_redemption(
+    subscription: &mut SubscriptionAccount,
+    redemption_amount: u16,
+    current_timestamp: i64,
+) -> Result<()> {
+    require!(
+        current_timestamp <= subscription.expiry_timestamp,
+        ErrorCode::ExpiredSubscription
+    );
+    require!(
+        subscription.remaining_quota >= redemption_amount,
+        ErrorCode::InsufficientQuota
+    );
+
+    subscription.remaining_quota = subscription
+        .remaining_quota
+        .saturating_sub(redemption_amount);
+    subscription.last_redeemed_at = current_timestamp;
+
+    Ok(())
+}
+
+// Helper function to build the canonical redemption message a merchant signs
+// The subscription's current quota and expiry change with every redemption and renewal,
+// so a signature authorizes a single redemption and can't be replayed
+fn redemption_message(
+    subscription_key: &Pubkey,
+    subscription: &SubscriptionAccount,
+    redemption_amount: u16,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(REDEMPTION_MESSAGE_PREFIX.len() + 32 + 32 + 2 + 2 + 8);
+    message.extend_from_slice(REDEMPTION_MESSAGE_PREFIX);
+    message.extend_from_slice(crate::ID.as_ref());
+    message.extend_from_slice(subscription_key.as_ref());
+    message.extend_from_slice(&redemption_amount.to_le_bytes());
+    message.extend_from_slice(&subscription.remaining_quota.to_le_bytes());
+    message.extend_from_slice(&subscription.expiry_timestamp.to_le_bytes());
+    message
+}
+
+// Helper function to check that the previous instruction is an Ed25519 verification of
+// `signature` by `merchant` over `message`, with all data carried in that instruction
+fn verify_merchant_signature(
+    instructions: &AccountInfo,
+    merchant: &Pubkey,
+    signature: &[u8; 64],
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)?;
+    require!(current_index > 0, ErrorCode::MissingSignatureVerification);
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, instructions)?;
+    require!(
+        ed25519_ix.program_id == ed25519_program::ID,
+        ErrorCode::MissingSignatureVerification
+    );
+
+    let data = &ed25519_ix.data;
+    require!(
+        ed25519_ix.accounts.is_empty()
+            && data.len() >= ED25519_OFFSETS_START + ED25519_OFFSETS_SIZE
+            && data[0] == 1,
+        ErrorCode::InvalidSignatureInstruction
+    );
+
+    let read_u16 = |index: usize| {
+        let at = ED25519_OFFSETS_START + index * 2;
+        u16::from_le_bytes([data[at], data[at + 1]])
+    };
+    let signature_offset = read_u16(0) as usize;
+    let signature_instruction_index = read_u16(1);
+    let public_key_offset = read_u16(2) as usize;
+    let public_key_instruction_index = read_u16(3);
+    let message_offset = read_u16(4) as usize;
+    let message_size = read_u16(5) as usize;
+    let message_instruction_index = read_u16(6);
+
+    // Data pointing into other instructions could be swapped without invalidating the signature
+    require!(
+        signature_instruction_index == ED25519_CURRENT_INSTRUCTION
+            && public_key_instruction_index == ED25519_CURRENT_INSTRUCTION
+            && message_instruction_index == ED25519_CURRENT_INSTRUCTION,
+        ErrorCode::InvalidSignatureInstruction
+    );
+
+    let read_slice = |offset: usize, len: usize| {
+        data.get(offset..offset.saturating_add(len))
+            .ok_or(error!(ErrorCode::InvalidSignatureInstruction))
+    };
+    require!(
+        read_slice(public_key_offset, ED25519_PUBKEY_SIZE)? == merchant.as_ref(),
+        ErrorCode::MerchantSignerMismatch
+    );
+    require!(
+        read_slice(signature_offset, ED25519_SIGNATURE_SIZE)? == signature.as_ref(),
+        ErrorCode::SignatureMismatch
+    );
+    require!(
+        read_slice(message_offset, message_size)? == message,
+        ErrorCode::RedemptionMessageMismatch
+    );
+
+    Ok(())
+}
+
+// Helper function to build a subscription NFT's metadata. Its attributes (remaining quota,
+// expiry, last redemption and status) travel in the URI query for the metadata server to render
+fn subscription_metadata(
+    program_name: &str,
+    base_uri: &str,
+    subscription: &SubscriptionAccount,
+    current_timestamp: i64,
+) -> DataV2 {
+    let status = if current_timestamp > subscription.expiry_timestamp {
+        "expired"
+    } else {
+        "active"
+    };
+
+    DataV2 {
+        name: subscription_nft_name(program_name),
+        symbol: NFT_SYMBOL.to_string(),
+        uri: format!(
+            "{}/{}?quota={}&expiry={}&last_redeemed={}&status={}",
+            base_uri,
+            subscription.token_mint,
+            subscription.remaining_quota,
+            subscription.expiry_timestamp,
+            subscription.last_redeemed_at,
+            status
+        ),
+        seller_fee_basis_points: 0,
+        creators: None,
+        collection: None,
+        uses: None,
+    }
+}
+
+// Helper function to overwrite a subscription NFT's metadata, signed by the manager as update authority
+fn sync_nft_metadata<'info>(
+    manager_state: &Account<'info, ManagerState>,
+    nft_metadata: &UncheckedAccount<'info>,
+    token_metadata_program: &Program<'info, Metadata>,
+    data: DataV2,
+) -> Result<()> {
+    let seeds = &[b"manager_state".as_ref(), &[manager_state.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let update_ctx = CpiContext::new_with_signer(
+        token_metadata_program.to_account_info(),
+        UpdateMetadataAccountsV2 {
+            metadata: nft_metadata.to_account_info(),
+            update_authority: manager_state.to_account_info(),
+        },
+        signer_seeds,
+    );
+    update_metadata_accounts_v2(update_ctx, None, Some(data), None, None)
+}
+
 #[derive(Accounts)]
 pub struct Initialize<'info> {
     #[account(
@@ -217,11 +682,7 @@ pub struct Subscribe<'info> {
         init,
         payer = user,
         space = 8 + SubscriptionAccount::SPACE,
-        seeds = [
-            b"subscription", 
-            user.key().as_ref(),
-            subscription_program.key().as_ref()
-        ],
+        seeds = [b"subscription", nft_mint.key().as_ref()],
         bump
     )]
     pub subscription: Account<'info, SubscriptionAccount>,
@@ -241,69 +702,242 @@ pub struct Subscribe<'info> {
     // Payment and token accounts
     #[account(mut)]
     pub user_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"pool_vault", payment_token_mint.key().as_ref()],
+        seeds::program = liquidity_pool::ID,
+        bump
+    )]
     pub pool_vault: Account<'info, TokenAccount>,
     pub payment_token_mint: Account<'info, Mint>,
 
-    // NFT mint account (for MVP, we're not actually minting the NFT)
-    /// CHECK: For MVP we're not actually using this account
-    pub nft_mint: UncheckedAccount<'info>,
+    // Merchant registry accounts for recording the sale as merchant revenue
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry_program.key(),
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    // Revenue ledger is optional because the merchant might not have one
+    #[account(
+        mut,
+        seeds = [b"revenue-ledger", subscription_program.merchant.as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = revenue_ledger.bump
+    )]
+    pub revenue_ledger: Option<Account<'info, RevenueLedger>>,
+    /// CHECK: PDA signer only, registered as the registry's revenue authority
+    #[account(seeds = [b"revenue_authority"], bump)]
+    pub revenue_authority: UncheckedAccount<'info>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
+
+    // Subscription NFT: a fresh 0-decimal mint with the manager as mint authority
+    #[account(
+        init,
+        payer = user,
+        mint::decimals = 0,
+        mint::authority = manager_state,
+    )]
+    pub nft_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = nft_mint,
+        associated_token::authority = user,
+    )]
+    pub user_nft_account: Account<'info, TokenAccount>,
+    /// CHECK: Metadata PDA, created and checked by the Token Metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref()
+        ],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
 
     // Programs
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_metadata_program: Program<'info, Metadata>,
     pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
 pub struct Redeem<'info> {
     #[account(
         mut,
-        seeds = [
-            b"subscription", 
-            subscription.user.as_ref(),
-            subscription.program_id.as_ref()
-        ],
+        seeds = [b"subscription", subscription.token_mint.as_ref()],
         bump = subscription.bump
     )]
     pub subscription: Account<'info, SubscriptionAccount>,
 
-    // Either the user or merchant can initiate redemption
+    // The NFT holder initiates redemption with the merchant's signed approval
     pub signer: Signer<'info>,
 
+    // Only the current holder of the subscription NFT may use it
+    #[account(
+        constraint = holder_nft_account.mint == subscription.token_mint @ ErrorCode::NotTokenHolder,
+        constraint = holder_nft_account.owner == signer.key() @ ErrorCode::NotTokenHolder,
+        constraint = holder_nft_account.amount == 1 @ ErrorCode::NotTokenHolder
+    )]
+    pub holder_nft_account: Account<'info, TokenAccount>,
+
     // We need to verify the merchant and program
     /// CHECK: This is the merchant wallet - verified in the instruction logic
     pub merchant_wallet: UncheckedAccount<'info>,
+    #[account(
+        constraint = subscription_program.key() == subscription.program_id @ ErrorCode::InvalidSubscriptionProgram
+    )]
     pub subscription_program: Account<'info, SubscriptionProgram>,
+
+    // Pause switches are recorded in the merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry::ID,
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    /// CHECK: Instructions sysvar, used to find the merchant's Ed25519 signature verification
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    // Subscription NFT metadata, kept in step with the subscription
+    #[account(
+        seeds = [b"manager_state"],
+        bump = manager_state.bump
+    )]
+    pub manager_state: Account<'info, ManagerState>,
+    /// CHECK: Metadata PDA of the subscription NFT, updated by the Token Metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            subscription.token_mint.as_ref()
+        ],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
 }
 
 #[derive(Accounts)]
-pub struct RenewSubscription<'info> {
+pub struct RedeemVoucher<'info> {
+    #[account(
+        mut,
+        seeds = [b"subscription", subscription.token_mint.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, SubscriptionAccount>,
+
+    // The NFT holder submits the voucher and pays for the nonce bitmap the first time
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    // Only the current holder of the subscription NFT may use it
+    #[account(
+        constraint = holder_nft_account.mint == subscription.token_mint @ ErrorCode::NotTokenHolder,
+        constraint = holder_nft_account.owner == signer.key() @ ErrorCode::NotTokenHolder,
+        constraint = holder_nft_account.amount == 1 @ ErrorCode::NotTokenHolder
+    )]
+    pub holder_nft_account: Account<'info, TokenAccount>,
+
+    #[account(
+        constraint = subscription_program.key() == subscription.program_id @ ErrorCode::InvalidSubscriptionProgram
+    )]
+    pub subscription_program: Account<'info, SubscriptionProgram>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + VoucherNonces::SPACE,
+        seeds = [b"voucher_nonces", subscription.key().as_ref()],
+        bump
+    )]
+    pub voucher_nonces: Account<'info, VoucherNonces>,
+
+    // Pause switches are recorded in the merchant registry
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry::ID,
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+
+    /// CHECK: Instructions sysvar, used to find the merchant's Ed25519 signature verification
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    // Subscription NFT metadata, kept in step with the subscription
+    #[account(
+        seeds = [b"manager_state"],
+        bump = manager_state.bump
+    )]
+    pub manager_state: Account<'info, ManagerState>,
+    /// CHECK: Metadata PDA of the subscription NFT, updated by the Token Metadata program
     #[account(
         mut,
         seeds = [
-            b"subscription", 
-            user.key().as_ref(),
-            subscription_program.key().as_ref()
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            subscription.token_mint.as_ref()
         ],
-        bump = subscription.bump,
-        constraint = user.key() == subscription.user @ ErrorCode::UnauthorizedAccess
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RenewSubscription<'info> {
+    #[account(
+        mut,
+        seeds = [b"subscription", subscription.token_mint.as_ref()],
+        bump = subscription.bump
     )]
     pub subscription: Account<'info, SubscriptionAccount>,
+    // The NFT holder renews and pays, whoever originally bought the subscription
     #[account(mut)]
     pub user: Signer<'info>,
 
+    // Only the current holder of the subscription NFT may use it
+    #[account(
+        constraint = holder_nft_account.mint == subscription.token_mint @ ErrorCode::NotTokenHolder,
+        constraint = holder_nft_account.owner == user.key() @ ErrorCode::NotTokenHolder,
+        constraint = holder_nft_account.amount == 1 @ ErrorCode::NotTokenHolder
+    )]
+    pub holder_nft_account: Account<'info, TokenAccount>,
+
     // Subscription program account (from subscription factory)
+    #[account(
+        constraint = subscription_program.key() == subscription.program_id @ ErrorCode::InvalidSubscriptionProgram
+    )]
     pub subscription_program: Account<'info, SubscriptionProgram>,
 
-    /// CHECK: The merchant wallet for payment
+    /// CHECK: The program's merchant wallet, receives the payment
+    #[account(
+        address = subscription_program.merchant @ ErrorCode::UnauthorizedMerchant
+    )]
     pub merchant_wallet: UncheckedAccount<'info>,
 
     // Liquidity pool accounts
     #[account(
-        seeds = [b"pool_state"],
+        mut,
+        seeds = [b"pool_state", payment_token_mint.key().as_ref()],
         seeds::program = liquidity_pool_program.key(),
-        bump
+        bump = pool_state.bump,
+        constraint = pool_state.token_mint == payment_token_mint.key() @ ErrorCode::InvalidPaymentMint,
+        constraint = pool_state.pool_vault == pool_vault.key() @ ErrorCode::InvalidPoolVault,
+        constraint = pool_state.reserve_vault == reserve_vault.key() @ ErrorCode::InvalidPoolVault
     )]
     pub pool_state: Account<'info, liquidity_pool::PoolState>,
 
@@ -319,6 +953,25 @@ pub struct RenewSubscription<'info> {
         bump,
     )]
     pub user_deposit: Option<Account<'info, liquidity_pool::UserDepositAccount>>,
+    // Required when the payment spends deposited principal
+    #[account(
+        mut,
+        seeds = [
+            b"depositor",
+            user.key().as_ref(),
+            payment_token_mint.key().as_ref()
+        ],
+        seeds::program = liquidity_pool_program.key(),
+        bump,
+    )]
+    pub depositor: Option<Account<'info, liquidity_pool::DepositorAccount>>,
+    // Required alongside the user deposit; its receipt tokens are burned for the shares spent
+    /// CHECK: Pool receipt mint, checked by the liquidity pool
+    #[account(mut)]
+    pub receipt_mint: Option<UncheckedAccount<'info>>,
+    /// CHECK: User's receipt token account, checked by the token program
+    #[account(mut)]
+    pub user_receipt_account: Option<UncheckedAccount<'info>>,
 
     #[account(
         mut,
@@ -327,11 +980,69 @@ pub struct RenewSubscription<'info> {
         bump
     )]
     pub pool_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub reserve_vault: Account<'info, TokenAccount>,
+
+    // Merchant loan is optional; when present, its repayment sweep applies
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_loan",
+            merchant_wallet.key().as_ref(),
+            payment_token_mint.key().as_ref(),
+            &merchant_loan.loan_index.to_le_bytes()
+        ],
+        seeds::program = liquidity_pool_program.key(),
+        bump,
+    )]
+    pub merchant_loan: Option<Account<'info, liquidity_pool::MerchantLoanAccount>>,
+    // Always passed, even before the merchant's first loan, so an active sweep can't be skipped
+    /// CHECK: Address checked by seeds; the liquidity pool reads it once initialized
+    #[account(
+        mut,
+        seeds = [
+            b"merchant_credit",
+            merchant_wallet.key().as_ref(),
+            payment_token_mint.key().as_ref()
+        ],
+        seeds::program = liquidity_pool_program.key(),
+        bump,
+    )]
+    pub merchant_credit: UncheckedAccount<'info>,
+
+    // Merchant registry accounts for pause switches and trust score bookkeeping
+    #[account(
+        seeds = [b"registry-state"],
+        seeds::program = merchant_registry::ID,
+        bump = registry_state.bump
+    )]
+    pub registry_state: Account<'info, RegistryState>,
+    /// CHECK: Merchant trust score, checked by the liquidity pool
+    #[account(mut)]
+    pub trust_score: Option<UncheckedAccount<'info>>,
+    // Revenue ledger is optional because the merchant might not have one
+    #[account(
+        mut,
+        seeds = [b"revenue-ledger", subscription_program.merchant.as_ref()],
+        seeds::program = merchant_registry_program.key(),
+        bump = revenue_ledger.bump
+    )]
+    pub revenue_ledger: Option<Account<'info, RevenueLedger>>,
+    /// CHECK: PDA signer only, registered as the registry's revenue authority
+    #[account(seeds = [b"revenue_authority"], bump)]
+    pub revenue_authority: UncheckedAccount<'info>,
+    /// CHECK: Liquidity pool trust score authority PDA, checked by the liquidity pool
+    pub trust_score_authority: UncheckedAccount<'info>,
+    pub merchant_registry_program: Program<'info, MerchantRegistry>,
 
     // Payment and token accounts
     #[account(mut)]
     pub user_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(
+        mut,
+        token::mint = payment_token_mint,
+        token::authority = subscription_program.merchant
+    )]
     pub merchant_token_account: Account<'info, TokenAccount>,
     pub payment_token_mint: Account<'info, Mint>,
 
@@ -339,24 +1050,77 @@ pub struct RenewSubscription<'info> {
     pub liquidity_pool_program: Program<'info, LiquidityPool>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
+
+    // Subscription NFT metadata, kept in step with the subscription
+    #[account(
+        seeds = [b"manager_state"],
+        bump = manager_state.bump
+    )]
+    pub manager_state: Account<'info, ManagerState>,
+    /// CHECK: Metadata PDA of the subscription NFT, updated by the Token Metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            subscription.token_mint.as_ref()
+        ],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+}
+
+#[derive(Accounts)]
+pub struct SyncMetadata<'info> {
+    #[account(
+        seeds = [b"subscription", subscription.token_mint.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, SubscriptionAccount>,
+    #[account(
+        constraint = subscription_program.key() == subscription.program_id @ ErrorCode::InvalidSubscriptionProgram
+    )]
+    pub subscription_program: Account<'info, SubscriptionProgram>,
+    #[account(
+        seeds = [b"manager_state"],
+        bump = manager_state.bump
+    )]
+    pub manager_state: Account<'info, ManagerState>,
+    /// CHECK: Metadata PDA of the subscription NFT, updated by the Token Metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            subscription.token_mint.as_ref()
+        ],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
 }
 
 #[account]
 pub struct ManagerState {
     pub authority: Pubkey,
+    pub metadata_base_uri: String, // Up to 64 chars
     pub bump: u8,
 }
 
 impl ManagerState {
     pub const SPACE: usize = 32 + // authority
+                           4 + MAX_METADATA_BASE_URI_LENGTH + // metadata_base_uri
                            1; // bump
 }
 
 #[account]
 pub struct SubscriptionAccount {
-    pub user: Pubkey,
+    pub user: Pubkey, // Original buyer; the current NFT holder controls the subscription
     pub program_id: Pubkey,
-    pub token_mint: Pubkey,
+    pub token_mint: Pubkey, // Subscription NFT mint, also the PDA seed
     pub remaining_quota: u16,
     pub expiry_timestamp: i64,
     pub created_at: i64,
@@ -375,6 +1139,168 @@ impl SubscriptionAccount {
                             1; // bump
 }
 
+// Redemption voucher a merchant terminal signs and shows as a QR code for the customer to submit
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RedemptionVoucher {
+    pub version: u8,          // Voucher format version
+    pub program_id: Pubkey,   // Subscription program the voucher redeems against
+    pub subscription: Pubkey, // Subscription account being redeemed
+    pub amount: u16,          // Quota to redeem
+    pub nonce: u64,           // Single-use within the subscription
+    pub expires_at: i64,      // Unix timestamp after which the voucher is void
+}
+
+impl RedemptionVoucher {
+    pub const SPACE: usize = 1 + // version
+                           32 + // program_id
+                           32 + // subscription
+                           2 +  // amount
+                           8 +  // nonce
+                           8; // expires_at
+
+    // Bytes the merchant signs: the domain prefix, this program and the serialized voucher
+    pub fn signing_message(&self) -> Vec<u8> {
+        let mut message =
+            Vec::with_capacity(VOUCHER_MESSAGE_PREFIX.len() + 32 + RedemptionVoucher::SPACE);
+        message.extend_from_slice(VOUCHER_MESSAGE_PREFIX);
+        message.extend_from_slice(crate::ID.as_ref());
+        message.extend_from_slice(&self.to_bytes());
+        message
+    }
+
+    // Encode the voucher and its signature as "RWRD:" + Base32, compact in QR alphanumeric mode
+    pub fn encode_qr(&self, merchant_signature: &[u8; 64]) -> String {
+        let mut payload = self.to_bytes();
+        payload.extend_from_slice(merchant_signature);
+        format!("{}{}", VOUCHER_QR_PREFIX, base32_encode(&payload))
+    }
+
+    // Decode a QR string produced by `encode_qr` back into the voucher and its signature
+    pub fn decode_qr(encoded: &str) -> Result<(RedemptionVoucher, [u8; 64])> {
+        let payload = encoded
+            .strip_prefix(VOUCHER_QR_PREFIX)
+            .and_then(base32_decode)
+            .ok_or(error!(ErrorCode::InvalidVoucherEncoding))?;
+        require!(
+            payload.len() == RedemptionVoucher::SPACE + 64,
+            ErrorCode::InvalidVoucherEncoding
+        );
+
+        let (voucher_bytes, signature_bytes) = payload.split_at(RedemptionVoucher::SPACE);
+        let voucher = RedemptionVoucher::try_from_slice(voucher_bytes)
+            .map_err(|_| error!(ErrorCode::InvalidVoucherEncoding))?;
+        require!(
+            voucher.version == VOUCHER_VERSION,
+            ErrorCode::UnsupportedVoucherVersion
+        );
+
+        let mut merchant_signature = [0u8; 64];
+        merchant_signature.copy_from_slice(signature_bytes);
+        Ok((voucher, merchant_signature))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RedemptionVoucher::SPACE);
+        // Serializing fixed-size fields into a Vec can't fail
+        self.serialize(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+// Helper function to encode bytes as unpadded RFC 4648 Base32
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+// Helper function to decode unpadded RFC 4648 Base32, returning None on any invalid character,
+// impossible length or non-zero padding bits, so each payload has exactly one accepted encoding
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for character in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|&c| c == character)? as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    // A whole number of bytes leaves under 5 bits over, and the encoder pads them with zeros
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+
+    Some(bytes)
+}
+
+// Per-subscription sliding window of consumed voucher nonces
+// The bitmap is a ring over the nonces from base_nonce up to base_nonce + VOUCHER_NONCE_WINDOW
+#[account]
+pub struct VoucherNonces {
+    pub subscription: Pubkey,
+    pub base_nonce: u64, // Oldest nonce still tracked; anything below it is refused
+    pub bitmap: [u8; VOUCHER_NONCE_WINDOW / 8], // Bit n % VOUCHER_NONCE_WINDOW set once nonce n is used
+    pub bump: u8,
+}
+
+impl VoucherNonces {
+    pub const SPACE: usize = 32 + // subscription
+                           8 +  // base_nonce
+                           VOUCHER_NONCE_WINDOW / 8 + // bitmap
+                           1; // bump
+
+    // Mark a nonce as used, failing if it's already consumed or has fallen out of the window
+    // A nonce past the window slides it forward, forgetting the oldest nonces
+    pub fn consume(&mut self, nonce: u64) -> Result<()> {
+        require!(nonce >= self.base_nonce, ErrorCode::VoucherNonceTooOld);
+
+        let window = VOUCHER_NONCE_WINDOW as u64;
+        if nonce - self.base_nonce >= window {
+            let base_nonce = nonce - window + 1;
+            if base_nonce - self.base_nonce >= window {
+                self.bitmap = [0; VOUCHER_NONCE_WINDOW / 8];
+            } else {
+                for forgotten in self.base_nonce..base_nonce {
+                    let (index, mask) = Self::bit(forgotten);
+                    self.bitmap[index] &= !mask;
+                }
+            }
+            self.base_nonce = base_nonce;
+        }
+
+        let (index, mask) = Self::bit(nonce);
+        require!(self.bitmap[index] & mask == 0, ErrorCode::VoucherNonceUsed);
+        self.bitmap[index] |= mask;
+
+        Ok(())
+    }
+
+    // Bitmap byte and mask for a nonce's slot in the ring
+    fn bit(nonce: u64) -> (usize, u8) {
+        let slot = (nonce % VOUCHER_NONCE_WINDOW as u64) as usize;
+        (slot / 8, 1u8 << (slot % 8))
+    }
+}
+
 // Events
 #[event]
 pub struct SubscriptionCreatedEvent {
@@ -404,6 +1330,24 @@ pub struct SubscriptionRenewedEvent {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct MetadataSyncedEvent {
+    pub subscription_id: Pubkey,
+    pub token_mint: Pubkey,
+    pub remaining_quota: u16,
+    pub expiry_timestamp: i64,
+    pub expired: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VoucherRedeemedEvent {
+    pub subscription_id: Pubkey,
+    pub nonce: u64,
+    pub amount: u16,
+    pub timestamp: i64,
+}
+
 #[error_code]
 pub enum ErrorCode {
     #[msg("Subscription program is inactive")]
@@ -418,4 +1362,201 @@ pub enum ErrorCode {
     UnauthorizedMerchant,
     #[msg("Unauthorized access")]
     UnauthorizedAccess,
+    #[msg("Payment mint does not match the pool")]
+    InvalidPaymentMint,
+    #[msg("Vault does not belong to the pool")]
+    InvalidPoolVault,
+    #[msg("This action is paused")]
+    ActionPaused,
+    #[msg("Metadata base URI is too long")]
+    MetadataUriTooLong,
+    #[msg("Subscription program does not match the subscription")]
+    InvalidSubscriptionProgram,
+    #[msg("Redemption must follow an Ed25519 signature verification instruction")]
+    MissingSignatureVerification,
+    #[msg("Ed25519 instruction must verify one signature with data held in itself")]
+    InvalidSignatureInstruction,
+    #[msg("Redemption was not signed by the program's merchant")]
+    MerchantSignerMismatch,
+    #[msg("Merchant signature does not match the verified signature")]
+    SignatureMismatch,
+    #[msg("Signed message does not match this redemption")]
+    RedemptionMessageMismatch,
+    #[msg("Voucher version is not supported")]
+    UnsupportedVoucherVersion,
+    #[msg("Voucher is for a different subscription")]
+    VoucherSubscriptionMismatch,
+    #[msg("Voucher has expired")]
+    VoucherExpired,
+    #[msg("Voucher nonce is older than the subscription's nonce window")]
+    VoucherNonceTooOld,
+    #[msg("Voucher nonce has already been used")]
+    VoucherNonceUsed,
+    #[msg("Voucher QR string is malformed")]
+    InvalidVoucherEncoding,
+    #[msg("Signer does not hold the subscription NFT")]
+    NotTokenHolder,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4648 test vectors, without the trailing '=' padding
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "MY"),
+        ("fo", "MZXQ"),
+        ("foo", "MZXW6"),
+        ("foob", "MZXW6YQ"),
+        ("fooba", "MZXW6YTB"),
+        ("foobar", "MZXW6YTBOI"),
+    ];
+
+    #[test]
+    fn base32_matches_rfc_vectors() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(base32_encode(plain.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn base32_round_trips_every_length() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in 0..=bytes.len() {
+            let encoded = base32_encode(&bytes[..len]);
+            assert_eq!(base32_decode(&encoded).unwrap(), &bytes[..len]);
+        }
+    }
+
+    #[test]
+    fn base32_rejects_bad_characters() {
+        for encoded in ["my", "MZXW6=", "MZXW0", "MZXW1", "MZ XQ"] {
+            assert_eq!(base32_decode(encoded), None, "{encoded}");
+        }
+    }
+
+    #[test]
+    fn base32_rejects_impossible_lengths() {
+        for encoded in ["M", "MZX", "MZXW6Y", "MZXW6YTBO"] {
+            assert_eq!(base32_decode(encoded), None, "{encoded}");
+        }
+    }
+
+    #[test]
+    fn base32_rejects_non_zero_trailing_bits() {
+        // "MY" is the only encoding of "f"; "MZ" sets one of its padding bits
+        for encoded in ["MZ", "MZXR", "MZXW7", "MZXW6YR", "MZXW6YTBOJ"] {
+            assert_eq!(base32_decode(encoded), None, "{encoded}");
+        }
+    }
+
+    fn voucher() -> RedemptionVoucher {
+        RedemptionVoucher {
+            version: VOUCHER_VERSION,
+            program_id: Pubkey::new_unique(),
+            subscription: Pubkey::new_unique(),
+            amount: 2,
+            nonce: 17,
+            expires_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn voucher_qr_round_trips() {
+        let voucher = voucher();
+        let signature = [7u8; 64];
+        let encoded = voucher.encode_qr(&signature);
+        assert!(encoded.starts_with(VOUCHER_QR_PREFIX));
+        assert_eq!(
+            RedemptionVoucher::decode_qr(&encoded).unwrap(),
+            (voucher, signature)
+        );
+    }
+
+    #[test]
+    fn voucher_qr_rejects_malformed_strings() {
+        let encoded = voucher().encode_qr(&[7u8; 64]);
+        let payload = encoded.strip_prefix(VOUCHER_QR_PREFIX).unwrap();
+
+        // Missing prefix, a dropped character and a truncated payload
+        assert!(RedemptionVoucher::decode_qr(payload).is_err());
+        assert!(RedemptionVoucher::decode_qr(&encoded[..encoded.len() - 1]).is_err());
+        assert!(RedemptionVoucher::decode_qr(&encoded[..encoded.len() - 8]).is_err());
+    }
+
+    fn voucher_nonces() -> VoucherNonces {
+        let data = [0u8; 8 + VoucherNonces::SPACE];
+        VoucherNonces::try_deserialize_unchecked(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn voucher_nonces_fill_the_window_once() {
+        let mut nonces = voucher_nonces();
+        let window = VOUCHER_NONCE_WINDOW as u64;
+        for nonce in 0..window {
+            nonces.consume(nonce).unwrap();
+        }
+        assert_eq!(nonces.base_nonce, 0);
+        assert!(nonces.bitmap.iter().all(|byte| *byte == u8::MAX));
+
+        for nonce in [0, window / 2, window - 1] {
+            assert!(nonces.consume(nonce).is_err());
+        }
+    }
+
+    #[test]
+    fn voucher_nonces_slide_past_the_window() {
+        let mut nonces = voucher_nonces();
+        let window = VOUCHER_NONCE_WINDOW as u64;
+        nonces.consume(0).unwrap();
+        nonces.consume(1).unwrap();
+        nonces.consume(window - 1).unwrap();
+
+        // The first nonce past the window drops only the oldest one
+        nonces.consume(window).unwrap();
+        assert_eq!(nonces.base_nonce, 1);
+        assert!(nonces.consume(0).is_err());
+        assert!(nonces.consume(1).is_err());
+        assert!(nonces.consume(window - 1).is_err());
+        assert!(nonces.consume(window).is_err());
+        nonces.consume(2).unwrap();
+
+        // A nonce reusing a forgotten slot is fresh
+        nonces.consume(window + 1).unwrap();
+        assert_eq!(nonces.base_nonce, 2);
+        assert!(nonces.consume(1).is_err());
+        assert!(nonces.consume(2).is_err());
+    }
+
+    #[test]
+    fn voucher_nonces_jump_a_whole_window_ahead() {
+        let mut nonces = voucher_nonces();
+        let window = VOUCHER_NONCE_WINDOW as u64;
+        for nonce in 0..window {
+            nonces.consume(nonce).unwrap();
+        }
+
+        // Skipping past everything tracked clears the ring
+        nonces.consume(3 * window).unwrap();
+        assert_eq!(nonces.base_nonce, 2 * window + 1);
+        assert_eq!(
+            nonces
+                .bitmap
+                .iter()
+                .map(|byte| byte.count_ones())
+                .sum::<u32>(),
+            1
+        );
+        assert!(nonces.consume(window - 1).is_err());
+        nonces.consume(2 * window + 1).unwrap();
+        assert!(nonces.consume(3 * window).is_err());
+
+        // Nonces run to the end of u64 without overflowing
+        nonces.consume(u64::MAX).unwrap();
+        assert_eq!(nonces.base_nonce, u64::MAX - window + 1);
+        assert!(nonces.consume(u64::MAX).is_err());
+        nonces.consume(u64::MAX - 1).unwrap();
+    }
 }
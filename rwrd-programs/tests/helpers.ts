@@ -1,4 +1,4 @@
-import { web3 } from "@coral-xyz/anchor";
+import { web3, BN } from "@coral-xyz/anchor";
 import crypto from "crypto";
 
 // Creates a keypair that will work with transaction signing
@@ -54,4 +54,100 @@ export async function airdropSol(
     console.error("Error in airdropSol:", err);
     throw err;
   }
-} 
\ No newline at end of file
+} 
+// Read the name, symbol and URI from a Token Metadata account
+// Layout: key (1) + update authority (32) + mint (32), then Borsh strings (u32 length + bytes)
+export async function fetchNftMetadata(
+  connection: web3.Connection,
+  metadata: web3.PublicKey
+): Promise<{ updateAuthority: web3.PublicKey; name: string; symbol: string; uri: string }> {
+  const info = await connection.getAccountInfo(metadata);
+  if (!info) {
+    throw new Error(`Metadata account ${metadata.toString()} not found`);
+  }
+
+  let offset = 1;
+  const updateAuthority = new web3.PublicKey(info.data.subarray(offset, offset + 32));
+  offset += 64;
+
+  const readString = (): string => {
+    const length = info.data.readUInt32LE(offset);
+    offset += 4;
+    const value = info.data.subarray(offset, offset + length).toString("utf8");
+    offset += length;
+    // Metaplex pads strings with null bytes
+    return value.replace(/\0/g, "");
+  };
+
+  const name = readString();
+  const symbol = readString();
+  const uri = readString();
+  return { updateAuthority, name, symbol, uri };
+}
+
+// Build the canonical redemption message a merchant signs for `redeem`
+// Layout: "rwrd:redeem" + manager program id + subscription + amount (u16) + remaining quota (u16)
+// + expiry timestamp (i64), integers little-endian
+export function redemptionMessage(
+  managerProgramId: web3.PublicKey,
+  subscription: web3.PublicKey,
+  redemptionAmount: number,
+  remainingQuota: number,
+  expiryTimestamp: BN
+): Buffer {
+  const amounts = Buffer.alloc(4);
+  amounts.writeUInt16LE(redemptionAmount, 0);
+  amounts.writeUInt16LE(remainingQuota, 2);
+
+  return Buffer.concat([
+    Buffer.from("rwrd:redeem"),
+    managerProgramId.toBuffer(),
+    subscription.toBuffer(),
+    amounts,
+    expiryTimestamp.toArrayLike(Buffer, "le", 8),
+  ]);
+}
+
+// Build the message a merchant signs for a redemption voucher: "rwrd:voucher" + manager program id
+// + the Borsh-serialized voucher (version, program, subscription, amount, nonce, expiry)
+export function voucherMessage(
+  managerProgramId: web3.PublicKey,
+  voucher: {
+    version: number;
+    programId: web3.PublicKey;
+    subscription: web3.PublicKey;
+    amount: number;
+    nonce: BN;
+    expiresAt: BN;
+  }
+): Buffer {
+  const amount = Buffer.alloc(2);
+  amount.writeUInt16LE(voucher.amount, 0);
+
+  return Buffer.concat([
+    Buffer.from("rwrd:voucher"),
+    managerProgramId.toBuffer(),
+    Buffer.from([voucher.version]),
+    voucher.programId.toBuffer(),
+    voucher.subscription.toBuffer(),
+    amount,
+    voucher.nonce.toArrayLike(Buffer, "le", 8),
+    voucher.expiresAt.toArrayLike(Buffer, "le", 8),
+  ]);
+}
+
+// Sign a message with the Ed25519 native program, returning the verification instruction to place
+// right before the program instruction and the signature it checks
+export function signWithEd25519(
+  signer: web3.Keypair,
+  message: Buffer
+): { instruction: web3.TransactionInstruction; signature: number[] } {
+  const instruction = web3.Ed25519Program.createInstructionWithPrivateKey({
+    privateKey: signer.secretKey,
+    message,
+  });
+
+  // Instruction data: 16-byte header, then the public key (32) and the signature (64)
+  const signature = Array.from(instruction.data.subarray(48, 112));
+  return { instruction, signature };
+}
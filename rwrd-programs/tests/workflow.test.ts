@@ -2,11 +2,19 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program, web3, BN } from "@coral-xyz/anchor";
 import { LiquidityPool } from "../target/types/liquidity_pool";
 import { MerchantRegistry } from "../target/types/merchant_registry";
+import { MockStrategy } from "../target/types/mock_strategy";
 import { SubscriptionFactory } from "../target/types/subscription_factory";
 import { SubscriptionManager } from "../target/types/subscription_manager";
 import * as token from "@solana/spl-token";
 import { expect } from "chai";
-import { createKeypair, airdropSol } from "./helpers";
+import {
+  createKeypair,
+  airdropSol,
+  fetchNftMetadata,
+  redemptionMessage,
+  signWithEd25519,
+  voucherMessage,
+} from "./helpers";
 import { Keypair } from "@solana/web3.js";
 
 describe("RWRD Protocol Workflow", () => {
@@ -21,6 +29,7 @@ describe("RWRD Protocol Workflow", () => {
   const liquidityPoolProgram = anchor.workspace.LiquidityPool as Program<LiquidityPool>;
   const subFactoryProgram = anchor.workspace.SubscriptionFactory as Program<SubscriptionFactory>;
   const subManagerProgram = anchor.workspace.SubscriptionManager as Program<SubscriptionManager>;
+  const mockStrategyProgram = anchor.workspace.MockStrategy as Program<MockStrategy>;
 
   // Test accounts
   const authority = wallet.publicKey;
@@ -30,29 +39,50 @@ describe("RWRD Protocol Workflow", () => {
   const merchantKeypair = Keypair.generate();
   let merchantWallet = merchantKeypair;
   let merchantTokenAccount: web3.PublicKey;
+  const treasuryKeypair = Keypair.generate();
+  let treasuryTokenAccount: web3.PublicKey;
 
   // Token accounts
   let mint: web3.PublicKey;
   let userTokenAccount: web3.PublicKey;
   let nftMint: web3.PublicKey;
   let userNftAccount: web3.PublicKey;
+  let nftMetadataPDA: web3.PublicKey;
 
   // PDAs
   // Merchant Registry
   let registryStatePDA: web3.PublicKey;
   let merchantAccountPDA: web3.PublicKey;
+  let trustScorePDA: web3.PublicKey;
+  let revenueLedgerPDA: web3.PublicKey;
 
   // Liquidity Pool
   let poolStatePDA: web3.PublicKey;
   let poolVaultPDA: web3.PublicKey;
+  let reserveVaultPDA: web3.PublicKey;
+  let receiptMintPDA: web3.PublicKey;
+  let userReceiptAccount: web3.PublicKey;
   let userDepositPDA: web3.PublicKey;
+  let depositorPDA: web3.PublicKey;
+  let merchantCreditPDA: web3.PublicKey;
   let merchantLoanPDA: web3.PublicKey;
+  let trustScoreAuthorityPDA: web3.PublicKey;
+  let strategyPDA: web3.PublicKey;
+  let strategyAuthorityPDA: web3.PublicKey;
+  let strategyTokenAccountPDA: web3.PublicKey;
+  const positionMintKeypair = Keypair.generate();
+  let positionPDA: web3.PublicKey;
+
+  // Mock Strategy
+  let strategyStatePDA: web3.PublicKey;
+  let strategyVaultPDA: web3.PublicKey;
 
   // Subscription Factory
   let factoryStatePDA: web3.PublicKey;
 
   // Subscription Manager
   let managerStatePDA: web3.PublicKey;
+  let revenueAuthorityPDA: web3.PublicKey;
   let subscriptionProgramPDA: web3.PublicKey;
   let subscriptionAccountPDA: web3.PublicKey;
 
@@ -68,13 +98,41 @@ describe("RWRD Protocol Workflow", () => {
   // Liquidity pool parameters
   const depositAmount = new BN(10_000_000); // 10 tokens
   const paymentAmount = new BN(2_000_000); // 2 tokens
-  const borrowAmount = new BN(5_000_000); // 5 tokens
+  const borrowAmount = new BN(50_000); // 0.05 tokens, within a new merchant's revenue-based limit
+  const loanTermDays = 90; // Repaid in installments over 90 days
+  const sweepPercent = 20; // 20% of incoming payments go to the loan
   const repayAmount = new BN(3_000_000); // 3 tokens
 
-  // Interest rate parameters
-  const baseRate = new BN(500); // 5% base interest rate (in basis points)
-  const utilizationSlope = new BN(3000); // 30% max increase based on utilization
+  // Interest rate parameters (all in basis points)
+  const rateModel = {
+    baseRate: new BN(500), // 5% base interest rate
+    utilizationSlope: new BN(1500), // +15% up to optimal utilization
+    optimalUtilization: new BN(8000), // Kink at 80% utilization
+    utilizationSlope2: new BN(6000), // +60% between 80% and 100% utilization
+    maxBorrowRate: new BN(10000), // 100% APR cap
+  };
   const protocolFeePercent = 5; // 5% protocol fee
+  const reserveFeePercent = 5; // 5% of loan interest funds the reserve
+  const loanPolicy = {
+    paymentIntervalDays: 30, // Installments due every 30 days
+    delinquencyGraceDays: 7, // Delinquent 7 days after a missed payment
+    defaultGraceDays: 30, // Defaulted 30 days after a missed payment
+    maxTermDays: 365, // Loans run for at most a year
+    lateFeeBps: 200, // 2% of the installment per missed payment
+    originationFeeBps: 100, // 1% of each loan at issue
+    originationReservePercent: 20, // 20% of the origination fee funds the reserve
+    originationFeeMode: { deducted: {} }, // Withheld from the disbursement
+  };
+  const liquidityPolicy = {
+    maxUtilizationBps: 9000, // At most 90% of deposits can be lent out
+    minLiquidityBps: 1000, // The vault keeps at least 10% of deposits on hand
+  };
+  const depositLimits = {
+    totalDepositCap: new BN(1_000_000_000), // 1,000 tokens of TVL at launch
+    maxUserDeposit: new BN(100_000_000), // 100 tokens per depositor
+    minDeposit: new BN(1_000), // 0.001 tokens per deposit
+  };
+  const strategyAllocationCap = new BN(1_000_000); // 1 token may be deployed to the mock strategy
 
   // Subscription parameters
   const programName = "premium-subscription";
@@ -83,6 +141,10 @@ describe("RWRD Protocol Workflow", () => {
   const durationDays = 30;
   const redemptionQuota = 10;
   const redemptionAmount = 2; // Using 2 out of 10 quota
+  const metadataBaseUri = "https://rwrd.example/metadata";
+
+  // Metaplex Token Metadata address; the local validator runs the mock-token-metadata stand-in there
+  const TOKEN_METADATA_PROGRAM_ID = new web3.PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
 
   before(async () => {
     try {
@@ -96,9 +158,6 @@ describe("RWRD Protocol Workflow", () => {
       const mintKeypair = web3.Keypair.generate();
       mint = mintKeypair.publicKey;
 
-      const nftMintKeypair = web3.Keypair.generate();
-      nftMint = nftMintKeypair.publicKey;
-
       const mintLamports = await provider.connection.getMinimumBalanceForRentExemption(
         token.MintLayout.span
       );
@@ -125,27 +184,8 @@ describe("RWRD Protocol Workflow", () => {
 
       createMintTx.add(createMintAccountIx, initMintIx);
 
-      // Create NFT mint account
-      const createNftMintAccountIx = web3.SystemProgram.createAccount({
-        fromPubkey: payer,
-        newAccountPubkey: nftMint,
-        lamports: mintLamports,
-        space: token.MintLayout.span,
-        programId: token.TOKEN_PROGRAM_ID
-      });
-
-      // Initialize NFT mint
-      const initNftMintIx = token.createInitializeMintInstruction(
-        nftMint,
-        0, // 0 decimals for NFT
-        payer,
-        payer
-      );
-
-      createMintTx.add(createNftMintAccountIx, initNftMintIx);
-
       // Send transaction with proper signing
-      await provider.sendAndConfirm(createMintTx, [mintKeypair, nftMintKeypair]);
+      await provider.sendAndConfirm(createMintTx, [mintKeypair]);
 
 
       // Create user token account
@@ -169,27 +209,6 @@ describe("RWRD Protocol Workflow", () => {
 
       }
 
-      // Create user NFT account
-      userNftAccount = await token.getAssociatedTokenAddress(
-        nftMint,
-        user
-      );
-
-      try {
-        await token.getAccount(provider.connection, userNftAccount);
-
-      } catch (e) {
-        const createUserNftAccountIx = token.createAssociatedTokenAccountInstruction(
-          payer,
-          userNftAccount,
-          user,
-          nftMint
-        );
-
-        await provider.sendAndConfirm(new web3.Transaction().add(createUserNftAccountIx));
-
-      }
-
       // Create merchant token account (ATA for merchant)
       merchantTokenAccount = await token.getAssociatedTokenAddress(
         mint,
@@ -209,6 +228,22 @@ describe("RWRD Protocol Workflow", () => {
 
       }
 
+      // Create treasury token account (receives protocol fees)
+      treasuryTokenAccount = await token.getAssociatedTokenAddress(
+        mint,
+        treasuryKeypair.publicKey
+      );
+      await provider.sendAndConfirm(
+        new web3.Transaction().add(
+          token.createAssociatedTokenAccountInstruction(
+            payer,
+            treasuryTokenAccount,
+            treasuryKeypair.publicKey,
+            mint
+          )
+        )
+      );
+
       // Mint tokens to user
       await token.mintTo(
         provider.connection,
@@ -231,8 +266,18 @@ describe("RWRD Protocol Workflow", () => {
         merchantRegistryProgram.programId
       );
 
+      [trustScorePDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("trust-score"), merchantWallet.publicKey.toBuffer()],
+        merchantRegistryProgram.programId
+      );
+
+      [revenueLedgerPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("revenue-ledger"), merchantWallet.publicKey.toBuffer()],
+        merchantRegistryProgram.programId
+      );
+
       [poolStatePDA] = web3.PublicKey.findProgramAddressSync(
-        [Buffer.from("pool_state")],
+        [Buffer.from("pool_state"), mint.toBuffer()],
         liquidityPoolProgram.programId
       );
 
@@ -241,16 +286,78 @@ describe("RWRD Protocol Workflow", () => {
         liquidityPoolProgram.programId
       );
 
+      [reserveVaultPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("reserve_vault"), mint.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+
+      [receiptMintPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("receipt_mint"), mint.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+      userReceiptAccount = await token.getAssociatedTokenAddress(receiptMintPDA, user);
+
       [userDepositPDA] = web3.PublicKey.findProgramAddressSync(
         [Buffer.from("user_deposit"), user.toBuffer(), mint.toBuffer()],
         liquidityPoolProgram.programId
       );
 
+      [depositorPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("depositor"), user.toBuffer(), mint.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+
+      [merchantCreditPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("merchant_credit"), merchantWallet.publicKey.toBuffer(), mint.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+
+      // The merchant's first loan; later loans are numbered by the credit account's loan count
       [merchantLoanPDA] = web3.PublicKey.findProgramAddressSync(
-        [Buffer.from("merchant_loan"), merchantWallet.publicKey.toBuffer(), mint.toBuffer()],
+        [
+          Buffer.from("merchant_loan"),
+          merchantWallet.publicKey.toBuffer(),
+          mint.toBuffer(),
+          new BN(0).toArrayLike(Buffer, "le", 8),
+        ],
+        liquidityPoolProgram.programId
+      );
+
+      [trustScoreAuthorityPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("trust_score_authority")],
+        liquidityPoolProgram.programId
+      );
+
+      [positionPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("position"), positionMintKeypair.publicKey.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+
+      [strategyPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("strategy"), mint.toBuffer(), mockStrategyProgram.programId.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+
+      [strategyAuthorityPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("strategy_authority"), strategyPDA.toBuffer()],
+        liquidityPoolProgram.programId
+      );
+
+      [strategyTokenAccountPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("strategy_token_account"), strategyPDA.toBuffer()],
         liquidityPoolProgram.programId
       );
 
+      [strategyStatePDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("strategy_state"), mint.toBuffer()],
+        mockStrategyProgram.programId
+      );
+
+      [strategyVaultPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("strategy_vault"), mint.toBuffer()],
+        mockStrategyProgram.programId
+      );
+
       [factoryStatePDA] = web3.PublicKey.findProgramAddressSync(
         [Buffer.from("factory_state")],
         subFactoryProgram.programId
@@ -261,6 +368,11 @@ describe("RWRD Protocol Workflow", () => {
         subManagerProgram.programId
       );
 
+      [revenueAuthorityPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("revenue_authority")],
+        subManagerProgram.programId
+      );
+
       // Derive subscription program PDA
       const programSeedPrefix = Buffer.from("subscription_program");
       const encodedProgramName = Buffer.from(programName);
@@ -276,13 +388,19 @@ describe("RWRD Protocol Workflow", () => {
         await liquidityPoolProgram.methods
           .initialize(
             authority,
-            baseRate,
-            utilizationSlope,
-            protocolFeePercent
+            rateModel,
+            protocolFeePercent,
+            reserveFeePercent,
+            loanPolicy,
+            liquidityPolicy,
+            depositLimits
           )
           .accounts({
             poolState: poolStatePDA,
             poolVault: poolVaultPDA,
+            reserveVault: reserveVaultPDA,
+            receiptMint: receiptMintPDA,
+            treasury: treasuryTokenAccount,
             payer: payer,
             tokenMint: mint,
             tokenProgram: token.TOKEN_PROGRAM_ID,
@@ -356,6 +474,7 @@ describe("RWRD Protocol Workflow", () => {
             .accounts({
               merchantAccount: merchantAccountPDA,
               merchantWallet: merchantWallet.publicKey,
+              registryState: registryStatePDA,
               payer: payer,
               systemProgram: web3.SystemProgram.programId,
             })
@@ -395,6 +514,120 @@ describe("RWRD Protocol Workflow", () => {
         throw e;
       }
     });
+
+    it("should let the liquidity pool record trust score activity", async () => {
+      try {
+        await merchantRegistryProgram.methods
+          .setScoreAuthority(trustScoreAuthorityPDA)
+          .accounts({
+            registryState: registryStatePDA,
+            authority: authority,
+          })
+          .rpc();
+
+        // Subscription sales are recorded by the subscription manager
+        await merchantRegistryProgram.methods
+          .setRevenueAuthority(revenueAuthorityPDA)
+          .accounts({
+            registryState: registryStatePDA,
+            authority: authority,
+          })
+          .rpc();
+
+        const registryState = await merchantRegistryProgram.account.registryState.fetch(registryStatePDA);
+        expect(registryState.scoreAuthority.toString()).to.equal(trustScoreAuthorityPDA.toString());
+        expect(registryState.revenueAuthority.toString()).to.equal(revenueAuthorityPDA.toString());
+      } catch (e) {
+        console.error("Error setting score authority:", e);
+        throw e;
+      }
+    });
+
+    it("should refuse to migrate a registry already on the current layout", async () => {
+      try {
+        let rejected = false;
+        try {
+          await merchantRegistryProgram.methods
+            .migrateRegistryState()
+            .accounts({
+              registryState: registryStatePDA,
+              authority: authority,
+              systemProgram: web3.SystemProgram.programId,
+            })
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+      } catch (e) {
+        console.error("Error migrating registry state:", e);
+        throw e;
+      }
+    });
+
+    it("should initialize the merchant trust score", async () => {
+      try {
+        let trustScoreExists = false;
+        try {
+          await merchantRegistryProgram.account.trustScore.fetch(trustScorePDA);
+          trustScoreExists = true;
+        } catch (e) {
+
+        }
+
+        if (!trustScoreExists) {
+          await merchantRegistryProgram.methods
+            .initializeTrustScore()
+            .accounts({
+              trustScore: trustScorePDA,
+              merchantAccount: merchantAccountPDA,
+              payer: payer,
+              systemProgram: web3.SystemProgram.programId,
+            })
+            .rpc();
+        }
+
+        // Scores are bounded to 1-5, and a new merchant has no history yet
+        const trustScore = await merchantRegistryProgram.account.trustScore.fetch(trustScorePDA);
+        expect(trustScore.merchantWallet.toString()).to.equal(merchantWallet.publicKey.toString());
+        expect(trustScore.score).to.be.within(1, 5);
+        expect(trustScore.paymentCount).to.equal(0);
+      } catch (e) {
+        console.error("Error initializing trust score:", e);
+        throw e;
+      }
+    });
+
+    it("should initialize the merchant revenue ledger", async () => {
+      try {
+        let ledgerExists = false;
+        try {
+          await merchantRegistryProgram.account.revenueLedger.fetch(revenueLedgerPDA);
+          ledgerExists = true;
+        } catch (e) {
+
+        }
+
+        if (!ledgerExists) {
+          await merchantRegistryProgram.methods
+            .initializeRevenueLedger()
+            .accounts({
+              revenueLedger: revenueLedgerPDA,
+              merchantAccount: merchantAccountPDA,
+              payer: payer,
+              systemProgram: web3.SystemProgram.programId,
+            })
+            .rpc();
+        }
+
+        const revenueLedger = await merchantRegistryProgram.account.revenueLedger.fetch(revenueLedgerPDA);
+        expect(revenueLedger.merchantWallet.toString()).to.equal(merchantWallet.publicKey.toString());
+        expect(revenueLedger.monthlyRevenue.length).to.equal(12);
+      } catch (e) {
+        console.error("Error initializing revenue ledger:", e);
+        throw e;
+      }
+    });
   });
 
   describe("2. Liquidity Pool", () => {
@@ -415,14 +648,23 @@ describe("RWRD Protocol Workflow", () => {
           const tx = await liquidityPoolProgram.methods
             .initialize(
               authority,
-              baseRate,
-              utilizationSlope,
-              protocolFeePercent
+              rateModel,
+              protocolFeePercent,
+              reserveFeePercent,
+              loanPolicy,
+              liquidityPolicy,
+              depositLimits
             )
             .accounts({
               poolState: poolStatePDA,
+              poolVault: poolVaultPDA,
+              reserveVault: reserveVaultPDA,
+              treasury: treasuryTokenAccount,
               payer: payer,
+              tokenMint: mint,
+              tokenProgram: token.TOKEN_PROGRAM_ID,
               systemProgram: web3.SystemProgram.programId,
+              rent: web3.SYSVAR_RENT_PUBKEY,
             })
             .rpc();
 
@@ -432,12 +674,121 @@ describe("RWRD Protocol Workflow", () => {
         // Verify pool state
         const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
         expect(poolState.authority.toString()).to.equal(authority.toString());
+        // Each mint gets its own pool state bound to its vault
+        expect(poolState.tokenMint.toString()).to.equal(mint.toString());
+        expect(poolState.poolVault.toString()).to.equal(poolVaultPDA.toString());
+        expect(poolState.treasury.toString()).to.equal(treasuryTokenAccount.toString());
       } catch (e) {
         console.error("Error initializing liquidity pool:", e);
         throw e;
       }
     });
 
+    it("should update the interest rate model", async () => {
+      try {
+        const updatedRateModel = { ...rateModel, utilizationSlope2: new BN(7500) };
+
+        await liquidityPoolProgram.methods
+          .updateRateModel(updatedRateModel)
+          .accounts({
+            poolState: poolStatePDA,
+            authority: authority,
+          })
+          .rpc();
+
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.rateModel.utilizationSlope2.toNumber()).to.equal(7500);
+
+        // A kink at 100% utilization leaves no room for the second slope
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .updateRateModel({ ...rateModel, optimalUtilization: new BN(10000) })
+            .accounts({
+              poolState: poolStatePDA,
+              authority: authority,
+            })
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+      } catch (e) {
+        console.error("Error updating interest rate model:", e);
+        throw e;
+      }
+    });
+
+    it("should update the liquidity policy", async () => {
+      try {
+        const updatedLiquidityPolicy = { ...liquidityPolicy, minLiquidityBps: 1500 };
+
+        await liquidityPoolProgram.methods
+          .updateLiquidityPolicy(updatedLiquidityPolicy)
+          .accounts({
+            poolState: poolStatePDA,
+            authority: authority,
+          })
+          .rpc();
+
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.liquidityPolicy.minLiquidityBps).to.equal(1500);
+
+        // A zero utilization cap would shut off borrowing entirely
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .updateLiquidityPolicy({ ...liquidityPolicy, maxUtilizationBps: 0 })
+            .accounts({
+              poolState: poolStatePDA,
+              authority: authority,
+            })
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+      } catch (e) {
+        console.error("Error updating liquidity policy:", e);
+        throw e;
+      }
+    });
+
+    it("should update the deposit limits", async () => {
+      try {
+        const raisedDepositLimits = { ...depositLimits, totalDepositCap: new BN(2_000_000_000) };
+
+        await liquidityPoolProgram.methods
+          .updateDepositLimits(raisedDepositLimits)
+          .accounts({
+            poolState: poolStatePDA,
+            authority: authority,
+          })
+          .rpc();
+
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.depositLimits.totalDepositCap.eq(raisedDepositLimits.totalDepositCap)).to.be.true;
+
+        // A per-user limit above the pool cap could never be reached
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .updateDepositLimits({ ...depositLimits, maxUserDeposit: new BN(2_000_000_000) })
+            .accounts({
+              poolState: poolStatePDA,
+              authority: authority,
+            })
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+      } catch (e) {
+        console.error("Error updating deposit limits:", e);
+        throw e;
+      }
+    });
+
     it("should add liquidity to the pool", async () => {
       try {
         // Create pool vault if it doesn't exist
@@ -526,8 +877,17 @@ describe("RWRD Protocol Workflow", () => {
             .deposit(depositAmount)
             .accounts({
               userDeposit: userDepositPDA,
+              receiptMint: receiptMintPDA,
+              userReceiptAccount: userReceiptAccount,
               user: user,
+              depositor: depositorPDA,
+              position: null,
+              previousDepositor: null,
+              positionMint: null,
+              positionTokenAccount: null,
               poolState: poolStatePDA,
+              registryState: registryStatePDA,
+              merchantRegistryProgram: merchantRegistryProgram.programId,
               poolVault: poolVaultPDA,
               userTokenAccount: userTokenAccount,
               tokenMint: mint,
@@ -543,21 +903,172 @@ describe("RWRD Protocol Workflow", () => {
         // Verify deposit
         const userDeposit = await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
         expect(userDeposit.depositedAmount.gte(new BN(0))).to.be.true;
+        expect(userDeposit.shares.gt(new BN(0))).to.be.true;
+
+        // Shares are minted against the pool supply index
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.totalShares.gte(userDeposit.shares)).to.be.true;
+        expect(poolState.supplyIndex.gte(new BN(0))).to.be.true;
+
+        // Each share is backed by one receipt token
+        const receiptAccount = await token.getAccount(provider.connection, userReceiptAccount);
+        expect(Number(receiptAccount.amount)).to.equal(userDeposit.shares.toNumber());
+
+        // Deposits below the minimum are refused
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .deposit(depositLimits.minDeposit.sub(new BN(1)))
+            .accounts({
+              userDeposit: userDepositPDA,
+              receiptMint: receiptMintPDA,
+              userReceiptAccount: userReceiptAccount,
+              user: user,
+              depositor: depositorPDA,
+              position: null,
+              previousDepositor: null,
+              positionMint: null,
+              positionTokenAccount: null,
+              poolState: poolStatePDA,
+              registryState: registryStatePDA,
+              merchantRegistryProgram: merchantRegistryProgram.programId,
+              poolVault: poolVaultPDA,
+              userTokenAccount: userTokenAccount,
+              tokenMint: mint,
+              tokenProgram: token.TOKEN_PROGRAM_ID,
+              associatedTokenProgram: token.ASSOCIATED_TOKEN_PROGRAM_ID,
+              systemProgram: web3.SystemProgram.programId,
+            })
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
       } catch (e) {
         console.error("Error depositing to liquidity pool:", e);
         throw e;
       }
     });
 
-    it("should withdraw from liquidity pool", async () => {
+    it("should let the guardian pause deposits until governance unpauses", async () => {
       try {
-        // Check if user deposit exists
-        let depositExists = false;
+        const guardianKeypair = web3.Keypair.generate();
+        await merchantRegistryProgram.methods
+          .setGuardian(guardianKeypair.publicKey)
+          .accounts({
+            registryState: registryStatePDA,
+            governance: governance,
+          })
+          .rpc();
 
-        try {
-          await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
-          depositExists = true;
-        } catch (e) {
+        const pauseDeposits = 1; // PAUSE_DEPOSITS
+        await merchantRegistryProgram.methods
+          .pause(pauseDeposits)
+          .accounts({
+            registryState: registryStatePDA,
+            signer: guardianKeypair.publicKey,
+          })
+          .signers([guardianKeypair])
+          .rpc();
+
+        let registryState = await merchantRegistryProgram.account.registryState.fetch(registryStatePDA);
+        expect(registryState.pausedActions & pauseDeposits).to.equal(pauseDeposits);
+
+        // Deposits are refused while paused
+        let depositRejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .deposit(new BN(1))
+            .accounts({
+              userDeposit: userDepositPDA,
+              receiptMint: receiptMintPDA,
+              userReceiptAccount: userReceiptAccount,
+              user: user,
+              depositor: depositorPDA,
+              position: null,
+              previousDepositor: null,
+              positionMint: null,
+              positionTokenAccount: null,
+              poolState: poolStatePDA,
+              registryState: registryStatePDA,
+              merchantRegistryProgram: merchantRegistryProgram.programId,
+              poolVault: poolVaultPDA,
+              userTokenAccount: userTokenAccount,
+              tokenMint: mint,
+              tokenProgram: token.TOKEN_PROGRAM_ID,
+              associatedTokenProgram: token.ASSOCIATED_TOKEN_PROGRAM_ID,
+              systemProgram: web3.SystemProgram.programId,
+            })
+            .rpc();
+        } catch (e) {
+          depositRejected = true;
+        }
+        expect(depositRejected).to.be.true;
+
+        // The guardian can pause but only governance can unpause
+        let unpauseRejected = false;
+        try {
+          await merchantRegistryProgram.methods
+            .unpause(pauseDeposits)
+            .accounts({
+              registryState: registryStatePDA,
+              governance: guardianKeypair.publicKey,
+            })
+            .signers([guardianKeypair])
+            .rpc();
+        } catch (e) {
+          unpauseRejected = true;
+        }
+        expect(unpauseRejected).to.be.true;
+
+        await merchantRegistryProgram.methods
+          .unpause(pauseDeposits)
+          .accounts({
+            registryState: registryStatePDA,
+            governance: governance,
+          })
+          .rpc();
+
+        registryState = await merchantRegistryProgram.account.registryState.fetch(registryStatePDA);
+        expect(registryState.pausedActions).to.equal(0);
+      } catch (e) {
+        console.error("Error pausing deposits:", e);
+        throw e;
+      }
+    });
+
+    it("should calculate earned interest", async () => {
+      try {
+        const earned: BN = await liquidityPoolProgram.methods
+          .calculateEarned()
+          .accounts({
+            userWallet: user,
+            poolState: poolStatePDA,
+            userDeposit: userDepositPDA,
+            positionTokenAccount: null,
+            receiptMint: receiptMintPDA,
+            userReceiptAccount: userReceiptAccount,
+            tokenMint: mint,
+          })
+          .view();
+
+        // Nothing is borrowed yet, so no interest has accrued
+        expect(earned.gte(new BN(0))).to.be.true;
+      } catch (e) {
+        console.error("Error calculating earned interest:", e);
+        throw e;
+      }
+    });
+
+    it("should withdraw from liquidity pool", async () => {
+      try {
+        // Check if user deposit exists
+        let depositExists = false;
+
+        try {
+          await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
+          depositExists = true;
+        } catch (e) {
 
           return;
         }
@@ -585,7 +1096,12 @@ describe("RWRD Protocol Workflow", () => {
             .withdraw(withdrawAmount)
             .accounts({
               userDeposit: userDepositPDA,
+              receiptMint: receiptMintPDA,
+              userReceiptAccount: userReceiptAccount,
               user: user,
+              depositor: depositorPDA,
+              positionMint: null,
+              positionTokenAccount: null,
               poolState: poolStatePDA,
               poolVault: poolVaultPDA,
               userTokenAccount: userTokenAccount,
@@ -607,6 +1123,614 @@ describe("RWRD Protocol Workflow", () => {
         throw e;
       }
     });
+
+    // Withdrawal requests are numbered by the pool's queue position
+    const withdrawalRequestPDA = (requestId: BN) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("withdrawal_request"), mint.toBuffer(), requestId.toArrayLike(Buffer, "le", 8)],
+        liquidityPoolProgram.programId
+      )[0];
+
+    // Pairs of (withdrawal request, owner token account) from the queue head to its tail
+    const withdrawalQueueAccounts = async () => {
+      const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+      const accounts = [];
+      for (
+        let requestId = poolState.withdrawalQueueHead;
+        requestId.lt(poolState.withdrawalQueueTail);
+        requestId = requestId.add(new BN(1))
+      ) {
+        const requestPDA = withdrawalRequestPDA(requestId);
+        const request = await liquidityPoolProgram.account.withdrawalRequest.fetch(requestPDA);
+        const ownerTokenAccount = await token.getAssociatedTokenAddress(mint, request.user);
+        accounts.push(
+          { pubkey: requestPDA, isWritable: true, isSigner: false },
+          { pubkey: ownerTokenAccount, isWritable: true, isSigner: false }
+        );
+      }
+      return accounts;
+    };
+
+    // Holding the whole vault back leaves nothing for instant withdrawals, so requests have to queue
+    const setMinLiquidity = async (minLiquidityBps: number) => {
+      const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+      await liquidityPoolProgram.methods
+        .updateLiquidityPolicy({ ...poolState.liquidityPolicy, minLiquidityBps })
+        .accounts({
+          poolState: poolStatePDA,
+          authority: authority,
+        })
+        .rpc();
+      return poolState.liquidityPolicy.minLiquidityBps;
+    };
+
+    const requestWithdrawalAccounts = (requestPDA: web3.PublicKey) => ({
+      userDeposit: userDepositPDA,
+      receiptMint: receiptMintPDA,
+      userReceiptAccount: userReceiptAccount,
+      user: user,
+      depositor: depositorPDA,
+      poolState: poolStatePDA,
+      poolVault: poolVaultPDA,
+      withdrawalRequest: requestPDA,
+      tokenMint: mint,
+      tokenProgram: token.TOKEN_PROGRAM_ID,
+      systemProgram: web3.SystemProgram.programId,
+    });
+
+    const processWithdrawalQueue = async (remainingAccounts: web3.AccountMeta[]) =>
+      liquidityPoolProgram.methods
+        .processWithdrawalQueue()
+        .accounts({
+          poolState: poolStatePDA,
+          poolVault: poolVaultPDA,
+          tokenMint: mint,
+          tokenProgram: token.TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+
+    it("should queue a withdrawal and fill it through the crank", async () => {
+      try {
+        const userDeposit = await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
+        const requestAmount = BN.min(userDeposit.depositedAmount.div(new BN(4)), new BN(1_000));
+        expect(requestAmount.gt(new BN(0))).to.be.true;
+
+        const poolStateBefore = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolStateBefore.pendingWithdrawals.toNumber()).to.equal(0);
+        const requestPDA = withdrawalRequestPDA(poolStateBefore.withdrawalQueueTail);
+
+        // While the vault can pay it, the withdrawal has to be taken instantly
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .requestWithdrawal(requestAmount)
+            .accounts(requestWithdrawalAccounts(requestPDA))
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+
+        const minLiquidityBps = await setMinLiquidity(10_000);
+        await liquidityPoolProgram.methods
+          .requestWithdrawal(requestAmount)
+          .accounts(requestWithdrawalAccounts(requestPDA))
+          .rpc();
+
+        // Instant withdrawals wait behind the queued request
+        rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .withdraw(new BN(1))
+            .accounts({
+              userDeposit: userDepositPDA,
+              receiptMint: receiptMintPDA,
+              userReceiptAccount: userReceiptAccount,
+              user: user,
+              depositor: depositorPDA,
+              positionMint: null,
+              positionTokenAccount: null,
+              poolState: poolStatePDA,
+              poolVault: poolVaultPDA,
+              userTokenAccount: userTokenAccount,
+              tokenMint: mint,
+              tokenProgram: token.TOKEN_PROGRAM_ID,
+              systemProgram: web3.SystemProgram.programId,
+            })
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+
+        // The crank refuses accounts it can't trust to be this pool's writable requests
+        const queueAccounts = await withdrawalQueueAccounts();
+        for (const remainingAccounts of [
+          [{ ...queueAccounts[0], isWritable: false }, queueAccounts[1]],
+          [{ pubkey: userDepositPDA, isWritable: true, isSigner: false }, queueAccounts[1]],
+          [{ pubkey: userReceiptAccount, isWritable: true, isSigner: false }, queueAccounts[1]],
+        ]) {
+          rejected = false;
+          try {
+            await processWithdrawalQueue(remainingAccounts);
+          } catch (e) {
+            rejected = true;
+          }
+          expect(rejected).to.be.true;
+        }
+
+        // Nothing is filled while the pool is short
+        await processWithdrawalQueue(queueAccounts);
+        let request = await liquidityPoolProgram.account.withdrawalRequest.fetch(requestPDA);
+        expect("pending" in request.status).to.be.true;
+        expect(request.filledAmount.toNumber()).to.equal(0);
+
+        // Once liquidity frees up the crank pays the request out in full
+        await setMinLiquidity(minLiquidityBps);
+        const balanceBefore = (await token.getAccount(provider.connection, userTokenAccount)).amount;
+        await processWithdrawalQueue(await withdrawalQueueAccounts());
+
+        request = await liquidityPoolProgram.account.withdrawalRequest.fetch(requestPDA);
+        expect("filled" in request.status).to.be.true;
+        expect(request.filledAmount.gte(requestAmount)).to.be.true;
+        const balanceAfter = (await token.getAccount(provider.connection, userTokenAccount)).amount;
+        expect(Number(balanceAfter - balanceBefore)).to.equal(request.filledAmount.toNumber());
+
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.pendingWithdrawals.toNumber()).to.equal(0);
+        expect(poolState.withdrawalQueueHead.eq(poolState.withdrawalQueueTail)).to.be.true;
+      } catch (e) {
+        console.error("Error processing the withdrawal queue:", e);
+        throw e;
+      }
+    });
+
+    it("should cancel a pending withdrawal request", async () => {
+      try {
+        const userDeposit = await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
+        const requestAmount = BN.min(userDeposit.depositedAmount.div(new BN(4)), new BN(1_000));
+        expect(requestAmount.gt(new BN(0))).to.be.true;
+
+        const poolStateBefore = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        const requestPDA = withdrawalRequestPDA(poolStateBefore.withdrawalQueueTail);
+        const minLiquidityBps = await setMinLiquidity(10_000);
+        await liquidityPoolProgram.methods
+          .requestWithdrawal(requestAmount)
+          .accounts(requestWithdrawalAccounts(requestPDA))
+          .rpc();
+        await setMinLiquidity(minLiquidityBps);
+
+        await liquidityPoolProgram.methods
+          .cancelWithdrawalRequest()
+          .accounts({
+            userDeposit: userDepositPDA,
+            receiptMint: receiptMintPDA,
+            userReceiptAccount: userReceiptAccount,
+            user: user,
+            depositor: depositorPDA,
+            poolState: poolStatePDA,
+            withdrawalRequest: requestPDA,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+        // The locked shares and principal go back into the position
+        const request = await liquidityPoolProgram.account.withdrawalRequest.fetch(requestPDA);
+        expect("cancelled" in request.status).to.be.true;
+        const updatedUserDeposit = await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
+        expect(updatedUserDeposit.shares.eq(userDeposit.shares)).to.be.true;
+        expect(updatedUserDeposit.depositedAmount.eq(userDeposit.depositedAmount)).to.be.true;
+
+        // The crank steps past the cancelled request
+        await processWithdrawalQueue(await withdrawalQueueAccounts());
+
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.withdrawalQueueHead.eq(poolState.withdrawalQueueTail)).to.be.true;
+      } catch (e) {
+        console.error("Error cancelling a withdrawal request:", e);
+        throw e;
+      }
+    });
+
+    it("should redeem transferred receipt tokens for the underlying", async () => {
+      try {
+        const receiptShares = 1_000;
+        const merchantReceiptAccount = await token.getAssociatedTokenAddress(
+          receiptMintPDA,
+          merchantWallet.publicKey
+        );
+        await provider.sendAndConfirm(
+          new web3.Transaction().add(
+            token.createAssociatedTokenAccountIdempotentInstruction(
+              payer,
+              merchantReceiptAccount,
+              merchantWallet.publicKey,
+              receiptMintPDA
+            ),
+            token.createTransferInstruction(userReceiptAccount, merchantReceiptAccount, user, receiptShares)
+          )
+        );
+
+        // The merchant holds receipts without a deposit position and can still cash them out
+        const poolStateBefore = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        const balanceBefore = (await token.getAccount(provider.connection, merchantTokenAccount)).amount;
+        await liquidityPoolProgram.methods
+          .redeemReceipts(new BN(receiptShares))
+          .accounts({
+            user: merchantWallet.publicKey,
+            poolState: poolStatePDA,
+            receiptMint: receiptMintPDA,
+            userReceiptAccount: merchantReceiptAccount,
+            poolVault: poolVaultPDA,
+            userTokenAccount: merchantTokenAccount,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+          })
+          .signers([merchantWallet])
+          .rpc();
+
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolStateBefore.totalShares.sub(poolState.totalShares).toNumber()).to.equal(receiptShares);
+        const balanceAfter = (await token.getAccount(provider.connection, merchantTokenAccount)).amount;
+        expect(balanceAfter > balanceBefore).to.be.true;
+        const merchantReceipts = await token.getAccount(provider.connection, merchantReceiptAccount);
+        expect(Number(merchantReceipts.amount)).to.equal(0);
+
+        // The user's position gives up the shares that left with the receipts the next time it is used
+        await liquidityPoolProgram.methods
+          .deposit(depositLimits.minDeposit)
+          .accounts({
+            userDeposit: userDepositPDA,
+            receiptMint: receiptMintPDA,
+            userReceiptAccount: userReceiptAccount,
+            user: user,
+            depositor: depositorPDA,
+            position: null,
+            previousDepositor: null,
+            positionMint: null,
+            positionTokenAccount: null,
+            poolState: poolStatePDA,
+            registryState: registryStatePDA,
+            merchantRegistryProgram: merchantRegistryProgram.programId,
+            poolVault: poolVaultPDA,
+            userTokenAccount: userTokenAccount,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+            associatedTokenProgram: token.ASSOCIATED_TOKEN_PROGRAM_ID,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .rpc();
+        const userDeposit = await liquidityPoolProgram.account.userDepositAccount.fetch(userDepositPDA);
+        const userReceipts = await token.getAccount(provider.connection, userReceiptAccount);
+        expect(userDeposit.shares.toString()).to.equal(userReceipts.amount.toString());
+      } catch (e) {
+        console.error("Error redeeming receipt tokens:", e);
+        throw e;
+      }
+    });
+
+    it("should track a deposit with a position NFT and burn it once emptied", async () => {
+      try {
+        const positionMint = positionMintKeypair.publicKey;
+        const positionDepositAmount = new BN(1_000_000); // 1 token
+        const userPositionAccount = await token.getAssociatedTokenAddress(positionMint, user);
+        const depositorBefore = await liquidityPoolProgram.account.depositorAccount.fetch(depositorPDA);
+        await liquidityPoolProgram.methods
+          .deposit(positionDepositAmount)
+          .accounts({
+            userDeposit: null,
+            user: user,
+            depositor: depositorPDA,
+            receiptMint: null,
+            userReceiptAccount: null,
+            position: positionPDA,
+            previousDepositor: null,
+            positionMint: positionMint,
+            positionTokenAccount: userPositionAccount,
+            poolState: poolStatePDA,
+            registryState: registryStatePDA,
+            merchantRegistryProgram: merchantRegistryProgram.programId,
+            poolVault: poolVaultPDA,
+            userTokenAccount: userTokenAccount,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+            associatedTokenProgram: token.ASSOCIATED_TOKEN_PROGRAM_ID,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .signers([positionMintKeypair])
+          .rpc();
+
+        const position = await liquidityPoolProgram.account.userDepositAccount.fetch(positionPDA);
+        expect(position.positionMint.toString()).to.equal(positionMint.toString());
+        expect(position.depositedAmount.eq(positionDepositAmount)).to.be.true;
+
+        // The NFT position counts toward the same per-wallet cap as the wallet position
+        let depositor = await liquidityPoolProgram.account.depositorAccount.fetch(depositorPDA);
+        expect(depositor.depositedAmount.sub(depositorBefore.depositedAmount).eq(positionDepositAmount)).to.be.true;
+        expect(Number((await token.getAccount(provider.connection, userPositionAccount)).amount)).to.equal(1);
+
+        // Hand the NFT to the merchant; the position follows it
+        const merchantPositionAccount = await token.getAssociatedTokenAddress(
+          positionMint,
+          merchantWallet.publicKey
+        );
+        await provider.sendAndConfirm(
+          new web3.Transaction().add(
+            token.createAssociatedTokenAccountInstruction(
+              payer,
+              merchantPositionAccount,
+              merchantWallet.publicKey,
+              positionMint
+            ),
+            token.createTransferInstruction(userPositionAccount, merchantPositionAccount, user, 1)
+          )
+        );
+
+        // The position moves onto the merchant's cap, freeing the original depositor's
+        const [merchantDepositorPDA] = web3.PublicKey.findProgramAddressSync(
+          [Buffer.from("depositor"), merchantWallet.publicKey.toBuffer(), mint.toBuffer()],
+          liquidityPoolProgram.programId
+        );
+        await liquidityPoolProgram.methods
+          .reattributePosition()
+          .accounts({
+            payer: payer,
+            position: positionPDA,
+            positionTokenAccount: merchantPositionAccount,
+            previousDepositor: depositorPDA,
+            depositor: merchantDepositorPDA,
+            tokenMint: mint,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .rpc();
+
+        depositor = await liquidityPoolProgram.account.depositorAccount.fetch(depositorPDA);
+        expect(depositor.depositedAmount.eq(depositorBefore.depositedAmount)).to.be.true;
+        let merchantDepositor = await liquidityPoolProgram.account.depositorAccount.fetch(merchantDepositorPDA);
+        expect(merchantDepositor.depositedAmount.eq(positionDepositAmount)).to.be.true;
+        const reattributed = await liquidityPoolProgram.account.userDepositAccount.fetch(positionPDA);
+        expect(reattributed.user.toString()).to.equal(merchantWallet.publicKey.toString());
+
+        const withdrawAccounts = (holder: web3.PublicKey, positionTokenAccount: web3.PublicKey, holderTokenAccount: web3.PublicKey) => ({
+          userDeposit: positionPDA,
+          user: holder,
+          depositor: merchantDepositorPDA, // The position now counts against its holder
+          receiptMint: null,
+          userReceiptAccount: null,
+          positionMint: positionMint,
+          positionTokenAccount: positionTokenAccount,
+          poolState: poolStatePDA,
+          poolVault: poolVaultPDA,
+          userTokenAccount: holderTokenAccount,
+          tokenMint: mint,
+          tokenProgram: token.TOKEN_PROGRAM_ID,
+          systemProgram: web3.SystemProgram.programId,
+        });
+
+        // Earnings on an NFT position are read against whoever holds the NFT
+        const earnedAccounts = (holder: web3.PublicKey, positionTokenAccount: web3.PublicKey) => ({
+          userWallet: holder,
+          poolState: poolStatePDA,
+          userDeposit: positionPDA,
+          positionTokenAccount: positionTokenAccount,
+          receiptMint: null,
+          userReceiptAccount: null,
+          tokenMint: mint,
+        });
+        const positionEarned: BN = await liquidityPoolProgram.methods
+          .calculateEarned()
+          .accounts(earnedAccounts(merchantWallet.publicKey, merchantPositionAccount))
+          .view();
+        expect(positionEarned.gte(new BN(0))).to.be.true;
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .calculateEarned()
+            .accounts(earnedAccounts(user, userPositionAccount))
+            .view();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+
+        // The original depositor no longer controls the position
+        rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .withdraw(new BN(1))
+            .accounts(withdrawAccounts(user, userPositionAccount, userTokenAccount))
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+
+        // The holder withdraws everything the position is worth, which burns the NFT
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        const positionValue = position.shares.mul(poolState.supplyIndex).div(new BN("1000000000000"));
+        await liquidityPoolProgram.methods
+          .withdraw(BN.min(positionValue, position.depositedAmount))
+          .accounts(withdrawAccounts(merchantWallet.publicKey, merchantPositionAccount, merchantTokenAccount))
+          .signers([merchantWallet])
+          .rpc();
+
+        const positionInfo = await provider.connection.getAccountInfo(positionPDA);
+        expect(positionInfo).to.be.null;
+        merchantDepositor = await liquidityPoolProgram.account.depositorAccount.fetch(merchantDepositorPDA);
+        expect(merchantDepositor.depositedAmount.toNumber()).to.equal(0);
+        const nftMint = await token.getMint(provider.connection, positionMint);
+        expect(Number(nftMint.supply)).to.equal(0);
+      } catch (e) {
+        console.error("Error using a position NFT:", e);
+        throw e;
+      }
+    });
+
+    it("should allocate idle funds to a strategy, harvest its yield and unwind it", async () => {
+      try {
+        // The strategy only ever deals with the pool's strategy authority, never the pool state
+        await mockStrategyProgram.methods
+          .initialize(strategyAuthorityPDA)
+          .accounts({
+            strategyState: strategyStatePDA,
+            strategyVault: strategyVaultPDA,
+            payer: payer,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+            systemProgram: web3.SystemProgram.programId,
+            rent: web3.SYSVAR_RENT_PUBKEY,
+          })
+          .rpc();
+
+        await liquidityPoolProgram.methods
+          .approveStrategy(strategyAllocationCap)
+          .accounts({
+            poolState: poolStatePDA,
+            strategy: strategyPDA,
+            strategyProgram: mockStrategyProgram.programId,
+            strategyAuthority: strategyAuthorityPDA,
+            strategyTokenAccount: strategyTokenAccountPDA,
+            authority: authority,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .rpc();
+
+        const strategyAccounts = {
+          poolState: poolStatePDA,
+          strategy: strategyPDA,
+          strategyProgram: mockStrategyProgram.programId,
+          strategyAuthority: strategyAuthorityPDA,
+          strategyTokenAccount: strategyTokenAccountPDA,
+          authority: authority,
+          poolVault: poolVaultPDA,
+          tokenMint: mint,
+          tokenProgram: token.TOKEN_PROGRAM_ID,
+        };
+        const mockStrategyAccounts = [
+          { pubkey: strategyStatePDA, isSigner: false, isWritable: true },
+          { pubkey: strategyVaultPDA, isSigner: false, isWritable: true },
+        ];
+        const vaultBalance = async (address: web3.PublicKey) =>
+          Number((await token.getAccount(provider.connection, address)).amount);
+
+        // No loans are out yet, so interest accrual leaves total_deposited alone
+        let poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.totalBorrowed.toNumber()).to.equal(0);
+
+        // Allocations past the strategy's cap are rejected
+        let rejected = false;
+        try {
+          await liquidityPoolProgram.methods
+            .allocateToStrategy(strategyAllocationCap.add(new BN(1)))
+            .accounts(strategyAccounts)
+            .remainingAccounts(mockStrategyAccounts)
+            .rpc();
+        } catch (e) {
+          rejected = true;
+        }
+        expect(rejected).to.be.true;
+
+        const allocation = 100_000; // 0.1 tokens
+        let totalDeposited = poolState.totalDeposited.toNumber();
+        let poolVaultBalance = await vaultBalance(poolVaultPDA);
+        await liquidityPoolProgram.methods
+          .allocateToStrategy(new BN(allocation))
+          .accounts(strategyAccounts)
+          .remainingAccounts(mockStrategyAccounts)
+          .rpc();
+
+        // Moving funds into the strategy doesn't change what depositors are owed
+        poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.totalAllocated.toNumber()).to.equal(allocation);
+        expect(poolState.totalDeposited.toNumber()).to.equal(totalDeposited);
+        let strategy = await liquidityPoolProgram.account.strategyAccount.fetch(strategyPDA);
+        expect(strategy.allocated.toNumber()).to.equal(allocation);
+        expect(strategy.markedValue.toNumber()).to.equal(allocation);
+        expect(await vaultBalance(poolVaultPDA)).to.equal(poolVaultBalance - allocation);
+        expect(await vaultBalance(strategyVaultPDA)).to.equal(allocation);
+        expect(await vaultBalance(strategyTokenAccountPDA)).to.equal(0);
+
+        // Simulate yield by topping up the strategy vault directly
+        const strategyYield = 10_000;
+        await token.transfer(
+          provider.connection,
+          provider.wallet.payer,
+          userTokenAccount,
+          strategyVaultPDA,
+          payer,
+          strategyYield
+        );
+
+        const protocolFee = Math.floor((strategyYield * poolState.protocolFeePercent) / 100);
+        const accruedProtocolFees = poolState.accruedProtocolFees.toNumber();
+        await liquidityPoolProgram.methods
+          .harvestStrategy()
+          .accounts(strategyAccounts)
+          .remainingAccounts(mockStrategyAccounts)
+          .rpc();
+
+        // Depositors earn the yield, less the protocol fee
+        strategy = await liquidityPoolProgram.account.strategyAccount.fetch(strategyPDA);
+        expect(strategy.totalHarvested.toNumber()).to.equal(strategyYield);
+        expect(strategy.markedValue.toNumber()).to.equal(allocation);
+        poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.totalDeposited.toNumber()).to.equal(totalDeposited + strategyYield - protocolFee);
+        expect(poolState.accruedProtocolFees.toNumber()).to.equal(accruedProtocolFees + protocolFee);
+        expect(await vaultBalance(poolVaultPDA)).to.equal(poolVaultBalance - allocation + strategyYield);
+        expect(await vaultBalance(strategyVaultPDA)).to.equal(allocation);
+
+        // A loss inside the strategy is marked into the pool as soon as it is reported
+        const strategyLoss = 20_000;
+        await mockStrategyProgram.methods
+          .simulateLoss(new BN(strategyLoss))
+          .accounts({
+            strategyState: strategyStatePDA,
+            strategyVault: strategyVaultPDA,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+        totalDeposited = poolState.totalDeposited.toNumber();
+        await liquidityPoolProgram.methods
+          .markStrategy()
+          .accounts(strategyAccounts)
+          .remainingAccounts(mockStrategyAccounts)
+          .rpc();
+
+        strategy = await liquidityPoolProgram.account.strategyAccount.fetch(strategyPDA);
+        expect(strategy.allocated.toNumber()).to.equal(allocation);
+        expect(strategy.markedValue.toNumber()).to.equal(allocation - strategyLoss);
+        poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.totalDeposited.toNumber()).to.equal(totalDeposited - strategyLoss);
+
+        // Unwinding returns what is left; the loss was already written off, so nothing more moves
+        totalDeposited = poolState.totalDeposited.toNumber();
+        poolVaultBalance = await vaultBalance(poolVaultPDA);
+        await liquidityPoolProgram.methods
+          .unwindStrategy(new BN(allocation))
+          .accounts(strategyAccounts)
+          .remainingAccounts(mockStrategyAccounts)
+          .rpc();
+
+        strategy = await liquidityPoolProgram.account.strategyAccount.fetch(strategyPDA);
+        expect(strategy.allocated.toNumber()).to.equal(0);
+        expect(strategy.markedValue.toNumber()).to.equal(0);
+        poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolState.totalAllocated.toNumber()).to.equal(0);
+        expect(poolState.totalDeposited.toNumber()).to.equal(totalDeposited);
+        expect(await vaultBalance(poolVaultPDA)).to.equal(poolVaultBalance + allocation - strategyLoss);
+        expect(await vaultBalance(strategyVaultPDA)).to.equal(0);
+        expect(await vaultBalance(strategyTokenAccountPDA)).to.equal(0);
+      } catch (e) {
+        console.error("Error running the yield strategy:", e);
+        throw e;
+      }
+    });
   });
 
   describe("3. Subscription Factory", () => {
@@ -669,6 +1793,7 @@ describe("RWRD Protocol Workflow", () => {
             .accounts({
               factoryState: factoryStatePDA,
               subscriptionProgram: subscriptionProgramPDA,
+              registryState: registryStatePDA,
               merchantAccount: merchantAccountPDA,
               merchantWallet: merchantWallet.publicKey,
               merchantRegistry: merchantRegistryProgram.programId,
@@ -708,7 +1833,7 @@ describe("RWRD Protocol Workflow", () => {
 
         if (!managerInitialized) {
           const tx = await subManagerProgram.methods
-            .initialize(authority)
+            .initialize(authority, metadataBaseUri)
             .accounts({
               managerState: managerStatePDA,
               payer: payer,
@@ -722,6 +1847,7 @@ describe("RWRD Protocol Workflow", () => {
         // Verify manager state
         const managerState = await subManagerProgram.account.managerState.fetch(managerStatePDA);
         expect(managerState.authority.toString()).to.equal(authority.toString());
+        expect(managerState.metadataBaseUri).to.equal(metadataBaseUri);
       } catch (e) {
         console.error("Error initializing subscription manager:", e);
         throw e;
@@ -730,10 +1856,14 @@ describe("RWRD Protocol Workflow", () => {
 
     it("should subscribe to a program", async () => {
       try {
-        // Calculate subscription account PDA
+        // The program creates the subscription NFT mint, the user's NFT account and its metadata
+        const nftMintKeypair = web3.Keypair.generate();
+        nftMint = nftMintKeypair.publicKey;
+
+        // Calculate subscription account PDA, keyed by the subscription NFT mint
         const subscriptionSeedPrefix = Buffer.from("subscription");
         [subscriptionAccountPDA] = web3.PublicKey.findProgramAddressSync(
-          [subscriptionSeedPrefix, user.toBuffer(), subscriptionProgramPDA.toBuffer()],
+          [subscriptionSeedPrefix, nftMint.toBuffer()],
           subManagerProgram.programId
         );
 
@@ -749,29 +1879,16 @@ describe("RWRD Protocol Workflow", () => {
         }
 
         if (!alreadySubscribed) {
-          // Mint NFT to user if not already done
-          try {
-            const tokenInfo = await token.getAccount(provider.connection, userNftAccount);
-            if (tokenInfo.amount === BigInt(0)) {
-              await token.mintTo(
-                provider.connection,
-                provider.wallet.payer,
-                nftMint,
-                userNftAccount,
-                payer,
-                1
-              );
-
-            }
-          } catch (e) {
-            console.error("Error checking or minting NFT:", e);
-            throw e;
-          }
+          userNftAccount = await token.getAssociatedTokenAddress(nftMint, user);
+          [nftMetadataPDA] = web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), nftMint.toBuffer()],
+            TOKEN_METADATA_PROGRAM_ID
+          );
 
           // Get the actual pool state PDA to ensure we're using the right one
           // We need to get the PDA directly from the liquidity pool program
           const [correctPoolStatePDA] = web3.PublicKey.findProgramAddressSync(
-            [Buffer.from("pool_state")],
+            [Buffer.from("pool_state"), mint.toBuffer()],
             liquidityPoolProgram.programId
           );
 
@@ -830,13 +1947,21 @@ describe("RWRD Protocol Workflow", () => {
               userTokenAccount: userTokenAccount,
               merchantTokenAccount: merchantTokenAccount,
               paymentTokenMint: mint,
+              registryState: registryStatePDA,
+              revenueLedger: revenueLedgerPDA,
+              revenueAuthority: revenueAuthorityPDA,
+              merchantRegistryProgram: merchantRegistryProgram.programId,
               nftMint: nftMint,
+              userNftAccount: userNftAccount,
+              nftMetadata: nftMetadataPDA,
               liquidityPoolProgram: liquidityPoolProgram.programId,
               tokenProgram: token.TOKEN_PROGRAM_ID,
               associatedTokenProgram: token.ASSOCIATED_TOKEN_PROGRAM_ID,
+              tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
               systemProgram: web3.SystemProgram.programId,
+              rent: web3.SYSVAR_RENT_PUBKEY,
             })
-            .signers([provider.wallet.payer])
+            .signers([provider.wallet.payer, nftMintKeypair])
             .rpc();
 
 
@@ -845,6 +1970,30 @@ describe("RWRD Protocol Workflow", () => {
         // Verify subscription
         const subscriptionAccount = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
         expect(subscriptionAccount.user.toString()).to.equal(user.toString());
+        expect(subscriptionAccount.tokenMint.toString()).to.equal(nftMint.toString());
+
+        // The user holds the one-of-one subscription NFT and its metadata exists
+        const nftAccount = await token.getAccount(provider.connection, userNftAccount);
+        expect(Number(nftAccount.amount)).to.equal(1);
+        const nftMintInfo = await token.getMint(provider.connection, nftMint);
+        expect(Number(nftMintInfo.supply)).to.equal(1);
+        expect(nftMintInfo.decimals).to.equal(0);
+        const metadataInfo = await provider.connection.getAccountInfo(nftMetadataPDA);
+        expect(metadataInfo.owner.toString()).to.equal(TOKEN_METADATA_PROGRAM_ID.toString());
+
+        // The metadata URI carries the subscription's attributes
+        const metadata = await fetchNftMetadata(provider.connection, nftMetadataPDA);
+        expect(metadata.updateAuthority.toString()).to.equal(managerStatePDA.toString());
+        expect(metadata.name).to.equal(programName);
+        expect(metadata.symbol).to.equal("RWRD");
+        expect(metadata.uri).to.equal(
+          `${metadataBaseUri}/${nftMint.toString()}?quota=${subscriptionAccount.remainingQuota}` +
+          `&expiry=${subscriptionAccount.expiryTimestamp.toString()}&last_redeemed=0&status=active`
+        );
+
+        // The sale counts toward the merchant's revenue
+        const revenueLedger = await merchantRegistryProgram.account.revenueLedger.fetch(revenueLedgerPDA);
+        expect(revenueLedger.totalRevenue.gte(subscriptionPrice)).to.be.true;
       } catch (e) {
         console.error("Error subscribing to program:", e);
         throw e;
@@ -866,25 +2015,106 @@ describe("RWRD Protocol Workflow", () => {
         }
 
         if (subscriptionExists) {
-          const tx = await subManagerProgram.methods
-            .redeem(
-              redemptionAmount,
-              // Dummy signature for testing - in production this would be a real signature
-              Array(64).fill(0)
+          const subscriptionBefore = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
+          const message = redemptionMessage(
+            subManagerProgram.programId,
+            subscriptionAccountPDA,
+            redemptionAmount,
+            subscriptionBefore.remainingQuota,
+            subscriptionBefore.expiryTimestamp
+          );
+          const redeemAccounts = {
+            subscription: subscriptionAccountPDA,
+            subscriptionProgram: subscriptionProgramPDA,
+            signer: user,
+            holderNftAccount: userNftAccount,
+            merchantWallet: merchantWallet.publicKey,
+            registryState: registryStatePDA,
+            instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+            managerState: managerStatePDA,
+            nftMetadata: nftMetadataPDA,
+            tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+          };
+
+          // Without the merchant's Ed25519 verification the redemption is rejected
+          let errorCode = "";
+          try {
+            await subManagerProgram.methods
+              .redeem(redemptionAmount, Array(64).fill(0))
+              .accounts(redeemAccounts)
+              .rpc();
+          } catch (e) {
+            errorCode = e.error?.errorCode?.code;
+          }
+          expect(errorCode).to.equal("MissingSignatureVerification");
+
+          // A signature from anyone but the merchant is rejected too
+          const impostor = signWithEd25519(Keypair.generate(), message);
+          errorCode = "";
+          try {
+            await subManagerProgram.methods
+              .redeem(redemptionAmount, impostor.signature)
+              .accounts(redeemAccounts)
+              .preInstructions([impostor.instruction])
+              .rpc();
+          } catch (e) {
+            errorCode = e.error?.errorCode?.code;
+          }
+          expect(errorCode).to.equal("MerchantSignerMismatch");
+
+          // The merchant's signature over a different amount doesn't cover this redemption
+          const otherAmount = signWithEd25519(
+            merchantWallet,
+            redemptionMessage(
+              subManagerProgram.programId,
+              subscriptionAccountPDA,
+              redemptionAmount + 1,
+              subscriptionBefore.remainingQuota,
+              subscriptionBefore.expiryTimestamp
             )
-            .accounts({
-              subscription: subscriptionAccountPDA,
-              subscriptionProgram: subscriptionProgramPDA,
-              signer: user,
-              merchantWallet: merchantWallet.publicKey,
-            })
+          );
+          errorCode = "";
+          try {
+            await subManagerProgram.methods
+              .redeem(redemptionAmount, otherAmount.signature)
+              .accounts(redeemAccounts)
+              .preInstructions([otherAmount.instruction])
+              .rpc();
+          } catch (e) {
+            errorCode = e.error?.errorCode?.code;
+          }
+          expect(errorCode).to.equal("RedemptionMessageMismatch");
+
+          const merchantApproval = signWithEd25519(merchantWallet, message);
+          const tx = await subManagerProgram.methods
+            .redeem(redemptionAmount, merchantApproval.signature)
+            .accounts(redeemAccounts)
+            .preInstructions([merchantApproval.instruction])
             .rpc();
 
 
 
           // Verify redemption
           const subscriptionAccount = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
-          expect(subscriptionAccount.remainingQuota).to.be.at.least(redemptionAmount);
+          expect(subscriptionAccount.remainingQuota).to.equal(subscriptionBefore.remainingQuota - redemptionAmount);
+
+          // The same approval can't be replayed: the quota it signed over has changed
+          errorCode = "";
+          try {
+            await subManagerProgram.methods
+              .redeem(redemptionAmount, merchantApproval.signature)
+              .accounts(redeemAccounts)
+              .preInstructions([merchantApproval.instruction])
+              .rpc();
+          } catch (e) {
+            errorCode = e.error?.errorCode?.code;
+          }
+          expect(errorCode).to.equal("RedemptionMessageMismatch");
+
+          // The NFT metadata follows the new quota and last redemption
+          const metadata = await fetchNftMetadata(provider.connection, nftMetadataPDA);
+          expect(metadata.uri).to.contain(`quota=${subscriptionAccount.remainingQuota}&`);
+          expect(metadata.uri).to.contain(`&last_redeemed=${subscriptionAccount.lastRedeemedAt.toString()}&`);
         }
       } catch (e) {
         console.error("Error redeeming benefits:", e);
@@ -892,6 +2122,186 @@ describe("RWRD Protocol Workflow", () => {
       }
     });
 
+    it("should redeem a signed voucher once", async () => {
+      try {
+        const subscriptionBefore = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
+        const [voucherNoncesPDA] = web3.PublicKey.findProgramAddressSync(
+          [Buffer.from("voucher_nonces"), subscriptionAccountPDA.toBuffer()],
+          subManagerProgram.programId
+        );
+        const voucherAccounts = {
+          subscription: subscriptionAccountPDA,
+          signer: user,
+          holderNftAccount: userNftAccount,
+          subscriptionProgram: subscriptionProgramPDA,
+          voucherNonces: voucherNoncesPDA,
+          registryState: registryStatePDA,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          managerState: managerStatePDA,
+          nftMetadata: nftMetadataPDA,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+          systemProgram: web3.SystemProgram.programId,
+        };
+
+        // The merchant terminal signs a voucher valid for the next ten minutes
+        const voucher = {
+          version: 1,
+          programId: subscriptionProgramPDA,
+          subscription: subscriptionAccountPDA,
+          amount: 1,
+          nonce: new BN(7),
+          expiresAt: new BN(Math.floor(Date.now() / 1000) + 600),
+        };
+        const merchantApproval = signWithEd25519(
+          merchantWallet,
+          voucherMessage(subManagerProgram.programId, voucher)
+        );
+
+        const tx = await subManagerProgram.methods
+          .redeemVoucher(voucher, merchantApproval.signature)
+          .accounts(voucherAccounts)
+          .preInstructions([merchantApproval.instruction])
+          .rpc();
+
+        const subscriptionAccount = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
+        expect(subscriptionAccount.remainingQuota).to.equal(subscriptionBefore.remainingQuota - voucher.amount);
+        const voucherNonces = await subManagerProgram.account.voucherNonces.fetch(voucherNoncesPDA);
+        expect(voucherNonces.baseNonce.toNumber()).to.equal(0);
+        expect(voucherNonces.bitmap[0]).to.equal(1 << voucher.nonce.toNumber());
+
+        // Scanning the same voucher again is rejected
+        let errorCode = "";
+        try {
+          await subManagerProgram.methods
+            .redeemVoucher(voucher, merchantApproval.signature)
+            .accounts(voucherAccounts)
+            .preInstructions([merchantApproval.instruction])
+            .rpc();
+        } catch (e) {
+          errorCode = e.error?.errorCode?.code;
+        }
+        expect(errorCode).to.equal("VoucherNonceUsed");
+
+        // So is a voucher past its expiry, even with a fresh nonce
+        const expiredVoucher = { ...voucher, nonce: new BN(8), expiresAt: new BN(Math.floor(Date.now() / 1000) - 600) };
+        const expiredApproval = signWithEd25519(
+          merchantWallet,
+          voucherMessage(subManagerProgram.programId, expiredVoucher)
+        );
+        errorCode = "";
+        try {
+          await subManagerProgram.methods
+            .redeemVoucher(expiredVoucher, expiredApproval.signature)
+            .accounts(voucherAccounts)
+            .preInstructions([expiredApproval.instruction])
+            .rpc();
+        } catch (e) {
+          errorCode = e.error?.errorCode?.code;
+        }
+        expect(errorCode).to.equal("VoucherExpired");
+      } catch (e) {
+        console.error("Error redeeming voucher:", e);
+        throw e;
+      }
+    });
+
+    it("should sync subscription NFT metadata", async () => {
+      try {
+        const subscriptionAccount = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
+        [nftMetadataPDA] = web3.PublicKey.findProgramAddressSync(
+          [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), subscriptionAccount.tokenMint.toBuffer()],
+          TOKEN_METADATA_PROGRAM_ID
+        );
+
+        // Anyone may refresh the metadata; the subscription is still active here
+        const tx = await subManagerProgram.methods
+          .syncMetadata()
+          .accounts({
+            subscription: subscriptionAccountPDA,
+            subscriptionProgram: subscriptionProgramPDA,
+            managerState: managerStatePDA,
+            nftMetadata: nftMetadataPDA,
+            tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+          })
+          .rpc();
+
+        const metadata = await fetchNftMetadata(provider.connection, nftMetadataPDA);
+        expect(metadata.uri).to.equal(
+          `${metadataBaseUri}/${subscriptionAccount.tokenMint.toString()}?quota=${subscriptionAccount.remainingQuota}` +
+          `&expiry=${subscriptionAccount.expiryTimestamp.toString()}` +
+          `&last_redeemed=${subscriptionAccount.lastRedeemedAt.toString()}&status=active`
+        );
+      } catch (e) {
+        console.error("Error syncing subscription metadata:", e);
+        throw e;
+      }
+    });
+
+    it("should hand a transferred subscription to the new holder", async () => {
+      try {
+        // The user sells the subscription NFT to a new holder
+        const newHolder = Keypair.generate();
+        await airdropSol(provider.connection, newHolder.publicKey, 1);
+        const newHolderNftAccount = await token.getAssociatedTokenAddress(nftMint, newHolder.publicKey);
+        await provider.sendAndConfirm(
+          new web3.Transaction().add(
+            token.createAssociatedTokenAccountInstruction(payer, newHolderNftAccount, newHolder.publicKey, nftMint),
+            token.createTransferInstruction(userNftAccount, newHolderNftAccount, user, 1)
+          )
+        );
+
+        const subscriptionBefore = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
+        const merchantApproval = signWithEd25519(
+          merchantWallet,
+          redemptionMessage(
+            subManagerProgram.programId,
+            subscriptionAccountPDA,
+            1,
+            subscriptionBefore.remainingQuota,
+            subscriptionBefore.expiryTimestamp
+          )
+        );
+        const redeemAccounts = {
+          subscription: subscriptionAccountPDA,
+          subscriptionProgram: subscriptionProgramPDA,
+          merchantWallet: merchantWallet.publicKey,
+          registryState: registryStatePDA,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          managerState: managerStatePDA,
+          nftMetadata: nftMetadataPDA,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+        };
+
+        // The original buyer no longer holds the NFT and can't redeem
+        let errorCode = "";
+        try {
+          await subManagerProgram.methods
+            .redeem(1, merchantApproval.signature)
+            .accounts({ ...redeemAccounts, signer: user, holderNftAccount: userNftAccount })
+            .preInstructions([merchantApproval.instruction])
+            .rpc();
+        } catch (e) {
+          errorCode = e.error?.errorCode?.code;
+        }
+        expect(errorCode).to.equal("NotTokenHolder");
+
+        // The new holder can
+        await subManagerProgram.methods
+          .redeem(1, merchantApproval.signature)
+          .accounts({ ...redeemAccounts, signer: newHolder.publicKey, holderNftAccount: newHolderNftAccount })
+          .preInstructions([merchantApproval.instruction])
+          .signers([newHolder])
+          .rpc();
+
+        const subscriptionAccount = await subManagerProgram.account.subscriptionAccount.fetch(subscriptionAccountPDA);
+        expect(subscriptionAccount.remainingQuota).to.equal(subscriptionBefore.remainingQuota - 1);
+        expect(subscriptionAccount.user.toString()).to.equal(user.toString());
+      } catch (e) {
+        console.error("Error using a transferred subscription:", e);
+        throw e;
+      }
+    });
+
     it("should borrow from liquidity pool", async () => {
       try {
         // Check if merchant loan account exists
@@ -905,17 +2315,27 @@ describe("RWRD Protocol Workflow", () => {
 
         }
 
+        let merchantBalanceBefore = BigInt(0);
+        let reserveBalanceBefore = BigInt(0);
         if (!loanExists) {
+          merchantBalanceBefore = (await token.getAccount(provider.connection, merchantTokenAccount)).amount;
+          reserveBalanceBefore = (await token.getAccount(provider.connection, reserveVaultPDA)).amount;
+
           const tx = await liquidityPoolProgram.methods
-            .borrow(borrowAmount)
+            .borrow(borrowAmount, loanTermDays)
             .accounts({
               poolState: poolStatePDA,
               poolVault: poolVaultPDA,
+              reserveVault: reserveVaultPDA,
               merchantLoan: merchantLoanPDA,
+              merchantCredit: merchantCreditPDA,
+              registryState: registryStatePDA,
               merchantAccount: merchantAccountPDA,
+              trustScore: trustScorePDA,
+              revenueLedger: revenueLedgerPDA,
               merchantTokenAccount: merchantTokenAccount,
               merchantWallet: merchantWallet.publicKey,
-              merchantRegistry: merchantRegistryProgram.programId,
+              merchantRegistryProgram: merchantRegistryProgram.programId,
               tokenMint: mint,
               tokenProgram: token.TOKEN_PROGRAM_ID,
               systemProgram: web3.SystemProgram.programId,
@@ -929,12 +2349,95 @@ describe("RWRD Protocol Workflow", () => {
         // Verify loan
         const merchantLoan = await liquidityPoolProgram.account.merchantLoanAccount.fetch(merchantLoanPDA);
         expect(merchantLoan.principal.gte(new BN(0))).to.be.true;
+
+        // A 90 day term with 30 day installments is split into three payments
+        if (!loanExists) {
+          expect(merchantLoan.termDays).to.equal(loanTermDays);
+          expect(merchantLoan.installmentAmount.toNumber()).to.equal(
+            Math.ceil(borrowAmount.toNumber() / 3)
+          );
+          expect(merchantLoan.nextDueDate.lte(merchantLoan.maturityDate)).to.be.true;
+
+          // The origination fee is withheld from the disbursement and split with the reserve
+          const originationFee = Math.floor((borrowAmount.toNumber() * loanPolicy.originationFeeBps) / 10_000);
+          const feeToReserve = Math.floor((originationFee * loanPolicy.originationReservePercent) / 100);
+          expect(merchantLoan.principal.eq(borrowAmount)).to.be.true;
+
+          const merchantBalanceAfter = (await token.getAccount(provider.connection, merchantTokenAccount)).amount;
+          expect(Number(merchantBalanceAfter - merchantBalanceBefore)).to.equal(
+            borrowAmount.toNumber() - originationFee
+          );
+          const reserveBalanceAfter = (await token.getAccount(provider.connection, reserveVaultPDA)).amount;
+          expect(Number(reserveBalanceAfter - reserveBalanceBefore)).to.equal(feeToReserve);
+        }
+
+        // The merchant's exposure tracks every loan they have open
+        const merchantCredit = await liquidityPoolProgram.account.merchantCreditAccount.fetch(merchantCreditPDA);
+        expect(merchantCredit.loanCount.gte(new BN(1))).to.be.true;
+        expect(merchantLoan.loanIndex.toNumber()).to.equal(0);
+        if (!loanExists) {
+          expect(merchantCredit.openLoans).to.equal(1);
+          expect(merchantCredit.outstandingPrincipal.eq(borrowAmount)).to.be.true;
+        }
       } catch (e) {
         console.error("Error borrowing from liquidity pool:", e);
         throw e;
       }
     });
 
+    it("should opt in to the repayment sweep", async () => {
+      try {
+        let loanExists = false;
+        try {
+          await liquidityPoolProgram.account.merchantLoanAccount.fetch(merchantLoanPDA);
+          loanExists = true;
+        } catch (e) {
+          return;
+        }
+
+        if (loanExists) {
+          await liquidityPoolProgram.methods
+            .setRepaymentSweep(sweepPercent)
+            .accounts({
+              merchantWallet: merchantWallet.publicKey,
+              merchantLoan: merchantLoanPDA,
+              merchantCredit: merchantCreditPDA,
+              tokenMint: mint,
+            })
+            .signers([merchantKeypair])
+            .rpc();
+
+          const merchantLoan = await liquidityPoolProgram.account.merchantLoanAccount.fetch(merchantLoanPDA);
+          expect(merchantLoan.sweepPercent).to.equal(sweepPercent);
+
+          // Payments to the merchant must now carry this loan
+          const merchantCredit = await liquidityPoolProgram.account.merchantCreditAccount.fetch(merchantCreditPDA);
+          expect(merchantCredit.sweepLoan.eq(merchantLoan.loanIndex)).to.be.true;
+
+          // More than the whole payment can't be swept
+          let rejected = false;
+          try {
+            await liquidityPoolProgram.methods
+              .setRepaymentSweep(101)
+              .accounts({
+                merchantWallet: merchantWallet.publicKey,
+                merchantLoan: merchantLoanPDA,
+                merchantCredit: merchantCreditPDA,
+                tokenMint: mint,
+              })
+              .signers([merchantKeypair])
+              .rpc();
+          } catch (e) {
+            rejected = true;
+          }
+          expect(rejected).to.be.true;
+        }
+      } catch (e) {
+        console.error("Error setting repayment sweep:", e);
+        throw e;
+      }
+    });
+
     it("should repay loan to liquidity pool", async () => {
       try {
         // Only test repayment if a loan exists
@@ -954,7 +2457,13 @@ describe("RWRD Protocol Workflow", () => {
             .accounts({
               poolState: poolStatePDA,
               poolVault: poolVaultPDA,
+              reserveVault: reserveVaultPDA,
               merchantLoan: merchantLoanPDA,
+              merchantCredit: merchantCreditPDA,
+              registryState: registryStatePDA,
+              trustScore: trustScorePDA,
+              trustScoreAuthority: trustScoreAuthorityPDA,
+              merchantRegistryProgram: merchantRegistryProgram.programId,
               merchantTokenAccount: merchantTokenAccount,
               merchantWallet: merchantWallet.publicKey,
               tokenMint: mint,
@@ -976,6 +2485,122 @@ describe("RWRD Protocol Workflow", () => {
       }
     });
 
+    it("should not mark a current loan delinquent", async () => {
+      try {
+        let loanExists = false;
+        try {
+          await liquidityPoolProgram.account.merchantLoanAccount.fetch(merchantLoanPDA);
+          loanExists = true;
+        } catch (e) {
+          return;
+        }
+
+        if (loanExists) {
+          // The loan was just issued, so it is well inside its payment interval
+          let rejected = false;
+          try {
+            await liquidityPoolProgram.methods
+              .markDelinquent()
+              .accounts({
+                poolState: poolStatePDA,
+                merchantLoan: merchantLoanPDA,
+                merchantCredit: merchantCreditPDA,
+                registryState: registryStatePDA,
+                trustScore: trustScorePDA,
+                trustScoreAuthority: trustScoreAuthorityPDA,
+                merchantRegistryProgram: merchantRegistryProgram.programId,
+                tokenMint: mint,
+              })
+              .rpc();
+          } catch (e) {
+            rejected = true;
+          }
+          expect(rejected).to.be.true;
+
+          const merchantLoan = await liquidityPoolProgram.account.merchantLoanAccount.fetch(merchantLoanPDA);
+          expect("delinquent" in merchantLoan.status).to.be.false;
+        }
+      } catch (e) {
+        console.error("Error checking loan delinquency:", e);
+        throw e;
+      }
+    });
+
+    it("should cover a defaulted loan from the reserve", async () => {
+      try {
+        const merchantLoan = await liquidityPoolProgram.account.merchantLoanAccount.fetch(merchantLoanPDA);
+        const reserveVault = await token.getAccount(provider.connection, reserveVaultPDA);
+        const poolBefore = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+
+        // The loan is still open after the partial repayment and its origination fee funded the reserve
+        expect("active" in merchantLoan.status).to.be.true;
+        expect(reserveVault.amount > BigInt(0)).to.be.true;
+
+        const coverAmount = new BN(1);
+        await liquidityPoolProgram.methods
+          .coverLoanDefault(coverAmount)
+          .accounts({
+            governance: governance,
+            registryState: registryStatePDA,
+            merchantRegistryProgram: merchantRegistryProgram.programId,
+            poolState: poolStatePDA,
+            merchantLoan: merchantLoanPDA,
+            merchantCredit: merchantCreditPDA,
+            poolVault: poolVaultPDA,
+            reserveVault: reserveVaultPDA,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+        const reserveAfter = await token.getAccount(provider.connection, reserveVaultPDA);
+        expect(Number(reserveVault.amount - reserveAfter.amount)).to.equal(coverAmount.toNumber());
+
+        // The loan is paid down as if the merchant had repaid it; depositors are untouched
+        const poolAfter = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        expect(poolAfter.totalBorrowed.lt(poolBefore.totalBorrowed)).to.be.true;
+        expect(poolAfter.totalDeposited.gte(poolBefore.totalDeposited)).to.be.true;
+      } catch (e) {
+        console.error("Error covering loan from reserve:", e);
+        throw e;
+      }
+    });
+
+    it("should collect protocol fees to the treasury", async () => {
+      try {
+        const poolState = await liquidityPoolProgram.account.poolState.fetch(poolStatePDA);
+        const poolVault = await token.getAccount(provider.connection, poolVaultPDA);
+        const collectable = BN.min(
+          poolState.accruedProtocolFees,
+          new BN(poolVault.amount.toString())
+        );
+
+        // The treasury's share of the origination fee is owed from the moment the loan was issued
+        expect(collectable.gt(new BN(0))).to.be.true;
+
+        const treasuryBefore = await token.getAccount(provider.connection, treasuryTokenAccount);
+
+        await liquidityPoolProgram.methods
+          .collectProtocolFees(collectable)
+          .accounts({
+            poolState: poolStatePDA,
+            authority: authority,
+            poolVault: poolVaultPDA,
+            reserveVault: reserveVaultPDA,
+            treasury: treasuryTokenAccount,
+            tokenMint: mint,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+        const treasuryAfter = await token.getAccount(provider.connection, treasuryTokenAccount);
+        expect(Number(treasuryAfter.amount - treasuryBefore.amount)).to.equal(collectable.toNumber());
+      } catch (e) {
+        console.error("Error collecting protocol fees:", e);
+        throw e;
+      }
+    });
+
     it("should withdraw from liquidity pool", async () => {
       try {
         // Check if user deposit exists
@@ -1012,7 +2637,11 @@ describe("RWRD Protocol Workflow", () => {
             .withdraw(withdrawAmount)
             .accounts({
               userDeposit: userDepositPDA,
+              receiptMint: receiptMintPDA,
+              userReceiptAccount: userReceiptAccount,
               user: user,
+              positionMint: null,
+              positionTokenAccount: null,
               poolState: poolStatePDA,
               poolVault: poolVaultPDA,
               userTokenAccount: userTokenAccount,